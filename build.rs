@@ -4,12 +4,20 @@ const VERSION_TEMPLATE: &str = r#"
 pub const VERSION: &str = "{version}";
 pub const HW_VER: &str = "{hw}";
 pub const FIRMWARE: &str = "{firmware}";
+pub const BUILD_TIME: u64 = {build_time};
+pub const OTA_PUBLIC_KEY: Option<[u8; 32]> = {ota_public_key};
 "#;
 
 fn main() {
     println!("cargo:rerun-if-changed=*.env*");
+    println!("cargo:rerun-if-env-changed=OTA_PUBLIC_KEY");
+    let mut ota_public_key = std::env::var("OTA_PUBLIC_KEY").ok();
     if let Ok(mut iter) = dotenvy::dotenv_iter() {
         while let Some(Ok((key, value))) = iter.next() {
+            if key == "OTA_PUBLIC_KEY" {
+                ota_public_key = Some(value.clone());
+            }
+
             println!("cargo:rustc-env={key}={value}");
         }
     }
@@ -18,15 +26,23 @@ fn main() {
     println!("cargo:rustc-link-arg=-Tlinkall.x");
     println!("cargo:rustc-cfg=feature=\"gen_version\"");
 
+    // release.sh passes same BUILD_TIME that is later appended to image metadata
+    println!("cargo:rerun-if-env-changed=BUILD_TIME");
+    let epoch = std::env::var("BUILD_TIME")
+        .ok()
+        .and_then(|t| t.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        });
+
+    let release = std::env::var("RELEASE_BUILD").is_ok();
     let version_str = if let Ok(rel) = std::env::var("RELEASE_BUILD") {
         println!("cargo:rustc-cfg=feature=\"release_build\"");
         rel
     } else {
-        let epoch = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-
         format!("D{epoch}")
     };
 
@@ -39,12 +55,42 @@ fn main() {
     let gen = VERSION_TEMPLATE
         .replace("{version}", &version_str)
         .replace("{hw}", hw)
-        .replace("{firmware}", "STAFF_ATTENDANCE");
+        .replace("{firmware}", "STAFF_ATTENDANCE")
+        .replace("{build_time}", &epoch.to_string())
+        .replace(
+            "{ota_public_key}",
+            &ota_public_key_const(ota_public_key, release),
+        );
 
     let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
     std::fs::write(out_dir.join("version.rs"), gen.trim()).unwrap();
 }
 
+/// OTA_PUBLIC_KEY is hex encoded raw ed25519 public key (32 bytes).
+/// Required for release builds, without it every update is rejected
+fn ota_public_key_const(key: Option<String>, release: bool) -> String {
+    let Some(key) = key.filter(|k| !k.trim().is_empty()) else {
+        if release {
+            panic!("OTA_PUBLIC_KEY must be set for release builds");
+        }
+
+        println!("cargo:warning=OTA_PUBLIC_KEY not set, OTA updates will be rejected!");
+        return "None".to_string();
+    };
+
+    let key = key.trim();
+    if key.len() != 64 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+        panic!("OTA_PUBLIC_KEY must be 64 hex characters (raw ed25519 public key)");
+    }
+
+    let bytes = (0..32)
+        .map(|i| format!("0x{}", &key[i * 2..i * 2 + 2]))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!("Some([{bytes}])")
+}
+
 fn linker_be_nice() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 {
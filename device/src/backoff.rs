@@ -0,0 +1,89 @@
+//! Reconnect backoff
+
+/// Exponential backoff with jitter. Delay doubles with every failed
+/// attempt (capped at max), random part spreads devices reconnecting at once
+pub struct Backoff {
+    attempts: u32,
+}
+
+impl Backoff {
+    pub const fn new() -> Self {
+        Self { attempts: 0 }
+    }
+
+    /// Failed attempts since last reset
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// Returns delay before next attempt (between half and full of
+    /// exponential delay, `random` picks the point)
+    pub fn next_delay(&mut self, base_ms: u64, max_ms: u64, random: u32) -> u64 {
+        let exp = base_ms
+            .saturating_mul(1 << self.attempts.min(16))
+            .min(max_ms);
+        self.attempts = self.attempts.saturating_add(1);
+
+        let half = exp / 2;
+        half + random as u64 % (exp - half + 1)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_doubles_between_half_and_full() {
+        let mut backoff = Backoff::new();
+        for exp in [1000, 2000, 4000, 8000] {
+            let mut low = Backoff {
+                attempts: backoff.attempts,
+            };
+            let mut high = Backoff {
+                attempts: backoff.attempts,
+            };
+
+            assert_eq!(low.next_delay(1000, 60_000, 0), exp / 2);
+            assert_eq!(high.next_delay(1000, 60_000, exp as u32 / 2), exp);
+            backoff.next_delay(1000, 60_000, 0);
+        }
+
+        assert_eq!(backoff.attempts(), 4);
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let mut backoff = Backoff::new();
+        for _ in 0..100 {
+            let delay = backoff.next_delay(1000, 60_000, u32::MAX);
+            assert!(delay <= 60_000);
+        }
+
+        assert!(backoff.next_delay(1000, 60_000, 0) >= 30_000);
+
+        // no overflow with huge base
+        assert!(backoff.next_delay(u64::MAX, u64::MAX, u32::MAX) >= u64::MAX / 2);
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = Backoff::new();
+        backoff.next_delay(1000, 60_000, 0);
+        backoff.next_delay(1000, 60_000, 0);
+        backoff.reset();
+
+        assert_eq!(backoff.attempts(), 0);
+        assert_eq!(backoff.next_delay(1000, 60_000, 0), 500);
+    }
+}
@@ -0,0 +1,54 @@
+//! Battery reporting loop
+
+use crate::battery_curve::{bat_percentage, calculate};
+use crate::{Link, Platform};
+use embassy_time::{Instant, Timer};
+use staff_at_protocol::{TimerPacket, TimerPacketInner};
+
+/// Battery voltage adc (raw reading, before calibration curve)
+#[allow(async_fn_in_trait)]
+pub trait BatteryAdc {
+    async fn read_raw(&mut self) -> u16;
+}
+
+/// Samples adc every 100ms (through `filter`, dynamic smoother on device)
+/// and reports battery every `battery_send_interval_ms` while connected
+pub async fn battery_loop(
+    platform: &impl Platform,
+    adc: &mut impl BatteryAdc,
+    mut filter: impl FnMut(f32) -> f32,
+) -> ! {
+    let mut battery_start = Instant::now();
+    loop {
+        Timer::after_millis(100).await;
+        if platform.sleep_state() {
+            Timer::after_millis(500).await;
+            continue;
+        }
+
+        let read = filter(adc.read_raw().await as f32);
+        if (Instant::now() - battery_start).as_millis() < platform.config().battery_send_interval_ms
+        {
+            continue;
+        }
+
+        battery_start = Instant::now();
+        let bat_calc_mv = calculate(read as f64);
+        let bat_percentage = bat_percentage(bat_calc_mv);
+
+        if platform.server_connected().await {
+            platform
+                .link()
+                .send_packet(TimerPacket {
+                    tag: None,
+                    data: TimerPacketInner::Battery {
+                        level: Some(bat_percentage as f64),
+                        voltage: Some(bat_calc_mv / 1000.0),
+                    },
+                })
+                .await;
+        }
+
+        log::info!("calc({read}): {bat_calc_mv}mV {bat_percentage}%");
+    }
+}
@@ -0,0 +1,49 @@
+//! Battery voltage math
+
+const BAT_MIN: f64 = 3200.0;
+const BAT_MAX: f64 = 4200.0;
+const BATTERY_CURVE: [(f64, u8); 11] = [
+    (3200.0, 0),
+    (3250.0, 5),
+    (3300.0, 10),
+    (3350.0, 20),
+    (3400.0, 30),
+    (3500.0, 40),
+    (3600.0, 50),
+    (3700.0, 60),
+    (3800.0, 70),
+    (3900.0, 80),
+    (4200.0, 100),
+];
+
+fn interpolate(v1: f64, p1: u8, v2: f64, p2: u8, voltage: f64) -> u8 {
+    let percentage = p1 as f64 + (voltage - v1) * (p2 as f64 - p1 as f64) / (v2 - v1);
+    percentage as u8
+}
+
+pub fn bat_percentage(mv: f64) -> u8 {
+    if mv <= BAT_MIN {
+        return 0;
+    }
+    if mv >= BAT_MAX {
+        return 100;
+    }
+
+    // Find the two closest voltage points in our curve
+    for window in BATTERY_CURVE.windows(2) {
+        let (v1, p1) = window[0];
+        let (v2, p2) = window[1];
+
+        if mv >= v1 && mv <= v2 {
+            return interpolate(v1, p1, v2, p2, mv);
+        }
+    }
+
+    // Fallback to linear interpolation if something goes wrong
+    ((mv - BAT_MIN) / (BAT_MAX - BAT_MIN) * 100.0) as u8
+}
+
+/// Converts raw (calibrated) adc reading into battery voltage (mV)
+pub fn calculate(x: f64) -> f64 {
+    1.18323 * x + 276.754
+}
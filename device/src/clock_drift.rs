@@ -0,0 +1,145 @@
+//! Clock drift estimation
+
+/// Shorter intervals are dominated by sync precision (tens of ms with ntp,
+/// 50ms over 30 minutes is still ~28ppm)
+const MIN_DRIFT_INTERVAL_MS: u64 = 30 * 60 * 1000;
+
+/// Weight of new drift sample (exponential average)
+const DRIFT_SMOOTHING: f32 = 0.25;
+
+/// Result of single time sync
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockSync {
+    /// Server time minus local time (None on first sync)
+    pub offset_ms: Option<i64>,
+    pub drift_ppm: Option<i32>,
+}
+
+/// Compares local clock with server time on every sync. Drift is estimated
+/// only between two precise (ms resolution) syncs, whole second server time
+/// would add up to ±1s of noise
+pub struct DriftEstimator {
+    /// Uptime of last sync
+    last_sync_ms: Option<u64>,
+
+    /// Uptime of last precise sync (cleared when clock is set from imprecise one)
+    last_precise_sync_ms: Option<u64>,
+    drift_ppm: Option<f32>,
+}
+
+impl DriftEstimator {
+    pub const fn new() -> Self {
+        Self {
+            last_sync_ms: None,
+            last_precise_sync_ms: None,
+            drift_ppm: None,
+        }
+    }
+
+    pub fn last_sync_ms(&self) -> Option<u64> {
+        self.last_sync_ms
+    }
+
+    /// `precise` is false for time with whole second resolution
+    pub fn sync(
+        &mut self,
+        uptime_ms: u64,
+        local_epoch_ms: u64,
+        server_epoch_ms: u64,
+        precise: bool,
+    ) -> ClockSync {
+        let offset_ms = self
+            .last_sync_ms
+            .map(|_| server_epoch_ms as i64 - local_epoch_ms as i64);
+
+        let last_precise = self.last_precise_sync_ms.filter(|_| precise);
+        if let (Some(offset_ms), Some(last_sync_ms)) = (offset_ms, last_precise) {
+            let elapsed = uptime_ms.saturating_sub(last_sync_ms);
+            if elapsed >= MIN_DRIFT_INTERVAL_MS {
+                let sample = offset_ms as f32 * 1_000_000.0 / elapsed as f32;
+                self.drift_ppm = Some(match self.drift_ppm {
+                    Some(drift) => drift + (sample - drift) * DRIFT_SMOOTHING,
+                    None => sample,
+                });
+            }
+        }
+
+        // local clock is corrected on every sync, offset accumulates from now
+        self.last_sync_ms = Some(uptime_ms);
+        self.last_precise_sync_ms = precise.then_some(uptime_ms);
+        ClockSync {
+            offset_ms,
+            drift_ppm: self.drift_ppm.map(|drift| drift as i32),
+        }
+    }
+}
+
+impl Default for DriftEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MS: u64 = 60 * 60 * 1000;
+    const EPOCH_MS: u64 = 1_700_000_000_000;
+
+    #[test]
+    fn first_sync_has_no_offset() {
+        let mut drift = DriftEstimator::new();
+        let sync = drift.sync(1000, 1000, EPOCH_MS, true);
+
+        assert_eq!(sync.offset_ms, None);
+        assert_eq!(sync.drift_ppm, None);
+        assert_eq!(drift.last_sync_ms(), Some(1000));
+    }
+
+    #[test]
+    fn drift_from_precise_syncs() {
+        let mut drift = DriftEstimator::new();
+        drift.sync(0, 0, EPOCH_MS, true);
+
+        // local clock 36ms behind after an hour = 10ppm slow
+        let sync = drift.sync(HOUR_MS, EPOCH_MS + HOUR_MS, EPOCH_MS + HOUR_MS + 36, true);
+        assert_eq!(sync.offset_ms, Some(36));
+        assert_eq!(sync.drift_ppm, Some(10));
+
+        // new samples are smoothed
+        let now = 2 * HOUR_MS;
+        let sync = drift.sync(now, EPOCH_MS + now + 36, EPOCH_MS + now + 36 + 180, true);
+        assert_eq!(sync.drift_ppm, Some(20));
+    }
+
+    #[test]
+    fn short_interval_is_ignored() {
+        let mut drift = DriftEstimator::new();
+        drift.sync(0, 0, EPOCH_MS, true);
+
+        let sync = drift.sync(60_000, EPOCH_MS + 60_000, EPOCH_MS + 60_050, true);
+        assert_eq!(sync.offset_ms, Some(50));
+        assert_eq!(sync.drift_ppm, None);
+    }
+
+    #[test]
+    fn imprecise_sync_is_not_used_for_drift() {
+        let mut drift = DriftEstimator::new();
+        drift.sync(0, 0, EPOCH_MS, true);
+
+        // whole second server time, offset is reported but drift isn't estimated
+        let sync = drift.sync(HOUR_MS, EPOCH_MS + HOUR_MS, EPOCH_MS + HOUR_MS + 800, false);
+        assert_eq!(sync.offset_ms, Some(800));
+        assert_eq!(sync.drift_ppm, None);
+
+        // clock was stepped by imprecise sync, so it can't be a baseline either
+        let now = 2 * HOUR_MS;
+        let sync = drift.sync(now, EPOCH_MS + now, EPOCH_MS + now + 36, true);
+        assert_eq!(sync.drift_ppm, None);
+
+        let now = 3 * HOUR_MS;
+        let sync = drift.sync(now, EPOCH_MS + now, EPOCH_MS + now + 36, true);
+        assert_eq!(sync.drift_ppm, Some(10));
+    }
+}
@@ -0,0 +1,159 @@
+//! Minimal DHCPINFORM codec, used to get ntp servers (option 42) that
+//! embassy-net dhcp client doesn't request
+
+use alloc::vec::Vec;
+
+pub const SERVER_PORT: u16 = 67;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// Offset of options (after fixed header and magic cookie)
+const OPTIONS_OFFSET: usize = 240;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_NTP_SERVERS: u8 = 42;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_PARAMETER_LIST: u8 = 55;
+const OPTION_END: u8 = 255;
+
+const MESSAGE_ACK: u8 = 5;
+const MESSAGE_INFORM: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DhcpError {
+    Malformed,
+    NotAck,
+
+    /// Response to other client (or other request)
+    XidMismatch,
+}
+
+/// DHCPINFORM asking for ntp servers. Client already has address (`ciaddr`),
+/// so server answers directly (to source port of request)
+pub fn inform(xid: u32, mac: [u8; 6], ciaddr: [u8; 4]) -> Vec<u8> {
+    let mut packet = alloc::vec![0; OPTIONS_OFFSET];
+    packet[0] = OP_REQUEST;
+    packet[1] = HTYPE_ETHERNET;
+    packet[2] = mac.len() as u8;
+    packet[4..8].copy_from_slice(&xid.to_be_bytes());
+    packet[12..16].copy_from_slice(&ciaddr);
+    packet[28..34].copy_from_slice(&mac);
+    packet[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+    packet.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, MESSAGE_INFORM]);
+    packet.extend_from_slice(&[OPTION_PARAMETER_LIST, 1, OPTION_NTP_SERVERS]);
+    packet.push(OPTION_END);
+    packet
+}
+
+/// Ntp servers (ipv4) from DHCPACK to [`inform`] request (empty if server
+/// has none configured)
+pub fn parse_ntp_servers(packet: &[u8], xid: u32) -> Result<Vec<[u8; 4]>, DhcpError> {
+    if packet.len() < OPTIONS_OFFSET || packet[0] != OP_REPLY || packet[236..240] != MAGIC_COOKIE {
+        return Err(DhcpError::Malformed);
+    }
+
+    if packet[4..8] != xid.to_be_bytes() {
+        return Err(DhcpError::XidMismatch);
+    }
+
+    let mut message_type = None;
+    let mut servers = Vec::new();
+    let mut options = &packet[OPTIONS_OFFSET..];
+    while let Some((&kind, rest)) = options.split_first() {
+        match kind {
+            OPTION_END => break,
+            OPTION_PAD => {
+                options = rest;
+                continue;
+            }
+            _ => {}
+        }
+
+        let (&len, rest) = rest.split_first().ok_or(DhcpError::Malformed)?;
+        let data = rest.get(..len as usize).ok_or(DhcpError::Malformed)?;
+        match kind {
+            OPTION_MESSAGE_TYPE => message_type = data.first().copied(),
+            OPTION_NTP_SERVERS => servers.extend(data.chunks_exact(4).map(|ip| {
+                let mut addr = [0; 4];
+                addr.copy_from_slice(ip);
+                addr
+            })),
+            _ => {}
+        }
+
+        options = &rest[len as usize..];
+    }
+
+    if message_type != Some(MESSAGE_ACK) {
+        return Err(DhcpError::NotAck);
+    }
+
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55];
+
+    /// Server side of exchange (reply to `request` with given options)
+    fn ack(request: &[u8], options: &[u8]) -> Vec<u8> {
+        let mut packet = request[..OPTIONS_OFFSET].to_vec();
+        packet[0] = OP_REPLY;
+        packet.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, MESSAGE_ACK, OPTION_PAD]);
+        packet.extend_from_slice(options);
+        packet.push(OPTION_END);
+        packet
+    }
+
+    #[test]
+    fn inform_requests_ntp_servers() {
+        let packet = inform(0xdeadbeef, MAC, [192, 168, 1, 20]);
+
+        assert_eq!(packet[0], OP_REQUEST);
+        assert_eq!(packet[4..8], [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(packet[12..16], [192, 168, 1, 20]);
+        assert_eq!(packet[28..34], MAC);
+        assert_eq!(
+            packet[OPTIONS_OFFSET..],
+            [53, 1, MESSAGE_INFORM, 55, 1, 42, OPTION_END]
+        );
+    }
+
+    #[test]
+    fn ntp_servers_are_parsed() {
+        let request = inform(7, MAC, [10, 0, 0, 5]);
+        let response = ack(
+            &request,
+            &[6, 4, 10, 0, 0, 1, 42, 8, 10, 0, 0, 1, 10, 0, 0, 2],
+        );
+
+        assert_eq!(
+            parse_ntp_servers(&response, 7),
+            Ok(alloc::vec![[10, 0, 0, 1], [10, 0, 0, 2]])
+        );
+        assert_eq!(parse_ntp_servers(&ack(&request, &[]), 7), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn invalid_responses_are_rejected() {
+        let request = inform(7, MAC, [10, 0, 0, 5]);
+        let response = ack(&request, &[42, 4, 10, 0, 0, 1]);
+
+        assert_eq!(parse_ntp_servers(&response, 8), Err(DhcpError::XidMismatch));
+        assert_eq!(parse_ntp_servers(&request, 7), Err(DhcpError::Malformed));
+        assert_eq!(
+            parse_ntp_servers(&response[..response.len() - 3], 7),
+            Err(DhcpError::Malformed)
+        );
+
+        let mut nak = response.clone();
+        nak[OPTIONS_OFFSET + 2] = 6;
+        assert_eq!(parse_ntp_servers(&nak, 7), Err(DhcpError::NotAck));
+    }
+}
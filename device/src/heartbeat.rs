@@ -0,0 +1,103 @@
+//! Ws ping/pong tracking
+
+/// Tracks pings sent every heartbeat interval. Ping payload is sequence
+/// number, so pongs of older (or foreign) pings are ignored
+pub struct Heartbeat {
+    seq: u32,
+
+    /// Sequence and send time of unanswered ping
+    pending: Option<(u32, u64)>,
+    missed: u32,
+}
+
+impl Heartbeat {
+    pub const fn new() -> Self {
+        Self {
+            seq: 0,
+            pending: None,
+            missed: 0,
+        }
+    }
+
+    /// Called every heartbeat interval. Returns payload of next ping, or
+    /// Err(missed) if `max_missed` pings in a row weren't answered
+    pub fn tick(&mut self, now_ms: u64, max_missed: u32) -> Result<[u8; 4], u32> {
+        if self.pending.is_some() {
+            self.missed += 1;
+            if self.missed >= max_missed {
+                return Err(self.missed);
+            }
+        }
+
+        self.seq = self.seq.wrapping_add(1);
+        self.pending = Some((self.seq, now_ms));
+        Ok(self.seq.to_be_bytes())
+    }
+
+    /// Returns round trip time (ms) if pong answers last ping
+    pub fn pong(&mut self, payload: &[u8], now_ms: u64) -> Option<u64> {
+        let (seq, sent_ms) = self.pending?;
+        if payload != seq.to_be_bytes() {
+            return None;
+        }
+
+        self.pending = None;
+        self.missed = 0;
+        Some(now_ms.saturating_sub(sent_ms))
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pong_returns_rtt() {
+        let mut hb = Heartbeat::new();
+        let ping = hb.tick(1000, 3).unwrap();
+
+        assert_eq!(hb.pong(&ping, 1042), Some(42));
+        // already answered
+        assert_eq!(hb.pong(&ping, 1050), None);
+    }
+
+    #[test]
+    fn stale_or_foreign_pongs_are_ignored() {
+        let mut hb = Heartbeat::new();
+        let old = hb.tick(0, 3).unwrap();
+        let new = hb.tick(1000, 3).unwrap();
+
+        assert_eq!(hb.pong(&old, 1100), None);
+        assert_eq!(hb.pong(b"ping", 1100), None);
+        assert_eq!(hb.pong(&new, 1100), Some(100));
+    }
+
+    #[test]
+    fn missed_pings_are_counted() {
+        let mut hb = Heartbeat::new();
+        assert!(hb.tick(0, 3).is_ok());
+        assert!(hb.tick(1000, 3).is_ok());
+        assert!(hb.tick(2000, 3).is_ok());
+        assert_eq!(hb.tick(3000, 3), Err(3));
+    }
+
+    #[test]
+    fn pong_resets_missed() {
+        let mut hb = Heartbeat::new();
+        hb.tick(0, 3).unwrap();
+        hb.tick(1000, 3).unwrap();
+        let ping = hb.tick(2000, 3).unwrap();
+        hb.pong(&ping, 2010);
+
+        assert!(hb.tick(3000, 3).is_ok());
+        assert!(hb.tick(4000, 3).is_ok());
+        assert!(hb.tick(5000, 3).is_ok());
+        assert_eq!(hb.tick(6000, 3), Err(3));
+    }
+}
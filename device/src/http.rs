@@ -0,0 +1,171 @@
+//! Http upgrade response parsing
+
+use alloc::format;
+use alloc::string::String;
+
+/// Value of header from raw http response (name is case insensitive)
+pub fn header<'a>(response: &'a [u8], name: &str) -> Option<&'a str> {
+    // body (first ws frames) doesn't have to be utf8
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .unwrap_or(response.len());
+    let head = core::str::from_utf8(&response[..end]).ok()?;
+
+    head.split("\r\n").skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then_some(value.trim())
+    })
+}
+
+/// Absolute ws url of redirect target. Location can be absolute (http
+/// schemes are mapped to ws ones), scheme relative or a path (relative ones
+/// are resolved against `path` of redirected request). Fragment is dropped.
+/// Returns None if redirect would drop tls (secure connection to plain one)
+pub fn redirect_url(
+    location: &str,
+    secure: bool,
+    host: &str,
+    port: u16,
+    path: &str,
+) -> Option<String> {
+    let scheme = if secure { "wss" } else { "ws" };
+    let location = location.split('#').next().unwrap_or_default();
+
+    let url = if let Some(rest) = location.strip_prefix("https://") {
+        format!("wss://{rest}")
+    } else if let Some(rest) = location.strip_prefix("http://") {
+        format!("ws://{rest}")
+    } else if location.starts_with("ws://") || location.starts_with("wss://") {
+        location.into()
+    } else if let Some(rest) = location.strip_prefix("//") {
+        format!("{scheme}://{rest}")
+    } else if location.starts_with('/') {
+        format!("{scheme}://{host}:{port}{location}")
+    } else {
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let base = if location.starts_with('?') {
+            path
+        } else {
+            // directory of current path
+            &path[..path.rfind('/').map_or(0, |i| i + 1)]
+        };
+
+        let base = base.trim_start_matches('/');
+        format!("{scheme}://{host}:{port}/{base}{location}")
+    };
+
+    if secure && url.starts_with("ws://") {
+        return None;
+    }
+
+    Some(url)
+}
+
+pub fn is_redirect(code: u16) -> bool {
+    matches!(code, 301 | 302 | 307 | 308)
+}
+
+pub fn is_unauthorized(code: u16) -> bool {
+    matches!(code, 401 | 403)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE: &[u8] = b"HTTP/1.1 302 Found\r\nLocation: /ws/v2\r\nX-Empty:\r\n\r\n\x81\xff";
+
+    #[test]
+    fn header_is_case_insensitive() {
+        assert_eq!(header(RESPONSE, "location"), Some("/ws/v2"));
+        assert_eq!(header(RESPONSE, "LOCATION"), Some("/ws/v2"));
+        assert_eq!(header(RESPONSE, "x-empty"), Some(""));
+    }
+
+    #[test]
+    fn header_ignores_status_line_and_body() {
+        assert_eq!(header(RESPONSE, "HTTP/1.1 302 Found"), None);
+        assert_eq!(
+            header(b"HTTP/1.1 101\r\n\r\nLocation: /body", "location"),
+            None
+        );
+        assert_eq!(header(RESPONSE, "missing"), None);
+    }
+
+    #[test]
+    fn relative_redirect_keeps_server() {
+        assert_eq!(
+            redirect_url("/ws/v2", true, "example.com", 443, "/ws/v1").as_deref(),
+            Some("wss://example.com:443/ws/v2")
+        );
+        assert_eq!(
+            redirect_url("//other.com/ws", false, "example.com", 80, "/ws/v1").as_deref(),
+            Some("ws://other.com/ws")
+        );
+    }
+
+    #[test]
+    fn relative_path_is_resolved_against_request_path() {
+        assert_eq!(
+            redirect_url("v2", false, "example.com", 80, "/ws/v1").as_deref(),
+            Some("ws://example.com:80/ws/v2")
+        );
+        assert_eq!(
+            redirect_url("v2?token=abc", false, "example.com", 80, "/ws/v1?id=1").as_deref(),
+            Some("ws://example.com:80/ws/v2?token=abc")
+        );
+        assert_eq!(
+            redirect_url("v2", false, "example.com", 80, "/").as_deref(),
+            Some("ws://example.com:80/v2")
+        );
+        assert_eq!(
+            redirect_url("?id=2", false, "example.com", 80, "/ws/v1?id=1").as_deref(),
+            Some("ws://example.com:80/ws/v1?id=2")
+        );
+    }
+
+    #[test]
+    fn query_is_kept_and_fragment_dropped() {
+        assert_eq!(
+            redirect_url("/ws/v2?id=1&ver=2#top", true, "example.com", 443, "/").as_deref(),
+            Some("wss://example.com:443/ws/v2?id=1&ver=2")
+        );
+        assert_eq!(
+            redirect_url("https://other.com/ws?a=b#c", true, "example.com", 443, "/").as_deref(),
+            Some("wss://other.com/ws?a=b")
+        );
+    }
+
+    #[test]
+    fn http_schemes_are_mapped_to_ws() {
+        assert_eq!(
+            redirect_url("https://other.com/ws", true, "example.com", 443, "/ws/v1").as_deref(),
+            Some("wss://other.com/ws")
+        );
+        assert_eq!(
+            redirect_url("http://other.com/ws", false, "example.com", 80, "/ws/v1").as_deref(),
+            Some("ws://other.com/ws")
+        );
+    }
+
+    #[test]
+    fn redirect_from_secure_to_plain_is_refused() {
+        assert_eq!(
+            redirect_url("http://other.com/ws", true, "example.com", 443, "/ws/v1"),
+            None
+        );
+        assert_eq!(
+            redirect_url("ws://other.com/ws", true, "example.com", 443, "/ws/v1"),
+            None
+        );
+
+        // upgrade to tls is fine
+        assert_eq!(
+            redirect_url("wss://other.com/ws", false, "example.com", 80, "/ws/v1").as_deref(),
+            Some("wss://other.com/ws")
+        );
+    }
+}
@@ -0,0 +1,27 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Persistent storage of json values (nvs on device, in-memory map in
+/// simulator and tests)
+#[allow(async_fn_in_trait)]
+pub trait KvStore {
+    async fn load<T: DeserializeOwned>(&self, key: &[u8]) -> Option<T>;
+
+    /// Overwrites value under given key (returns false if write failed)
+    async fn store<T: Serialize>(&self, key: &[u8], value: &T) -> bool;
+
+    async fn remove(&self, key: &[u8]);
+}
+
+impl<T: KvStore + ?Sized> KvStore for &T {
+    async fn load<V: DeserializeOwned>(&self, key: &[u8]) -> Option<V> {
+        (**self).load(key).await
+    }
+
+    async fn store<V: Serialize>(&self, key: &[u8], value: &V) -> bool {
+        (**self).store(key, value).await
+    }
+
+    async fn remove(&self, key: &[u8]) {
+        (**self).remove(key).await
+    }
+}
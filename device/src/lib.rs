@@ -0,0 +1,33 @@
+//! Hardware independent device logic (offline queue, scan delivery, time
+//! keeping, server session and device task loops) shared by firmware and
+//! host simulator. Storage, server connection and clock are accessed through
+//! [`KvStore`], [`Link`] and [`Clock`], rest of device state through [`Platform`]
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod backoff;
+pub mod battery;
+pub mod battery_curve;
+pub mod clock_drift;
+pub mod dhcp;
+pub mod heartbeat;
+pub mod http;
+pub mod logs;
+pub mod outbound;
+pub mod queue;
+pub mod scan;
+pub mod session;
+pub mod settings;
+pub mod sntp;
+pub mod time;
+
+mod kv;
+mod link;
+mod platform;
+
+pub use kv::KvStore;
+pub use link::{deliver_with_retries, Link};
+pub use platform::Platform;
+pub use time::Clock;
@@ -0,0 +1,71 @@
+use core::future::Future;
+use staff_at_protocol::{ApiError, FromPacket, TimerPacket, TimerPacketInner};
+
+/// Connection to server (ws on device and simulator, scripted in tests)
+#[allow(async_fn_in_trait)]
+pub trait Link {
+    async fn send_packet(&self, packet: TimerPacket);
+
+    /// Tagged request sent up to `tries` times (only timeouts are retried)
+    async fn send_request<T: FromPacket>(
+        &self,
+        packet: TimerPacketInner,
+        tries: usize,
+    ) -> Result<T, ApiError>;
+
+    /// Server rejected device time, it isn't trusted until next sync
+    async fn reset_time(&self);
+}
+
+/// Calls `send` up to `tries` times (only timeouts are retried, server error
+/// won't change by sending the same request again)
+pub async fn deliver_with_retries<T, F, Fut>(tries: usize, mut send: F) -> Result<T, ApiError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, ApiError>>,
+{
+    let mut res = Err(ApiError::timeout());
+    for attempt in 1..=tries {
+        res = send().await;
+        match &res {
+            Err(e) if e.is_timeout() => {
+                log::warn!("[WS] Request timed out (try {attempt}/{tries})");
+            }
+            _ => break,
+        }
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+
+    #[test]
+    fn delivery_retries_only_timeouts() {
+        let mut responses =
+            [Err(ApiError::timeout()), Err(ApiError::timeout()), Ok(())].into_iter();
+        let mut calls = 0;
+        let res = block_on(deliver_with_retries(3, || {
+            calls += 1;
+            core::future::ready(responses.next().unwrap())
+        }));
+        assert!(res.is_ok());
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let res = block_on(deliver_with_retries(3, || {
+            calls += 1;
+            core::future::ready(Err::<(), _>(ApiError::new("rejected", false)))
+        }));
+        assert!(res.is_err_and(|e| !e.is_timeout()));
+        assert_eq!(calls, 1);
+
+        let res = block_on(deliver_with_retries(3, || {
+            core::future::ready(Err::<(), _>(ApiError::timeout()))
+        }));
+        assert!(res.is_err_and(|e| e.is_timeout()));
+    }
+}
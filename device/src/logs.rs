@@ -0,0 +1,46 @@
+//! Log lines collected for server (filled by platform logger)
+
+use crate::{Link, Platform};
+use alloc::string::String;
+use alloc::vec::Vec;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::Timer;
+use staff_at_protocol::{TimerPacket, TimerPacketInner};
+
+const MAX_LOGS_SIZE: usize = 100;
+static LOGS_CHANNEL: Channel<CriticalSectionRawMutex, String, MAX_LOGS_SIZE> = Channel::new();
+
+/// Keeps line for next batch (oldest line is dropped if buffer is full)
+pub fn push(line: String) {
+    if LOGS_CHANNEL.is_full() {
+        _ = LOGS_CHANNEL.try_receive();
+    }
+
+    _ = LOGS_CHANNEL.try_send(line);
+}
+
+/// Sends collected lines every `log_send_interval_ms` (dropped while
+/// sleeping or updating)
+pub async fn send_loop(platform: &impl Platform) -> ! {
+    loop {
+        Timer::after_millis(platform.config().log_send_interval_ms).await;
+
+        let mut logs = Vec::new();
+        while let Ok(line) = LOGS_CHANNEL.try_receive() {
+            logs.push(line);
+        }
+
+        if platform.ota_state() || platform.sleep_state() || logs.is_empty() {
+            continue;
+        }
+
+        logs.reverse();
+        platform
+            .link()
+            .send_packet(TimerPacket {
+                tag: None,
+                data: TimerPacketInner::Logs { logs },
+            })
+            .await;
+    }
+}
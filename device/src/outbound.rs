@@ -0,0 +1,249 @@
+//! Prioritised outbound frame queue
+
+use embassy_futures::select::{select4, Either4};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use staff_at_protocol::TimerPacketInner;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameClass {
+    /// Protocol requests and replies (ws control frames and ota acks are
+    /// written directly by connection task)
+    Control,
+    Attendance,
+    Telemetry,
+    Logs,
+}
+
+impl FrameClass {
+    /// Every packet is listed, so new ones have to be classified explicitly
+    pub fn of_packet(data: &TimerPacketInner) -> Self {
+        match data {
+            // queue status is sent before replayed scans
+            TimerPacketInner::CardInfoRequest { .. } | TimerPacketInner::OfflineQueue { .. } => {
+                FrameClass::Attendance
+            }
+            TimerPacketInner::Logs { .. } => FrameClass::Logs,
+            TimerPacketInner::DeviceStatus(_)
+            | TimerPacketInner::Battery { .. }
+            | TimerPacketInner::ClockSync { .. }
+            | TimerPacketInner::Telemetry(_) => FrameClass::Telemetry,
+
+            // resets are marked reported only after ack, request can't be dropped
+            TimerPacketInner::ResetReport { .. }
+            | TimerPacketInner::EpochTimeRequest
+            | TimerPacketInner::Add { .. }
+            | TimerPacketInner::DeviceConfig(_)
+            | TimerPacketInner::UpdateResume { .. }
+            | TimerPacketInner::UpdateFailed(_) => FrameClass::Control,
+
+            // sent only by server
+            TimerPacketInner::StartUpdate { .. }
+            | TimerPacketInner::ApiError(_)
+            | TimerPacketInner::AttendanceMarked
+            | TimerPacketInner::DeviceSettings { .. }
+            | TimerPacketInner::EpochTime { .. }
+            | TimerPacketInner::UpdateResumeAck { .. }
+            | TimerPacketInner::ResetReportAck
+            | TimerPacketInner::AuthChallenge { .. } => FrameClass::Control,
+        }
+    }
+}
+
+/// Queue per frame class (of [`crate::session::Frame`]), sender always takes
+/// frame of highest class first. Control and attendance frames wait for free
+/// space, telemetry and logs drop their oldest frame instead (newer batch is
+/// more useful)
+pub struct OutboundQueue<F> {
+    control: Channel<CriticalSectionRawMutex, F, 8>,
+    attendance: Channel<CriticalSectionRawMutex, F, 8>,
+    telemetry: Channel<CriticalSectionRawMutex, F, 4>,
+    logs: Channel<CriticalSectionRawMutex, F, 2>,
+}
+
+impl<F> OutboundQueue<F> {
+    pub const fn new() -> Self {
+        Self {
+            control: Channel::new(),
+            attendance: Channel::new(),
+            telemetry: Channel::new(),
+            logs: Channel::new(),
+        }
+    }
+
+    pub async fn send(&self, class: FrameClass, frame: F) {
+        match class {
+            FrameClass::Control => self.control.send(frame).await,
+            FrameClass::Attendance => self.attendance.send(frame).await,
+            FrameClass::Telemetry => {
+                if push_drop_oldest(&self.telemetry, frame) {
+                    log::warn!("[WS] Telemetry queue full, oldest frame dropped");
+                }
+            }
+
+            // not logged, dropped line would end up in next logs batch
+            FrameClass::Logs => _ = push_drop_oldest(&self.logs, frame),
+        }
+    }
+
+    pub async fn receive(&self) -> F {
+        // select polls futures in order, so ready frame of higher class wins
+        match select4(
+            self.control.receive(),
+            self.attendance.receive(),
+            self.telemetry.receive(),
+            self.logs.receive(),
+        )
+        .await
+        {
+            Either4::First(frame)
+            | Either4::Second(frame)
+            | Either4::Third(frame)
+            | Either4::Fourth(frame) => frame,
+        }
+    }
+
+    pub fn clear(&self) {
+        self.control.clear();
+        self.attendance.clear();
+        self.telemetry.clear();
+        self.logs.clear();
+    }
+
+    pub fn clear_class(&self, class: FrameClass) {
+        match class {
+            FrameClass::Control => self.control.clear(),
+            FrameClass::Attendance => self.attendance.clear(),
+            FrameClass::Telemetry => self.telemetry.clear(),
+            FrameClass::Logs => self.logs.clear(),
+        }
+    }
+}
+
+impl<F> Default for OutboundQueue<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns true if oldest frame had to be dropped
+fn push_drop_oldest<F, const N: usize>(
+    channel: &Channel<CriticalSectionRawMutex, F, N>,
+    frame: F,
+) -> bool {
+    let Err(embassy_sync::channel::TrySendError::Full(frame)) = channel.try_send(frame) else {
+        return false;
+    };
+
+    _ = channel.try_receive();
+    _ = channel.try_send(frame);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+
+    #[test]
+    fn higher_class_is_sent_first() {
+        let queue = OutboundQueue::new();
+        block_on(async {
+            queue.send(FrameClass::Logs, "logs").await;
+            queue.send(FrameClass::Telemetry, "telemetry").await;
+            queue.send(FrameClass::Attendance, "attendance").await;
+            queue.send(FrameClass::Control, "control").await;
+
+            assert_eq!(queue.receive().await, "control");
+            assert_eq!(queue.receive().await, "attendance");
+            assert_eq!(queue.receive().await, "telemetry");
+            assert_eq!(queue.receive().await, "logs");
+        });
+    }
+
+    #[test]
+    fn frames_of_one_class_keep_order() {
+        let queue = OutboundQueue::new();
+        block_on(async {
+            for scan in 0..8 {
+                queue.send(FrameClass::Attendance, scan).await;
+            }
+
+            for scan in 0..8 {
+                assert_eq!(queue.receive().await, scan);
+            }
+        });
+    }
+
+    #[test]
+    fn full_telemetry_and_logs_drop_oldest() {
+        let queue = OutboundQueue::new();
+        block_on(async {
+            for batch in 0..6 {
+                queue.send(FrameClass::Telemetry, batch).await;
+            }
+            for batch in 10..13 {
+                queue.send(FrameClass::Logs, batch).await;
+            }
+
+            for batch in [2, 3, 4, 5, 11, 12] {
+                assert_eq!(queue.receive().await, batch);
+            }
+        });
+    }
+
+    #[test]
+    fn clear_drops_everything() {
+        let queue = OutboundQueue::new();
+        block_on(async {
+            queue.send(FrameClass::Control, 1).await;
+            queue.send(FrameClass::Logs, 2).await;
+            queue.clear();
+            queue.send(FrameClass::Telemetry, 3).await;
+
+            assert_eq!(queue.receive().await, 3);
+        });
+    }
+
+    #[test]
+    fn clear_class_keeps_other_classes() {
+        let queue = OutboundQueue::new();
+        block_on(async {
+            queue.send(FrameClass::Control, 1).await;
+            queue.send(FrameClass::Attendance, 2).await;
+            queue.send(FrameClass::Telemetry, 3).await;
+            queue.clear_class(FrameClass::Control);
+            queue.clear_class(FrameClass::Telemetry);
+
+            assert_eq!(queue.receive().await, 2);
+            assert!(queue.control.is_empty() && queue.telemetry.is_empty());
+        });
+    }
+
+    #[test]
+    fn device_packets_are_classified() {
+        let scan = TimerPacketInner::CardInfoRequest {
+            card_id: 1,
+            card_uid: None,
+            attendance_device: Some(true),
+            scan_epoch: None,
+            time_valid: None,
+            scan_id: None,
+        };
+        assert_eq!(FrameClass::of_packet(&scan), FrameClass::Attendance);
+
+        let logs = TimerPacketInner::Logs {
+            logs: Default::default(),
+        };
+        assert_eq!(FrameClass::of_packet(&logs), FrameClass::Logs);
+
+        let battery = TimerPacketInner::Battery {
+            level: None,
+            voltage: None,
+        };
+        assert_eq!(FrameClass::of_packet(&battery), FrameClass::Telemetry);
+        assert_eq!(
+            FrameClass::of_packet(&TimerPacketInner::EpochTimeRequest),
+            FrameClass::Control
+        );
+    }
+}
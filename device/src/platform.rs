@@ -0,0 +1,77 @@
+use crate::clock_drift::DriftEstimator;
+use crate::queue::OfflineQueue;
+use crate::scan::ScanCounter;
+use crate::{Clock, KvStore, Link};
+use embassy_sync::mutex::Mutex;
+use staff_at_protocol::{DeviceConfig, DeviceStatus, ResetReason};
+
+/// Device state and side effects used by shared tasks (`GlobalState` on
+/// device, `SimState` in simulator). Hooks are for feedback that only real
+/// hardware has (led, health checks, telemetry)
+#[allow(async_fn_in_trait)]
+pub trait Platform {
+    type RawMutex: embassy_sync::blocking_mutex::raw::RawMutex;
+
+    fn clock(&self) -> impl Clock + '_;
+    fn kv(&self) -> impl KvStore + '_;
+    fn link(&self) -> impl Link + '_;
+
+    fn device_id(&self) -> u32;
+
+    /// Firmware name sent when device asks to be added
+    fn firmware(&self) -> &str;
+
+    /// Random number (backoff jitter)
+    fn random_u32(&self) -> u32;
+
+    fn config(&self) -> DeviceConfig;
+
+    /// Validates, persists and applies config pushed by server.
+    /// Returns effective config (old one if validation failed)
+    async fn update_config(&self, config: DeviceConfig) -> DeviceConfig;
+
+    fn offline_queue(&self) -> &Mutex<Self::RawMutex, OfflineQueue>;
+    fn scan_counter(&self) -> &Mutex<Self::RawMutex, ScanCounter>;
+    fn device_status(&self) -> &Mutex<Self::RawMutex, DeviceStatus>;
+    fn clock_drift(&self) -> &Mutex<Self::RawMutex, DriftEstimator>;
+
+    /// True if clock was synced (and server didn't reject it since)
+    fn time_valid(&self) -> bool;
+
+    fn sleep_state(&self) -> bool;
+    fn set_sleep_state(&self, sleep: bool);
+    fn enter_deeper_sleep(&self);
+
+    /// Update is being written (logs aren't sent)
+    fn ota_state(&self) -> bool {
+        false
+    }
+
+    async fn server_connected(&self) -> bool;
+    async fn set_server_connected(&self, connected: bool);
+
+    /// Server told device whether it's added
+    async fn set_device_added(&self, added: bool);
+
+    /// Device restarts, everything not in kv store is lost
+    fn restart(&self, reason: ResetReason) -> !;
+
+    /// Reader init finished (or failed and will be retried)
+    async fn rfid_init(&self, _ok: bool) {}
+
+    /// Card was read (before it's delivered or queued)
+    async fn card_read(&self) {}
+
+    /// Card was read again within debounce window
+    async fn scan_suppressed(&self) {}
+
+    /// Delivery finished (whether server responded or not)
+    async fn scan_sent(&self) {}
+
+    /// Status is sent at start of session (with events since last one)
+    async fn status_reported(&self, _status: &DeviceStatus) {}
+
+    /// Runs next to every session (requests waiting for server response),
+    /// cancelled when session ends
+    async fn session_task(&self) {}
+}
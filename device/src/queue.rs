@@ -0,0 +1,237 @@
+use crate::{KvStore, Link};
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+use serde::{Deserialize, Serialize};
+use staff_at_protocol::{
+    ApiErrorKind, AttendanceMarkedPacket, QueueOverflowPolicy, TimerPacket, TimerPacketInner,
+};
+
+const QUEUE_META_KEY: &[u8] = b"ATT_QUEUE_META";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueuedScan {
+    pub card_id: u64,
+
+    /// Full uid as hex (missing in entries queued by older firmware)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card_uid: Option<String>,
+
+    pub scan_epoch: u64,
+
+    /// Missing in entries queued by older firmware
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_valid: Option<bool>,
+
+    /// Missing in entries queued by older firmware
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan_id: Option<String>,
+}
+
+impl QueuedScan {
+    /// Attendance request of this scan (same for live and replayed scans)
+    pub fn request(&self) -> TimerPacketInner {
+        TimerPacketInner::CardInfoRequest {
+            card_id: self.card_id,
+            card_uid: self.card_uid.clone(),
+            attendance_device: Some(true),
+            scan_epoch: Some(self.scan_epoch),
+            time_valid: self.time_valid,
+            scan_id: self.scan_id.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct QueueMeta {
+    head: u32,
+    tail: u32,
+    dropped: u32,
+}
+
+/// Fifo of scans that couldn't be delivered to the server.
+/// Every entry is stored under its own key (ring buffer indexed by head/tail)
+pub struct OfflineQueue {
+    entries: VecDeque<QueuedScan>,
+    meta: QueueMeta,
+    capacity: usize,
+    drop_oldest: bool,
+}
+
+impl OfflineQueue {
+    pub fn new(capacity: usize, drop_oldest: bool) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            meta: QueueMeta::default(),
+            capacity,
+            drop_oldest,
+        }
+    }
+
+    pub async fn load(&mut self, kv: &impl KvStore) {
+        let Some(meta) = kv.load::<QueueMeta>(QUEUE_META_KEY).await else {
+            return;
+        };
+
+        self.entries.clear();
+        for idx in meta.head..meta.tail {
+            match kv.load::<QueuedScan>(&self.entry_key(idx)).await {
+                Some(scan) => self.entries.push_back(scan),
+                None => log::error!("[QUEUE] Missing entry {idx}!"),
+            }
+        }
+
+        self.meta = meta;
+        log::info!("[QUEUE] Loaded {} pending scans", self.entries.len());
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn dropped(&self) -> u32 {
+        self.meta.dropped
+    }
+
+    pub fn front(&self) -> Option<&QueuedScan> {
+        self.entries.front()
+    }
+
+    /// State reported to server before replay
+    pub fn status(&self) -> TimerPacketInner {
+        TimerPacketInner::OfflineQueue {
+            pending: self.entries.len() as u32,
+            capacity: self.capacity as u32,
+            dropped: self.meta.dropped,
+            overflow_policy: if self.drop_oldest {
+                QueueOverflowPolicy::DropOldest
+            } else {
+                QueueOverflowPolicy::DropNewest
+            },
+        }
+    }
+
+    pub async fn push(&mut self, kv: &impl KvStore, scan: QueuedScan) {
+        if self.entries.len() >= self.capacity {
+            self.meta.dropped = self.meta.dropped.saturating_add(1);
+            if !self.drop_oldest {
+                log::warn!("[QUEUE] Full! Dropping scan of card {}", scan.card_id);
+                self.save_meta(kv).await;
+                return;
+            }
+
+            if let Some(old) = self.entries.pop_front() {
+                log::warn!("[QUEUE] Full! Dropping oldest scan of card {}", old.card_id);
+            }
+            kv.remove(&self.entry_key(self.meta.head)).await;
+            self.meta.head += 1;
+        }
+
+        if !kv.store(&self.entry_key(self.meta.tail), &scan).await {
+            log::error!("[QUEUE] Failed to persist scan of card {}", scan.card_id);
+        }
+
+        self.entries.push_back(scan);
+        self.meta.tail += 1;
+        self.save_meta(kv).await;
+    }
+
+    pub async fn pop(&mut self, kv: &impl KvStore) {
+        if self.entries.pop_front().is_none() {
+            return;
+        }
+
+        kv.remove(&self.entry_key(self.meta.head)).await;
+        self.meta.head += 1;
+        if self.entries.is_empty() {
+            self.meta.head = 0;
+            self.meta.tail = 0;
+        }
+
+        self.save_meta(kv).await;
+    }
+
+    pub async fn reset_dropped(&mut self, kv: &impl KvStore) {
+        if self.meta.dropped == 0 {
+            return;
+        }
+
+        self.meta.dropped = 0;
+        self.save_meta(kv).await;
+    }
+
+    async fn save_meta(&self, kv: &impl KvStore) {
+        if !kv.store(QUEUE_META_KEY, &self.meta).await {
+            log::error!("[QUEUE] Failed to persist queue meta!");
+        }
+    }
+
+    fn entry_key(&self, idx: u32) -> Vec<u8> {
+        alloc::format!("ATT_QUEUE_{}", idx % self.capacity as u32).into_bytes()
+    }
+}
+
+/// Reports queue state and replays queued scans (oldest first) until queue
+/// is empty or some scan isn't accepted. Only scans rejected by server
+/// are dropped, all others are kept for next replay
+pub async fn replay<M: RawMutex>(
+    queue: &Mutex<M, OfflineQueue>,
+    kv: &impl KvStore,
+    link: &impl Link,
+) {
+    {
+        let mut queue = queue.lock().await;
+        if queue.is_empty() && queue.dropped() == 0 {
+            return;
+        }
+
+        link.send_packet(TimerPacket {
+            tag: None,
+            data: queue.status(),
+        })
+        .await;
+        queue.reset_dropped(kv).await;
+    }
+
+    loop {
+        let Some(scan) = queue.lock().await.front().cloned() else {
+            break;
+        };
+
+        log::info!("[QUEUE] Replaying scan of card {}", scan.card_id);
+        match link
+            .send_request::<AttendanceMarkedPacket>(scan.request(), 1)
+            .await
+        {
+            Ok(resp) => {
+                log::info!("[QUEUE] Attendance card response: {resp:?}");
+            }
+            Err(e) if e.kind == ApiErrorKind::Server && !e.should_reset_time => {
+                // server processed (and rejected) this scan, keeping it would block the queue
+                log::error!("[QUEUE] Scan rejected, dropping it: {:?}", e.error);
+            }
+            Err(e) => {
+                // scan may not be processed (or was rejected because of wrong time),
+                // it stays in queue and is replayed again later
+                log::error!(
+                    "[QUEUE] Replay failed ({:?}, {}): {:?}. Retrying later..",
+                    e.kind,
+                    e.should_reset_time,
+                    e.error
+                );
+
+                if e.should_reset_time {
+                    link.reset_time().await;
+                }
+                break;
+            }
+        }
+
+        queue.lock().await.pop(kv).await;
+    }
+}
@@ -0,0 +1,189 @@
+use crate::queue::{OfflineQueue, QueuedScan};
+use crate::{Clock, KvStore, Link, Platform};
+use alloc::string::String;
+use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+use embassy_time::{Instant, Timer};
+use staff_at_protocol::{AttendanceMarkedPacket, ResetReason};
+use staff_at_scanner::{init_reader, CardReader, ScanAction, Scanner, ScannerConfig};
+
+const SCAN_COUNTER_KEY: &[u8] = b"SCAN_COUNTER";
+pub const SEND_TRIES: usize = 3;
+
+/// Monotonic scan counter. Only end of reserved block is stored,
+/// so ids reserved before restart are skipped (but never reused)
+pub struct ScanCounter {
+    next: u64,
+    reserved: u64,
+
+    /// Number of ids reserved by single write
+    reserve: u64,
+}
+
+impl ScanCounter {
+    pub fn new(reserve: u64) -> Self {
+        Self {
+            next: 0,
+            reserved: 0,
+            reserve,
+        }
+    }
+
+    pub async fn load(&mut self, kv: &impl KvStore) {
+        let reserved = kv.load::<u64>(SCAN_COUNTER_KEY).await.unwrap_or_default();
+
+        self.next = reserved;
+        self.reserved = reserved;
+    }
+
+    /// Returns id of new scan (see [`staff_at_protocol::scan_id`])
+    pub async fn next_id(&mut self, kv: &impl KvStore, device_id: u32, epoch: u64) -> String {
+        if self.next >= self.reserved {
+            let reserved = self.next + self.reserve;
+            if kv.store(SCAN_COUNTER_KEY, &reserved).await {
+                self.reserved = reserved;
+            } else {
+                log::error!("[SCAN] Failed to persist scan counter!");
+            }
+        }
+
+        let counter = self.next;
+        self.next += 1;
+
+        staff_at_protocol::scan_id(device_id, counter, epoch)
+    }
+}
+
+/// Returns false if server didn't respond at all (scan should be queued)
+pub async fn deliver(link: &impl Link, scan: &QueuedScan, tries: usize) -> bool {
+    let resp = link
+        .send_request::<AttendanceMarkedPacket>(scan.request(), tries)
+        .await;
+
+    match resp {
+        Ok(resp) => {
+            log::info!("Attendance card response: {resp:?}");
+            true
+        }
+        Err(e) => {
+            log::error!(
+                "[RFID] Resp_error: ({}): {:?}",
+                e.should_reset_time,
+                e.error
+            );
+
+            if e.should_reset_time {
+                link.reset_time().await;
+            }
+
+            !e.is_timeout()
+        }
+    }
+}
+
+pub async fn queue<M: RawMutex>(
+    queue: &Mutex<M, OfflineQueue>,
+    kv: &impl KvStore,
+    scan: QueuedScan,
+) {
+    log::warn!("[RFID] Server unreachable, queueing card {}", scan.card_id);
+    queue.lock().await.push(kv, scan).await;
+}
+
+/// Reader loop (initializes reader, then polls it). Scans are delivered
+/// right away when possible, otherwise queued (after already queued ones)
+pub async fn scan_loop(platform: &impl Platform, reader: &mut impl CardReader) -> ! {
+    init_reader(reader, |_| async {
+        platform.rfid_init(false).await;
+        Timer::after_millis(platform.config().rfid_retry_init_ms).await;
+    })
+    .await;
+    platform.rfid_init(true).await;
+
+    let mut scanner = Scanner::new(Instant::now().as_millis());
+    loop {
+        Timer::after_millis(10).await;
+        let config = platform.config();
+        let scanner_config = ScannerConfig {
+            sleep_after_ms: config.sleep_after_ms,
+            deeper_sleep_after_ms: config.deeper_sleep_after_ms,
+            debounce_ms: config.scan_debounce_ms,
+        };
+
+        // keep scans in order if there is something still waiting in queue
+        let can_deliver =
+            platform.server_connected().await && platform.offline_queue().lock().await.is_empty();
+
+        let action = scanner
+            .poll(
+                reader,
+                &scanner_config,
+                Instant::now().as_millis(),
+                can_deliver,
+            )
+            .await;
+
+        let card_uid = match action {
+            ScanAction::Idle => continue,
+            ScanAction::EnterSleep => {
+                log::info!("Going into sleep!");
+                platform.set_sleep_state(true);
+                continue;
+            }
+            ScanAction::EnterDeeperSleep => {
+                log::info!("Going into depper sleep!");
+                platform.enter_deeper_sleep();
+                continue;
+            }
+            ScanAction::Suppressed(card_uid) => {
+                log::warn!("[RFID] Duplicate scan of {} suppressed", card_uid.to_hex());
+                platform.device_status().lock().await.suppressed_scans += 1;
+                platform.scan_suppressed().await;
+                continue;
+            }
+            ScanAction::Deliver(card_uid)
+            | ScanAction::Queue(card_uid)
+            | ScanAction::QueueAndRestart(card_uid) => card_uid,
+        };
+
+        log::info!("Card UID: {} ({})", card_uid.to_hex(), card_uid.card_id);
+        platform.card_read().await;
+        if platform.sleep_state() {
+            platform.set_sleep_state(false);
+        }
+
+        let scan_epoch = platform.clock().epoch_ms() / 1000;
+        let scan_id = platform
+            .scan_counter()
+            .lock()
+            .await
+            .next_id(&platform.kv(), platform.device_id(), scan_epoch)
+            .await;
+
+        let scan = QueuedScan {
+            card_id: card_uid.card_id,
+            card_uid: Some(card_uid.to_hex()),
+            scan_epoch,
+            time_valid: Some(platform.time_valid()),
+            scan_id: Some(scan_id),
+        };
+
+        match action {
+            ScanAction::QueueAndRestart(_) => {
+                log::info!("Deeper sleep done!");
+
+                // scan will be replayed from offline queue after restart
+                queue(platform.offline_queue(), &platform.kv(), scan).await;
+                Timer::after_millis(100).await;
+                platform.restart(ResetReason::DeeperSleepWake);
+            }
+            ScanAction::Queue(_) => queue(platform.offline_queue(), &platform.kv(), scan).await,
+            _ => {
+                let delivered = deliver(&platform.link(), &scan, SEND_TRIES).await;
+                platform.scan_sent().await;
+                if !delivered {
+                    queue(platform.offline_queue(), &platform.kv(), scan).await;
+                }
+            }
+        }
+    }
+}
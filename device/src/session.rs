@@ -0,0 +1,478 @@
+//! Server connection loop shared by firmware and simulator. Dns, tcp, tls
+//! and http upgrade are done by platform [`Connector`], packets that need
+//! platform (updates, authentication) are passed to [`SessionHandler`]
+
+use crate::backoff::Backoff;
+use crate::heartbeat::Heartbeat;
+use crate::outbound::{FrameClass, OutboundQueue};
+use crate::time::set_platform_time;
+use crate::Platform;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, RawMutex};
+use embassy_sync::pubsub::PubSubChannel;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer, WithTimeout};
+use staff_at_protocol::{
+    ApiError, FromPacket, TimeSource, TimerPacket, TimerPacketInner, WsCloseInfo, WsCloseKind,
+};
+
+/// Tagged request without response after this time is timed out (and retried)
+pub const REQUEST_TIMEOUT_MS: u64 = 5000;
+
+/// Ws frame (framing itself is done by [`WsConnection`])
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+    Close(u16, String),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+}
+
+/// Upgraded ws connection (tls or plain tcp)
+#[allow(async_fn_in_trait)]
+pub trait WsConnection {
+    /// Next frame from server, Err if connection was lost. Has to be cancel
+    /// safe (it's raced with outbound frames and timers)
+    async fn read_frame(&mut self) -> Result<Frame, ()>;
+
+    async fn write_frame(&mut self, frame: Frame) -> Result<(), ()>;
+
+    /// Frames sent by connection task itself (replies, acks) are written
+    /// directly, waiting for space in outbound queue (drained only by that
+    /// task) would deadlock
+    async fn write_packet(&mut self, packet: &TimerPacket) -> Result<(), ()> {
+        match serde_json::to_string(packet) {
+            Ok(string) => self.write_frame(Frame::Text(string)).await,
+            Err(e) => {
+                log::error!("write_packet json to_string failed: {e:?}");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Why connection wasn't established (or session ended early)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectError {
+    Failed,
+
+    /// Upgrade redirected (3xx) to other url (kept by connector)
+    Redirect,
+
+    /// Upgrade refused with 401/403
+    Unauthorized,
+
+    /// Upgrade failed with 5xx
+    ServerError,
+
+    /// Server closed connection with auth challenge (answered by next upgrade)
+    AuthRequired,
+}
+
+impl From<()> for ConnectError {
+    fn from(_: ()) -> Self {
+        ConnectError::Failed
+    }
+}
+
+/// Opens ws connections to server (endpoint selection, tls and upgrade)
+#[allow(async_fn_in_trait)]
+pub trait Connector {
+    type Connection<'a>: WsConnection
+    where
+        Self: 'a;
+
+    async fn connect(&mut self) -> Result<Self::Connection<'_>, ConnectError>;
+
+    /// Backoff base multiplier before next attempt, `None` reconnects
+    /// right away (redirect, answered auth challenge)
+    fn failed(&mut self, _error: ConnectError) -> Option<u64> {
+        Some(1)
+    }
+
+    /// Session on established connection ended
+    fn session_ended(&mut self) {}
+}
+
+/// Platform part of session. Packets and frames not handled by session
+/// itself are passed here (updates on device, refused by simulator)
+#[allow(async_fn_in_trait)]
+pub trait SessionHandler {
+    /// Called after session start (status and time request) was written
+    async fn started(&mut self, _conn: &mut impl WsConnection) -> Result<(), ()> {
+        Ok(())
+    }
+
+    async fn packet(
+        &mut self,
+        _conn: &mut impl WsConnection,
+        _packet: TimerPacket,
+    ) -> Result<(), ()> {
+        Ok(())
+    }
+
+    async fn binary(&mut self, _conn: &mut impl WsConnection, _data: Vec<u8>) -> Result<(), ()> {
+        Ok(())
+    }
+
+    /// [`SessionHandler::timeout`] is called once this instant passes
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    async fn timeout(&mut self) {}
+
+    async fn closed(&mut self, _kind: WsCloseKind) {}
+}
+
+/// Outbound frames and responses to tagged requests. Every task sends
+/// through it, connection task drains it while session runs
+pub struct Mailbox {
+    outbound: OutboundQueue<Frame>,
+    responses: PubSubChannel<CriticalSectionRawMutex, (u64, TimerPacket), 20, 20, 4>,
+}
+
+impl Mailbox {
+    pub const fn new() -> Self {
+        Self {
+            outbound: OutboundQueue::new(),
+            responses: PubSubChannel::new(),
+        }
+    }
+
+    pub async fn send_packet(&self, packet: TimerPacket) {
+        match serde_json::to_string(&packet) {
+            Ok(string) => {
+                let class = FrameClass::of_packet(&packet.data);
+                self.outbound.send(class, Frame::Text(string)).await;
+            }
+            Err(e) => {
+                log::error!("send_packet json to_string failed: {e:?}");
+            }
+        }
+    }
+
+    /// Sends request and waits for response with the same tag (forever if
+    /// `timeout_ms` is None). Retries should use the same tag, so late
+    /// response to earlier try is accepted too
+    pub async fn request<T: FromPacket>(
+        &self,
+        tag: u64,
+        packet: TimerPacketInner,
+        timeout_ms: Option<u64>,
+    ) -> Result<T, ApiError> {
+        // subscribed before sending, response can't be missed
+        let mut subscriber = loop {
+            match self.responses.subscriber() {
+                Ok(subscriber) => break subscriber,
+                Err(_) => {
+                    log::error!("failed to get response subscriber! Retry!");
+                    Timer::after_millis(500).await;
+                }
+            }
+        };
+
+        self.send_packet(TimerPacket {
+            tag: Some(tag),
+            data: packet,
+        })
+        .await;
+
+        let response = async {
+            loop {
+                let (packet_tag, packet) = subscriber.next_message_pure().await;
+                if packet_tag == tag {
+                    return packet;
+                }
+            }
+        };
+
+        let packet = match timeout_ms {
+            Some(timeout_ms) => response
+                .with_timeout(Duration::from_millis(timeout_ms))
+                .await
+                .map_err(|_| ApiError::timeout())?,
+            None => response.await,
+        };
+
+        T::from_packet(packet)
+    }
+}
+
+impl Default for Mailbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Connects to server and runs sessions until device goes to sleep (radio
+/// off), reconnecting with backoff. Never returns
+pub async fn connection_loop<M: RawMutex>(
+    platform: &impl Platform,
+    mailbox: &Mailbox,
+    connector: &mut impl Connector,
+    handler: &mut impl SessionHandler,
+    sleep_signal: &Signal<M, bool>,
+    connect_signal: &Signal<M, ()>,
+) -> ! {
+    let mut backoff = Backoff::new();
+    loop {
+        let session_fut = async {
+            let mut conn = connector.connect().await?;
+            let connected_at = Instant::now();
+
+            match session(platform, mailbox, &mut conn, handler, connect_signal).await {
+                Ok(WsCloseKind::AuthRequired) => return Err(ConnectError::AuthRequired),
+                Ok(_) => log::warn!("ws_rw: connection closed by server"),
+                Err(e) => log::error!("ws_rw_error: {e:?}"),
+            }
+
+            Ok(connected_at)
+        };
+
+        let res = select(session_fut, sleep_signal.wait()).await;
+        platform.set_server_connected(false).await;
+        log::info!("Server disconnected!");
+
+        match res {
+            Either::First(Ok(connected_at)) => {
+                connector.session_ended();
+
+                // endpoint worked - retry it first
+                platform.device_status().lock().await.ws_reconnects += 1;
+                if (Instant::now() - connected_at).as_millis()
+                    >= platform.config().ws_backoff_reset_after_ms
+                {
+                    backoff.reset();
+                }
+
+                wait_reconnect(platform, &mut backoff, 1).await;
+            }
+            Either::First(Err(e)) => {
+                if let Some(base_multiplier) = connector.failed(e) {
+                    wait_reconnect(platform, &mut backoff, base_multiplier).await;
+                }
+            }
+            Either::Second(sleep) => {
+                if sleep {
+                    while sleep_signal.wait().await {}
+                }
+
+                // radio was off, server is most likely fine
+                backoff.reset();
+                Timer::after_millis(500).await;
+            }
+        }
+    }
+}
+
+/// Waits before next connection attempt (exponential backoff with jitter,
+/// base is multiplied for failures that won't go away quickly)
+async fn wait_reconnect(platform: &impl Platform, backoff: &mut Backoff, base_multiplier: u64) {
+    let config = platform.config();
+    let delay = backoff.next_delay(
+        config.ws_backoff_base_ms.saturating_mul(base_multiplier),
+        config.ws_backoff_max_ms,
+        platform.random_u32(),
+    );
+
+    {
+        let mut status = platform.device_status().lock().await;
+        status.ws_retries = backoff.attempts();
+        status.ws_backoff_ms = delay;
+    }
+
+    log::warn!(
+        "[WS] Reconnecting in {delay}ms (attempt {})",
+        backoff.attempts()
+    );
+    Timer::after_millis(delay).await;
+}
+
+/// Session on upgraded connection. Returns Ok only if connection was closed
+/// by server (with kind of close frame)
+async fn session<M: RawMutex>(
+    platform: &impl Platform,
+    mailbox: &Mailbox,
+    conn: &mut impl WsConnection,
+    handler: &mut impl SessionHandler,
+    connect_signal: &Signal<M, ()>,
+) -> Result<WsCloseKind, ()> {
+    platform.set_server_connected(true).await;
+    connect_signal.signal(());
+    log::info!("Server connected!");
+
+    // replies and status queued for previous session are stale (attendance
+    // and logs are still delivered)
+    mailbox.outbound.clear_class(FrameClass::Control);
+    mailbox.outbound.clear_class(FrameClass::Telemetry);
+
+    // written directly, outbound queue isn't drained until session loop runs
+    let status = {
+        let mut status = platform.device_status().lock().await;
+        let current = status.clone();
+        status.clear_session_events();
+
+        current
+    };
+    platform.status_reported(&status).await;
+
+    for data in [
+        TimerPacketInner::DeviceStatus(status),
+        TimerPacketInner::EpochTimeRequest,
+    ] {
+        conn.write_packet(&TimerPacket { tag: None, data }).await?;
+    }
+
+    handler.started(conn).await?;
+
+    let background = async {
+        platform.session_task().await;
+        core::future::pending::<Infallible>().await
+    };
+
+    match select(rw(platform, mailbox, conn, handler), background).await {
+        Either::First(res) => res,
+        Either::Second(never) => match never {},
+    }
+}
+
+async fn rw(
+    platform: &impl Platform,
+    mailbox: &Mailbox,
+    conn: &mut impl WsConnection,
+    handler: &mut impl SessionHandler,
+) -> Result<WsCloseKind, ()> {
+    let tagged_publisher = mailbox.responses.publisher().map_err(|_| ())?;
+
+    let mut heartbeat = Heartbeat::new();
+    let mut next_ping = Instant::now();
+    loop {
+        let deadline = handler.deadline();
+        let deadline_fut = async {
+            match deadline {
+                Some(deadline) => Timer::at(deadline).await,
+                None => core::future::pending().await,
+            }
+        };
+
+        let frame = match select4(
+            conn.read_frame(),
+            mailbox.outbound.receive(),
+            deadline_fut,
+            Timer::at(next_ping),
+        )
+        .await
+        {
+            Either4::First(frame) => frame?,
+            Either4::Second(frame) => {
+                conn.write_frame(frame).await?;
+                continue;
+            }
+            Either4::Third(_) => {
+                handler.timeout().await;
+                continue;
+            }
+            Either4::Fourth(_) => {
+                let config = platform.config();
+                next_ping = Instant::now() + Duration::from_millis(config.heartbeat_interval_ms);
+
+                match heartbeat.tick(Instant::now().as_millis(), config.heartbeat_max_missed) {
+                    Ok(payload) => conn.write_frame(Frame::Ping(payload.to_vec())).await?,
+                    Err(missed) => {
+                        log::error!("[WS] {missed} pings without pong, dropping connection!");
+                        platform.device_status().lock().await.heartbeat_timeouts += 1;
+                        return Err(());
+                    }
+                }
+
+                continue;
+            }
+        };
+
+        match frame {
+            Frame::Text(text) => {
+                let packet = match serde_json::from_str::<TimerPacket>(&text) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        log::error!("timer_packet_fail: {e:?}\nTried to parse:\n{text}\n\n");
+                        continue;
+                    }
+                };
+
+                if let Some(tag) = packet.tag {
+                    tagged_publisher.publish((tag, packet.clone())).await;
+                }
+
+                let tag = packet.tag;
+                match packet.data {
+                    TimerPacketInner::DeviceSettings { added } => {
+                        platform.set_device_added(added).await;
+                        if !added {
+                            let add = TimerPacket {
+                                tag: None,
+                                data: TimerPacketInner::Add {
+                                    firmware: platform.firmware().to_string(),
+                                },
+                            };
+                            conn.write_packet(&add).await?;
+                        }
+                    }
+                    TimerPacketInner::ApiError(e) => {
+                        log::error!("Api Error: {e:?}");
+                    }
+                    TimerPacketInner::EpochTime { current_epoch } => {
+                        set_platform_time(platform, current_epoch * 1000, TimeSource::Server).await;
+                    }
+                    TimerPacketInner::DeviceConfig(config) => {
+                        let effective = platform.update_config(config).await;
+                        let reply = TimerPacket {
+                            tag,
+                            data: TimerPacketInner::DeviceConfig(effective),
+                        };
+                        conn.write_packet(&reply).await?;
+                    }
+                    data => handler.packet(conn, TimerPacket { tag, data }).await?,
+                }
+            }
+            Frame::Binary(data) => handler.binary(conn, data).await?,
+            Frame::Close(code, reason) => {
+                let kind = WsCloseKind::from_code(code);
+                log::warn!("Ws close frame: {code} ({kind:?}) reason: {reason:?}");
+                match kind {
+                    WsCloseKind::DeviceRejected => log::error!("Device rejected by server!"),
+                    WsCloseKind::AuthRequired => {
+                        log::warn!("Server requires authentication, retrying with challenge")
+                    }
+                    WsCloseKind::AuthFailed => {
+                        log::error!("Authentication failed! Check device secret");
+                        platform.device_status().lock().await.auth_failures += 1;
+                    }
+                    _ => {}
+                }
+                handler.closed(kind).await;
+
+                // close handshake - echo received code back (if it can be sent)
+                let echo = Frame::Close(WsCloseKind::echo_code(code), String::new());
+                _ = conn.write_frame(echo).await;
+
+                platform.set_server_connected(false).await;
+                platform.device_status().lock().await.last_close =
+                    Some(WsCloseInfo { code, reason, kind });
+
+                return Ok(kind);
+            }
+            Frame::Ping(_) => conn.write_frame(Frame::Pong(Vec::new())).await?,
+            Frame::Pong(payload) => {
+                if let Some(rtt) = heartbeat.pong(&payload, Instant::now().as_millis()) {
+                    log::debug!("[WS] Ping rtt: {rtt}ms");
+                    platform.device_status().lock().await.ws_rtt_ms = Some(rtt as u32);
+                }
+            }
+        }
+    }
+}
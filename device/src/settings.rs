@@ -0,0 +1,104 @@
+//! Connection settings saved by wifi manager panel
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use serde::Deserialize;
+use staff_at_protocol::TlsVerifyMode;
+
+#[derive(Deserialize, Debug)]
+pub struct ConnSettings {
+    /// Also look for server over mdns (after configured urls)
+    pub mdns: bool,
+
+    /// Server urls in order of preference
+    #[serde(default)]
+    pub ws_urls: Vec<String>,
+
+    /// Single url saved by older firmware
+    pub ws_url: Option<String>,
+
+    #[serde(default)]
+    pub tls_verify: TlsVerifyMode,
+
+    /// Base64 DER CA certificate (ca mode) or base64 SHA-256 of server public key (pin mode)
+    pub tls_cert: Option<String>,
+
+    /// Shared secret used to authenticate device in upgrade request
+    pub device_secret: Option<String>,
+
+    /// Ntp servers (`host[:port]`). If empty, servers provided by dhcp
+    /// (option 42) and public pool are used
+    #[serde(default)]
+    pub ntp_servers: Vec<String>,
+}
+
+impl Default for ConnSettings {
+    fn default() -> Self {
+        Self {
+            mdns: true,
+            ws_urls: Vec::new(),
+            ws_url: None,
+            tls_verify: TlsVerifyMode::None,
+            tls_cert: None,
+            device_secret: None,
+            ntp_servers: Vec::new(),
+        }
+    }
+}
+
+impl ConnSettings {
+    /// Settings saved by older firmware have only single `ws_url` (and mdns
+    /// was used only without it), it's moved to `ws_urls` and mdns is turned off
+    pub fn migrate(&mut self) {
+        if !self.ws_urls.is_empty() {
+            return;
+        }
+
+        let Some(url) = self.ws_url.take().filter(|url| !url.trim().is_empty()) else {
+            return;
+        };
+
+        log::info!("[SETTINGS] Migrated legacy server url: {url}");
+        self.ws_urls = vec![url];
+        self.mdns = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> ConnSettings {
+        let mut settings: ConnSettings = serde_json::from_str(json).unwrap();
+        settings.migrate();
+        settings
+    }
+
+    #[test]
+    fn legacy_url_is_migrated_without_mdns() {
+        let settings = parse(r#"{"mdns":true,"ws_url":"ws://10.0.0.2:8080"}"#);
+
+        assert_eq!(settings.ws_urls, ["ws://10.0.0.2:8080"]);
+        assert_eq!(settings.ws_url, None);
+        assert!(!settings.mdns);
+    }
+
+    #[test]
+    fn legacy_config_without_url_keeps_mdns() {
+        for json in [r#"{"mdns":true}"#, r#"{"mdns":false,"ws_url":" "}"#] {
+            let settings = parse(json);
+            assert!(settings.ws_urls.is_empty());
+        }
+
+        assert!(parse(r#"{"mdns":true}"#).mdns);
+    }
+
+    #[test]
+    fn new_config_is_kept() {
+        let settings = parse(r#"{"mdns":true,"ws_urls":["wss://a.example.com","ws://b:80"]}"#);
+
+        assert_eq!(settings.ws_urls, ["wss://a.example.com", "ws://b:80"]);
+        assert!(settings.mdns);
+    }
+}
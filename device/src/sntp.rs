@@ -0,0 +1,103 @@
+//! SNTP packet codec (also used by mock server)
+
+pub const NTP_PORT: u16 = 123;
+pub const PACKET_LEN: usize = 48;
+
+/// Seconds between ntp era 0 (1900) and unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+const VERSION: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SntpError {
+    Malformed,
+    WrongMode,
+
+    /// Stratum 0, server asks client to stop (or go away)
+    KissOfDeath,
+    Unsynchronized,
+
+    /// Response to other (older) request
+    OriginMismatch,
+}
+
+/// Client request. Transmit timestamp doesn't have to be real time, server
+/// only echoes it back (so local uptime is used to match response)
+pub fn request(sent_ms: u64) -> [u8; PACKET_LEN] {
+    let mut packet = [0; PACKET_LEN];
+    packet[0] = (VERSION << 3) | MODE_CLIENT;
+    packet[40..48].copy_from_slice(&sent_ms.to_be_bytes());
+    packet
+}
+
+/// Server response to `request` (transmitted at `unix_ms`), used by mock server
+pub fn response(request: &[u8], received_unix_ms: u64, unix_ms: u64) -> [u8; PACKET_LEN] {
+    let mut packet = [0; PACKET_LEN];
+    packet[0] = (VERSION << 3) | MODE_SERVER;
+    packet[1] = 1; // stratum (primary reference)
+    packet[12..16].copy_from_slice(b"MOCK");
+    if let Some(transmit) = request.get(40..48) {
+        packet[24..32].copy_from_slice(transmit);
+    }
+
+    packet[32..40].copy_from_slice(&to_timestamp(received_unix_ms).to_be_bytes());
+    packet[40..48].copy_from_slice(&to_timestamp(unix_ms).to_be_bytes());
+    packet
+}
+
+/// Returns unix time (ms) at `received_ms`. Both `sent_ms` (same as passed
+/// to [`request`]) and `received_ms` are local uptime
+pub fn parse_response(packet: &[u8], sent_ms: u64, received_ms: u64) -> Result<u64, SntpError> {
+    if packet.len() < PACKET_LEN {
+        return Err(SntpError::Malformed);
+    }
+
+    if packet[0] & 0x7 != MODE_SERVER {
+        return Err(SntpError::WrongMode);
+    }
+
+    let leap = packet[0] >> 6;
+    let stratum = packet[1];
+    if stratum == 0 {
+        return Err(SntpError::KissOfDeath);
+    }
+
+    if leap == 3 || stratum >= 16 {
+        return Err(SntpError::Unsynchronized);
+    }
+
+    if read_u64(packet, 24) != sent_ms {
+        return Err(SntpError::OriginMismatch);
+    }
+
+    let server_received = to_unix_ms(read_u64(packet, 32)).ok_or(SntpError::Malformed)?;
+    let server_sent = to_unix_ms(read_u64(packet, 40)).ok_or(SntpError::Malformed)?;
+
+    // time spent on server isn't part of network delay
+    let round_trip = received_ms
+        .saturating_sub(sent_ms)
+        .saturating_sub(server_sent.saturating_sub(server_received));
+
+    Ok(server_sent + round_trip / 2)
+}
+
+fn read_u64(packet: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&packet[offset..offset + 8]);
+    u64::from_be_bytes(bytes)
+}
+
+fn to_timestamp(unix_ms: u64) -> u64 {
+    let secs = unix_ms / 1000 + NTP_UNIX_OFFSET;
+    let frac = ((unix_ms % 1000) << 32) / 1000;
+    (secs << 32) | frac
+}
+
+fn to_unix_ms(timestamp: u64) -> Option<u64> {
+    let secs = (timestamp >> 32).checked_sub(NTP_UNIX_OFFSET)?;
+    // rounded, truncating loses millisecond on round trip
+    let frac_ms = ((timestamp & 0xFFFF_FFFF) * 1000 + (1 << 31)) >> 32;
+    Some(secs * 1000 + frac_ms)
+}
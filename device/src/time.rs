@@ -0,0 +1,84 @@
+use crate::clock_drift::{ClockSync, DriftEstimator};
+use crate::{Link, Platform};
+use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+use staff_at_protocol::{TimeSource, TimerPacket, TimerPacketInner};
+
+/// Device clock. Unix time is kept as time at boot plus uptime
+pub trait Clock {
+    fn uptime_ms(&self) -> u64;
+
+    /// Unix time (ms) at boot
+    fn epoch_base_ms(&self) -> u64;
+
+    /// Time is trusted after it's set (until server rejects it)
+    fn set_epoch_base_ms(&self, epoch_base_ms: u64);
+
+    fn epoch_ms(&self) -> u64 {
+        self.epoch_base_ms() + self.uptime_ms()
+    }
+}
+
+impl<T: Clock + ?Sized> Clock for &T {
+    fn uptime_ms(&self) -> u64 {
+        (**self).uptime_ms()
+    }
+
+    fn epoch_base_ms(&self) -> u64 {
+        (**self).epoch_base_ms()
+    }
+
+    fn set_epoch_base_ms(&self, epoch_base_ms: u64) {
+        (**self).set_epoch_base_ms(epoch_base_ms)
+    }
+}
+
+/// Sets device time (unix ms) and reports estimated clock drift
+pub async fn set_time<M: RawMutex>(
+    clock: &impl Clock,
+    drift: &Mutex<M, DriftEstimator>,
+    link: &impl Link,
+    epoch_ms: u64,
+    source: TimeSource,
+) -> ClockSync {
+    let now_ms = clock.uptime_ms();
+
+    // server sends whole seconds, only ntp is precise enough for drift
+    let precise = source == TimeSource::Ntp;
+    let sync = drift
+        .lock()
+        .await
+        .sync(now_ms, clock.epoch_ms(), epoch_ms, precise);
+    clock.set_epoch_base_ms(epoch_ms.saturating_sub(now_ms));
+
+    log::info!(
+        "[TIME] Synced from {source:?} (offset: {:?}ms, drift: {:?}ppm)",
+        sync.offset_ms,
+        sync.drift_ppm
+    );
+
+    link.send_packet(TimerPacket {
+        tag: None,
+        data: TimerPacketInner::ClockSync {
+            source,
+            offset_ms: sync.offset_ms,
+            drift_ppm: sync.drift_ppm,
+        },
+    })
+    .await;
+
+    sync
+}
+
+/// [`set_time`] on platform state (source is also kept in device status)
+pub async fn set_platform_time(platform: &impl Platform, epoch_ms: u64, source: TimeSource) {
+    set_time(
+        &platform.clock(),
+        platform.clock_drift(),
+        &platform.link(),
+        epoch_ms,
+        source,
+    )
+    .await;
+
+    platform.device_status().lock().await.time_source = Some(source);
+}
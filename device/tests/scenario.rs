@@ -0,0 +1,432 @@
+//! Device logic driven end to end (scan, offline queue, restart, replay)
+//! against in-memory storage and scripted server
+
+use embassy_futures::block_on;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
+use serde::{de::DeserializeOwned, Serialize};
+use staff_at_device::clock_drift::DriftEstimator;
+use staff_at_device::queue::{self, OfflineQueue, QueuedScan};
+use staff_at_device::scan::{self, ScanCounter};
+use staff_at_device::time::{self, Clock};
+use staff_at_device::{KvStore, Link};
+use staff_at_protocol::{
+    ApiError, ApiErrorKind, FromPacket, TimeSource, TimerPacket, TimerPacketInner,
+};
+use staff_at_scanner::mock::MockReader;
+use staff_at_scanner::{CardUid, ScanAction, Scanner, ScannerConfig};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
+
+const DEVICE_ID: u32 = 1234;
+const SCANNER_CONFIG: ScannerConfig = ScannerConfig {
+    sleep_after_ms: 60_000,
+    deeper_sleep_after_ms: 120_000,
+    debounce_ms: 300,
+};
+
+/// Survives simulated restart (only queue and counter are reloaded)
+#[derive(Default)]
+struct MemKv(RefCell<BTreeMap<String, String>>);
+
+impl MemKv {
+    fn keys(&self) -> Vec<String> {
+        self.0.borrow().keys().cloned().collect()
+    }
+}
+
+impl KvStore for MemKv {
+    async fn load<T: DeserializeOwned>(&self, key: &[u8]) -> Option<T> {
+        let keys = self.0.borrow();
+        serde_json::from_str(keys.get(std::str::from_utf8(key).ok()?)?).ok()
+    }
+
+    async fn store<T: Serialize>(&self, key: &[u8], value: &T) -> bool {
+        let key = String::from_utf8_lossy(key).into_owned();
+        let json = serde_json::to_string(value).unwrap();
+        self.0.borrow_mut().insert(key, json);
+        true
+    }
+
+    async fn remove(&self, key: &[u8]) {
+        self.0.borrow_mut().remove(&*String::from_utf8_lossy(key));
+    }
+}
+
+/// Server that marks every scan while responding (requests time out otherwise)
+#[derive(Default)]
+struct ScriptedLink {
+    connected: Cell<bool>,
+    responding: Cell<bool>,
+    sent: RefCell<Vec<TimerPacketInner>>,
+}
+
+impl Link for ScriptedLink {
+    async fn send_packet(&self, packet: TimerPacket) {
+        self.sent.borrow_mut().push(packet.data);
+    }
+
+    async fn send_request<T: FromPacket>(
+        &self,
+        packet: TimerPacketInner,
+        tries: usize,
+    ) -> Result<T, ApiError> {
+        for _ in 0..tries {
+            self.sent.borrow_mut().push(packet.clone());
+            if self.responding.get() {
+                return T::from_packet(TimerPacket {
+                    tag: Some(1),
+                    data: TimerPacketInner::AttendanceMarked,
+                });
+            }
+        }
+
+        Err(ApiError::timeout())
+    }
+
+    async fn reset_time(&self) {}
+}
+
+struct TestClock {
+    uptime_ms: Cell<u64>,
+    epoch_base_ms: Cell<u64>,
+}
+
+impl Clock for TestClock {
+    fn uptime_ms(&self) -> u64 {
+        self.uptime_ms.get()
+    }
+
+    fn epoch_base_ms(&self) -> u64 {
+        self.epoch_base_ms.get()
+    }
+
+    fn set_epoch_base_ms(&self, epoch_base_ms: u64) {
+        self.epoch_base_ms.set(epoch_base_ms);
+    }
+}
+
+/// Device state lost on restart
+struct Device {
+    queue: Mutex<NoopRawMutex, OfflineQueue>,
+    counter: ScanCounter,
+    scanner: Scanner,
+}
+
+impl Device {
+    fn boot(kv: &MemKv) -> Self {
+        let mut queue = OfflineQueue::new(4, true);
+        let mut counter = ScanCounter::new(32);
+        block_on(queue.load(kv));
+        block_on(counter.load(kv));
+
+        Self {
+            queue: Mutex::new(queue),
+            counter,
+            scanner: Scanner::new(0),
+        }
+    }
+
+    /// Same flow as `rfid_task` (scan is queued if it can't be delivered)
+    fn scan(&mut self, reader: &mut MockReader, kv: &MemKv, link: &ScriptedLink, now_ms: u64) {
+        let can_deliver = link.connected.get() && block_on(self.queue.lock()).is_empty();
+        let action = block_on(
+            self.scanner
+                .poll(reader, &SCANNER_CONFIG, now_ms, can_deliver),
+        );
+
+        let (ScanAction::Deliver(uid) | ScanAction::Queue(uid)) = action else {
+            panic!("Expected scan, got {action:?}");
+        };
+
+        let scan_epoch = 1_700_000_000 + now_ms / 1000;
+        let scan = QueuedScan {
+            card_id: uid.card_id,
+            card_uid: Some(uid.to_hex()),
+            scan_epoch,
+            time_valid: Some(true),
+            scan_id: Some(block_on(self.counter.next_id(kv, DEVICE_ID, scan_epoch))),
+        };
+
+        if matches!(action, ScanAction::Queue(_)) || !block_on(scan::deliver(link, &scan, 3)) {
+            block_on(scan::queue(&self.queue, kv, scan));
+        }
+    }
+}
+
+fn card(b: u8) -> CardUid {
+    CardUid::new(&[b, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66], b as u64)
+}
+
+fn requested_scan_ids(sent: &[TimerPacketInner]) -> Vec<String> {
+    sent.iter()
+        .filter_map(|packet| match packet {
+            TimerPacketInner::CardInfoRequest { scan_id, .. } => scan_id.clone(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn offline_scans_survive_restart_and_replay_in_order() {
+    let kv = MemKv::default();
+    let link = ScriptedLink::default();
+    let mut reader = MockReader::new([Some(card(1)), Some(card(2))]);
+
+    // server connected, but not responding - first scan is retried then queued
+    link.connected.set(true);
+    let mut device = Device::boot(&kv);
+    device.scan(&mut reader, &kv, &link, 1000);
+    device.scan(&mut reader, &kv, &link, 2000);
+
+    assert_eq!(link.sent.borrow().len(), 3);
+    assert_eq!(
+        kv.keys(),
+        [
+            "ATT_QUEUE_0",
+            "ATT_QUEUE_1",
+            "ATT_QUEUE_META",
+            "SCAN_COUNTER"
+        ]
+    );
+
+    let queued_ids = requested_scan_ids(&link.sent.borrow());
+    assert_eq!(queued_ids, ["1234-0-1700000001"; 3]);
+
+    // restart - queue is loaded from storage, scan ids are never reused
+    let mut device = Device::boot(&kv);
+    assert_eq!(block_on(device.queue.lock()).len(), 2);
+    assert_eq!(
+        block_on(device.counter.next_id(&kv, DEVICE_ID, 0)),
+        "1234-32-0"
+    );
+
+    link.responding.set(true);
+    link.sent.borrow_mut().clear();
+    block_on(queue::replay(&device.queue, &kv, &link));
+
+    let sent = link.sent.borrow();
+    assert!(matches!(
+        sent[0],
+        TimerPacketInner::OfflineQueue {
+            pending: 2,
+            capacity: 4,
+            dropped: 0,
+            ..
+        }
+    ));
+    assert_eq!(
+        requested_scan_ids(&sent),
+        ["1234-0-1700000001", "1234-1-1700000002"]
+    );
+
+    assert!(block_on(device.queue.lock()).is_empty());
+    assert_eq!(kv.keys(), ["ATT_QUEUE_META", "SCAN_COUNTER"]);
+}
+
+#[test]
+fn scans_are_queued_while_older_ones_wait() {
+    let kv = MemKv::default();
+    let link = ScriptedLink::default();
+    let mut reader = MockReader::new([Some(card(1)), Some(card(2))]);
+
+    let mut device = Device::boot(&kv);
+    device.scan(&mut reader, &kv, &link, 1000);
+
+    // server is back, but first scan wasn't replayed yet
+    link.connected.set(true);
+    link.responding.set(true);
+    device.scan(&mut reader, &kv, &link, 2000);
+
+    assert!(link.sent.borrow().is_empty());
+    assert_eq!(block_on(device.queue.lock()).len(), 2);
+}
+
+#[test]
+fn full_queue_drops_oldest_scan() {
+    let kv = MemKv::default();
+    let link = ScriptedLink::default();
+    let mut device = Device::boot(&kv);
+
+    for b in 1..=6 {
+        let mut reader = MockReader::new([Some(card(b))]);
+        device.scan(&mut reader, &kv, &link, b as u64 * 1000);
+    }
+
+    // ring wrapped around, oldest two entries were overwritten
+    let mut device = Device::boot(&kv);
+    {
+        let queue = block_on(device.queue.lock());
+        assert_eq!(queue.len(), 4);
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(queue.front().map(|scan| scan.card_id), Some(3));
+    }
+
+    link.responding.set(true);
+    block_on(queue::replay(&device.queue, &kv, &link));
+    assert!(matches!(
+        link.sent.borrow()[0],
+        TimerPacketInner::OfflineQueue {
+            pending: 4,
+            dropped: 2,
+            ..
+        }
+    ));
+
+    device = Device::boot(&kv);
+    let queue = block_on(device.queue.lock());
+    assert!(queue.is_empty());
+    assert_eq!(queue.dropped(), 0);
+}
+
+#[test]
+fn rejected_scan_is_not_queued() {
+    #[derive(Default)]
+    struct RejectingLink {
+        time_resets: Cell<usize>,
+    }
+
+    impl Link for RejectingLink {
+        async fn send_packet(&self, _packet: TimerPacket) {}
+
+        async fn send_request<T: FromPacket>(
+            &self,
+            _packet: TimerPacketInner,
+            _tries: usize,
+        ) -> Result<T, ApiError> {
+            Err(ApiError::new("Competitor not found", true))
+        }
+
+        async fn reset_time(&self) {
+            self.time_resets.set(self.time_resets.get() + 1);
+        }
+    }
+
+    let link = RejectingLink::default();
+    let scan = QueuedScan {
+        card_id: 1,
+        card_uid: None,
+        scan_epoch: 0,
+        time_valid: None,
+        scan_id: None,
+    };
+
+    assert!(block_on(scan::deliver(&link, &scan, 3)));
+    assert_eq!(link.time_resets.get(), 1);
+}
+
+#[test]
+fn replay_drops_only_rejected_scans() {
+    /// Responds to requests with scripted results (scan is marked on `None`)
+    #[derive(Default)]
+    struct ErrorLink {
+        results: RefCell<VecDeque<Option<ApiError>>>,
+        time_resets: Cell<usize>,
+    }
+
+    impl Link for ErrorLink {
+        async fn send_packet(&self, _packet: TimerPacket) {}
+
+        async fn send_request<T: FromPacket>(
+            &self,
+            _packet: TimerPacketInner,
+            _tries: usize,
+        ) -> Result<T, ApiError> {
+            match self.results.borrow_mut().pop_front().flatten() {
+                Some(e) => Err(e),
+                None => T::from_packet(TimerPacket {
+                    tag: Some(1),
+                    data: TimerPacketInner::AttendanceMarked,
+                }),
+            }
+        }
+
+        async fn reset_time(&self) {
+            self.time_resets.set(self.time_resets.get() + 1);
+        }
+    }
+
+    let kv = MemKv::default();
+    let device = Device::boot(&kv);
+    for b in 1..=3 {
+        let scan = QueuedScan {
+            card_id: b,
+            card_uid: None,
+            scan_epoch: 0,
+            time_valid: None,
+            scan_id: None,
+        };
+        block_on(scan::queue(&device.queue, &kv, scan));
+    }
+
+    let link = ErrorLink::default();
+    let front = || {
+        block_on(device.queue.lock())
+            .front()
+            .map(|scan| scan.card_id)
+    };
+    let wrong_response = ApiError {
+        kind: ApiErrorKind::WrongResponse,
+        ..ApiError::new("Wrong response", false)
+    };
+
+    // scans that may not be processed are kept and replay stops
+    for e in [
+        ApiError::timeout(),
+        wrong_response,
+        ApiError::new("Wrong time", true),
+    ] {
+        link.results.borrow_mut().push_back(Some(e));
+        block_on(queue::replay(&device.queue, &kv, &link));
+        assert_eq!(front(), Some(1));
+    }
+    assert_eq!(link.time_resets.get(), 1);
+
+    // rejected scan is dropped, replay continues with next one
+    link.results.borrow_mut().extend([
+        Some(ApiError::new("Competitor not found", false)),
+        None,
+        None,
+    ]);
+    block_on(queue::replay(&device.queue, &kv, &link));
+    assert_eq!(front(), None);
+    assert_eq!(kv.keys(), ["ATT_QUEUE_META"]);
+}
+
+#[test]
+fn time_sync_reports_offset() {
+    let link = ScriptedLink::default();
+    let drift = Mutex::<NoopRawMutex, _>::new(DriftEstimator::new());
+    let clock = TestClock {
+        uptime_ms: Cell::new(5000),
+        epoch_base_ms: Cell::new(0),
+    };
+
+    block_on(time::set_time(
+        &clock,
+        &drift,
+        &link,
+        1_700_000_000_000,
+        TimeSource::Server,
+    ));
+    assert_eq!(clock.epoch_ms(), 1_700_000_000_000);
+
+    clock.uptime_ms.set(65_000);
+    let sync = block_on(time::set_time(
+        &clock,
+        &drift,
+        &link,
+        1_700_000_060_250,
+        TimeSource::Ntp,
+    ));
+
+    assert_eq!(sync.offset_ms, Some(250));
+    assert_eq!(clock.epoch_ms(), 1_700_000_060_250);
+    assert!(matches!(
+        link.sent.borrow()[1],
+        TimerPacketInner::ClockSync {
+            source: TimeSource::Ntp,
+            offset_ms: Some(250),
+            ..
+        }
+    ));
+}
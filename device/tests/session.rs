@@ -0,0 +1,447 @@
+//! Shared connection loop driven over scripted ws connection
+
+use core::cell::{Cell, RefCell};
+use embassy_futures::{block_on, join::join, select::select};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, mutex::Mutex, signal::Signal,
+};
+use embassy_time::{Instant, Timer};
+use serde::{de::DeserializeOwned, Serialize};
+use staff_at_device::clock_drift::DriftEstimator;
+use staff_at_device::queue::OfflineQueue;
+use staff_at_device::scan::ScanCounter;
+use staff_at_device::session::{
+    connection_loop, ConnectError, Connector, Frame, Mailbox, SessionHandler, WsConnection,
+};
+use staff_at_device::{Clock, KvStore, Link, Platform};
+use staff_at_protocol::{
+    ApiError, AttendanceMarkedPacket, DeviceConfig, DeviceStatus, FromPacket, ResetReason,
+    TimeSource, TimerPacket, TimerPacketInner, WsCloseKind,
+};
+
+type Cs = CriticalSectionRawMutex;
+
+struct NoKv;
+
+impl KvStore for NoKv {
+    async fn load<T: DeserializeOwned>(&self, _key: &[u8]) -> Option<T> {
+        None
+    }
+
+    async fn store<T: Serialize>(&self, _key: &[u8], _value: &T) -> bool {
+        true
+    }
+
+    async fn remove(&self, _key: &[u8]) {}
+}
+
+struct TestLink<'a>(&'a Mailbox);
+
+impl Link for TestLink<'_> {
+    async fn send_packet(&self, packet: TimerPacket) {
+        self.0.send_packet(packet).await;
+    }
+
+    async fn send_request<T: FromPacket>(
+        &self,
+        packet: TimerPacketInner,
+        _tries: usize,
+    ) -> Result<T, ApiError> {
+        self.0.request(1, packet, Some(1000)).await
+    }
+
+    async fn reset_time(&self) {}
+}
+
+struct TestPlatform {
+    mailbox: Mailbox,
+    config: Cell<DeviceConfig>,
+    epoch_base_ms: Cell<u64>,
+    connected: Cell<bool>,
+    added: Cell<Option<bool>>,
+    offline_queue: Mutex<Cs, OfflineQueue>,
+    scan_counter: Mutex<Cs, ScanCounter>,
+    device_status: Mutex<Cs, DeviceStatus>,
+    clock_drift: Mutex<Cs, DriftEstimator>,
+}
+
+impl TestPlatform {
+    fn new() -> Self {
+        Self {
+            mailbox: Mailbox::new(),
+            config: Cell::new(DeviceConfig {
+                ws_backoff_base_ms: 10,
+                ws_backoff_max_ms: 20,
+                heartbeat_interval_ms: 60_000,
+                ..DeviceConfig::DEFAULT
+            }),
+            epoch_base_ms: Cell::new(0),
+            connected: Cell::new(false),
+            added: Cell::new(None),
+            offline_queue: Mutex::new(OfflineQueue::new(4, true)),
+            scan_counter: Mutex::new(ScanCounter::new(32)),
+            device_status: Mutex::new(DeviceStatus::default()),
+            clock_drift: Mutex::new(DriftEstimator::new()),
+        }
+    }
+}
+
+impl Clock for TestPlatform {
+    fn uptime_ms(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+
+    fn epoch_base_ms(&self) -> u64 {
+        self.epoch_base_ms.get()
+    }
+
+    fn set_epoch_base_ms(&self, epoch_base_ms: u64) {
+        self.epoch_base_ms.set(epoch_base_ms);
+    }
+}
+
+impl Platform for TestPlatform {
+    type RawMutex = Cs;
+
+    fn clock(&self) -> impl Clock + '_ {
+        self
+    }
+
+    fn kv(&self) -> impl KvStore + '_ {
+        NoKv
+    }
+
+    fn link(&self) -> impl Link + '_ {
+        TestLink(&self.mailbox)
+    }
+
+    fn device_id(&self) -> u32 {
+        1234
+    }
+
+    fn firmware(&self) -> &str {
+        "TEST"
+    }
+
+    fn random_u32(&self) -> u32 {
+        0
+    }
+
+    fn config(&self) -> DeviceConfig {
+        self.config.get()
+    }
+
+    async fn update_config(&self, config: DeviceConfig) -> DeviceConfig {
+        self.config.set(config);
+        config
+    }
+
+    fn offline_queue(&self) -> &Mutex<Cs, OfflineQueue> {
+        &self.offline_queue
+    }
+
+    fn scan_counter(&self) -> &Mutex<Cs, ScanCounter> {
+        &self.scan_counter
+    }
+
+    fn device_status(&self) -> &Mutex<Cs, DeviceStatus> {
+        &self.device_status
+    }
+
+    fn clock_drift(&self) -> &Mutex<Cs, DriftEstimator> {
+        &self.clock_drift
+    }
+
+    fn time_valid(&self) -> bool {
+        self.epoch_base_ms.get() != 0
+    }
+
+    fn sleep_state(&self) -> bool {
+        false
+    }
+
+    fn set_sleep_state(&self, _sleep: bool) {}
+
+    fn enter_deeper_sleep(&self) {}
+
+    async fn server_connected(&self) -> bool {
+        self.connected.get()
+    }
+
+    async fn set_server_connected(&self, connected: bool) {
+        self.connected.set(connected);
+    }
+
+    async fn set_device_added(&self, added: bool) {
+        self.added.set(Some(added));
+    }
+
+    fn restart(&self, reason: ResetReason) -> ! {
+        panic!("restart: {reason:?}");
+    }
+}
+
+/// Server side of connection (frames are pushed by test)
+struct Server {
+    incoming: Channel<Cs, Frame, 8>,
+    written: RefCell<Vec<Frame>>,
+    connects: Cell<usize>,
+    reconnected: Signal<Cs, ()>,
+}
+
+impl Server {
+    fn new() -> Self {
+        Self {
+            incoming: Channel::new(),
+            written: RefCell::new(Vec::new()),
+            connects: Cell::new(0),
+            reconnected: Signal::new(),
+        }
+    }
+
+    async fn send(&self, packet: TimerPacket) {
+        let json = serde_json::to_string(&packet).unwrap();
+        self.incoming.send(Frame::Text(json)).await;
+    }
+
+    fn written_packets(&self) -> Vec<TimerPacket> {
+        self.written
+            .borrow()
+            .iter()
+            .filter_map(|frame| match frame {
+                Frame::Text(text) => Some(serde_json::from_str(text).unwrap()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Waits until device writes frame matching `f`
+    async fn wait_written(&self, f: impl Fn(&Frame) -> bool) {
+        while !self.written.borrow().iter().any(&f) {
+            Timer::after_millis(1).await;
+        }
+    }
+}
+
+struct TestConnection<'a>(&'a Server);
+
+impl WsConnection for TestConnection<'_> {
+    async fn read_frame(&mut self) -> Result<Frame, ()> {
+        Ok(self.0.incoming.receive().await)
+    }
+
+    async fn write_frame(&mut self, frame: Frame) -> Result<(), ()> {
+        self.0.written.borrow_mut().push(frame);
+        Ok(())
+    }
+}
+
+/// First connect fails, second one works, later ones never finish
+struct TestConnector<'a>(&'a Server);
+
+impl Connector for TestConnector<'_> {
+    type Connection<'a>
+        = TestConnection<'a>
+    where
+        Self: 'a;
+
+    async fn connect(&mut self) -> Result<Self::Connection<'_>, ConnectError> {
+        let connects = self.0.connects.get() + 1;
+        self.0.connects.set(connects);
+        match connects {
+            1 => Err(ConnectError::Failed),
+            2 => Ok(TestConnection(self.0)),
+            _ => {
+                self.0.reconnected.signal(());
+                core::future::pending().await
+            }
+        }
+    }
+}
+
+/// Records packets not handled by session
+#[derive(Default)]
+struct TestHandler {
+    packets: Vec<TimerPacketInner>,
+    closed: Option<WsCloseKind>,
+}
+
+impl SessionHandler for TestHandler {
+    async fn packet(
+        &mut self,
+        _conn: &mut impl WsConnection,
+        packet: TimerPacket,
+    ) -> Result<(), ()> {
+        self.packets.push(packet.data);
+        Ok(())
+    }
+
+    async fn closed(&mut self, kind: WsCloseKind) {
+        self.closed = Some(kind);
+    }
+}
+
+/// Runs connection loop until `script` finishes
+fn run_session<F: core::future::Future>(
+    platform: &TestPlatform,
+    server: &Server,
+    handler: &mut TestHandler,
+    script: F,
+) -> F::Output {
+    let sleep_signal = Signal::<Cs, bool>::new();
+    let connect_signal = Signal::<Cs, ()>::new();
+    let mut connector = TestConnector(server);
+
+    let res = block_on(select(
+        connection_loop(
+            platform,
+            &platform.mailbox,
+            &mut connector,
+            handler,
+            &sleep_signal,
+            &connect_signal,
+        ),
+        script,
+    ));
+
+    match res {
+        embassy_futures::select::Either::First(never) => match never {},
+        embassy_futures::select::Either::Second(output) => output,
+    }
+}
+
+#[test]
+fn session_handles_server_packets_and_reconnects() {
+    let platform = TestPlatform::new();
+    let server = Server::new();
+    let mut handler = TestHandler::default();
+
+    let config = DeviceConfig {
+        scan_debounce_ms: 1234,
+        ..platform.config()
+    };
+
+    run_session(&platform, &server, &mut handler, async {
+        for data in [
+            TimerPacketInner::DeviceSettings { added: false },
+            TimerPacketInner::EpochTime {
+                current_epoch: 1_700_000_000,
+            },
+            TimerPacketInner::AuthChallenge {
+                nonce: "abc".into(),
+            },
+        ] {
+            server.send(TimerPacket { tag: None, data }).await;
+        }
+        server
+            .send(TimerPacket {
+                tag: Some(7),
+                data: TimerPacketInner::DeviceConfig(config),
+            })
+            .await;
+        server.incoming.send(Frame::Close(1001, "bye".into())).await;
+
+        server.reconnected.wait().await;
+    });
+
+    let written = server.written_packets();
+    assert!(matches!(written[0].data, TimerPacketInner::DeviceStatus(_)));
+    assert!(matches!(
+        written[1].data,
+        TimerPacketInner::EpochTimeRequest
+    ));
+    assert!(matches!(&written[2].data, TimerPacketInner::Add { firmware } if firmware == "TEST"));
+    assert_eq!(written[3].tag, Some(7));
+    assert!(matches!(written[3].data, TimerPacketInner::DeviceConfig(c) if c == config));
+    assert!(server
+        .written
+        .borrow()
+        .iter()
+        .any(|frame| matches!(frame, Frame::Close(1001, _))));
+
+    assert_eq!(platform.added.get(), Some(false));
+    assert_eq!(platform.config(), config);
+    assert_eq!(platform.epoch_base_ms() / 1000, 1_700_000_000 - 1);
+    assert!(!platform.connected.get());
+    assert!(matches!(
+        &handler.packets[..],
+        [TimerPacketInner::AuthChallenge { nonce }] if nonce == "abc"
+    ));
+    assert_eq!(handler.closed, Some(WsCloseKind::GoingAway));
+
+    let status = block_on(platform.device_status.lock());
+    assert_eq!(status.ws_reconnects, 1);
+    assert_eq!(status.time_source, Some(TimeSource::Server));
+    assert_eq!(status.last_close.as_ref().map(|c| c.code), Some(1001));
+    assert_eq!(server.connects.get(), 3);
+}
+
+#[test]
+fn tagged_response_is_returned_to_request() {
+    let platform = TestPlatform::new();
+    let server = Server::new();
+    let mut handler = TestHandler::default();
+
+    let res = run_session(&platform, &server, &mut handler, async {
+        let request = platform.mailbox.request::<AttendanceMarkedPacket>(
+            42,
+            TimerPacketInner::CardInfoRequest {
+                card_id: 1,
+                card_uid: None,
+                attendance_device: None,
+                scan_epoch: None,
+                time_valid: None,
+                scan_id: None,
+            },
+            Some(1000),
+        );
+
+        let response = async {
+            server
+                .wait_written(|frame| matches!(frame, Frame::Text(text) if text.contains("42")))
+                .await;
+            server
+                .send(TimerPacket {
+                    tag: Some(41),
+                    data: TimerPacketInner::ResetReportAck,
+                })
+                .await;
+            server
+                .send(TimerPacket {
+                    tag: Some(42),
+                    data: TimerPacketInner::AttendanceMarked,
+                })
+                .await;
+        };
+
+        join(request, response).await.0
+    });
+
+    assert!(res.is_ok());
+}
+
+#[test]
+fn missed_pongs_drop_connection() {
+    let platform = TestPlatform::new();
+    platform.config.set(DeviceConfig {
+        heartbeat_interval_ms: 5,
+        heartbeat_max_missed: 2,
+        ..platform.config()
+    });
+    let server = Server::new();
+    let mut handler = TestHandler::default();
+
+    run_session(&platform, &server, &mut handler, server.reconnected.wait());
+
+    let pings = server
+        .written
+        .borrow()
+        .iter()
+        .filter(|frame| matches!(frame, Frame::Ping(_)))
+        .count();
+    assert_eq!(pings, 2);
+    assert_eq!(
+        block_on(platform.device_status.lock()).heartbeat_timeouts,
+        1
+    );
+}
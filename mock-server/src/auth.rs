@@ -0,0 +1,214 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use staff_at_protocol::auth::{
+    auth_message, AUTH_FAILED_CLOSE_CODE, AUTH_MAX_CLOCK_SKEW_S, AUTH_REQUIRED_CLOSE_CODE,
+};
+use std::collections::HashSet;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Nonces handed out to devices (removed once used) and client nonces of
+/// time signed upgrades (kept, so captured upgrade can't be replayed)
+#[derive(Debug, Default)]
+pub struct Nonces {
+    issued: Mutex<HashSet<String>>,
+    used_cnonces: Mutex<HashSet<String>>,
+}
+
+impl Nonces {
+    pub fn issue(&self) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let nonce = format!("{:016x}", RandomState::new().hash_one(nanos));
+
+        self.issued.lock().unwrap().insert(nonce.clone());
+        nonce
+    }
+
+    fn take(&self, nonce: &str) -> bool {
+        self.issued.lock().unwrap().remove(nonce)
+    }
+
+    fn cnonce_used(&self, cnonce: &str) -> bool {
+        self.used_cnonces.lock().unwrap().contains(cnonce)
+    }
+
+    fn mark_cnonce_used(&self, cnonce: &str) {
+        self.used_cnonces.lock().unwrap().insert(cnonce.into());
+    }
+}
+
+/// Query params of upgrade answering challenge
+#[derive(Debug, Default)]
+pub struct AuthParams<'a> {
+    pub nonce: &'a str,
+    pub cnonce: &'a str,
+    pub ts: &'a str,
+    pub sig: &'a str,
+}
+
+/// Checks upgrade query answer (nonce, or device time if nonce is empty),
+/// Err is close code
+pub fn verify(
+    secret: &str,
+    nonces: &Nonces,
+    device_id: &str,
+    params: &AuthParams,
+    now_s: u64,
+) -> Result<(), u16> {
+    let ts = params.ts.parse::<u64>().unwrap_or_default();
+    if params.nonce.is_empty() {
+        let fresh = ts.abs_diff(now_s) <= AUTH_MAX_CLOCK_SKEW_S;
+        if !fresh || params.cnonce.is_empty() || nonces.cnonce_used(params.cnonce) {
+            return Err(AUTH_REQUIRED_CLOSE_CODE);
+        }
+    } else if !nonces.take(params.nonce) {
+        return Err(AUTH_REQUIRED_CLOSE_CODE);
+    }
+
+    let device_id = device_id.parse().map_err(|_| AUTH_FAILED_CLOSE_CODE)?;
+    let sig = from_hex(params.sig).ok_or(AUTH_FAILED_CLOSE_CODE)?;
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|_| AUTH_FAILED_CLOSE_CODE)?;
+    mac.update(auth_message(device_id, params.nonce, params.cnonce, ts).as_bytes());
+    mac.verify_slice(&sig).map_err(|_| AUTH_FAILED_CLOSE_CODE)?;
+
+    if params.nonce.is_empty() {
+        nonces.mark_cnonce_used(params.cnonce);
+    }
+
+    Ok(())
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn sign(secret: &str, device_id: u32, nonce: &str, cnonce: &str, ts: u64) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(auth_message(device_id, nonce, cnonce, ts).as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    #[test]
+    fn valid_signature_consumes_nonce() {
+        let nonces = Nonces::default();
+        let nonce = nonces.issue();
+        let sig = sign("secret", 1234, &nonce, "abcd", 0);
+        let params = AuthParams {
+            nonce: &nonce,
+            cnonce: "abcd",
+            ts: "0",
+            sig: &sig,
+        };
+
+        assert_eq!(verify("secret", &nonces, "1234", &params, NOW), Ok(()));
+        assert_eq!(
+            verify("secret", &nonces, "1234", &params, NOW),
+            Err(AUTH_REQUIRED_CLOSE_CODE)
+        );
+    }
+
+    #[test]
+    fn wrong_secret_fails() {
+        let nonces = Nonces::default();
+        let nonce = nonces.issue();
+        let sig = sign("other", 1234, &nonce, "abcd", 0);
+        let params = AuthParams {
+            nonce: &nonce,
+            cnonce: "abcd",
+            ts: "0",
+            sig: &sig,
+        };
+
+        assert_eq!(
+            verify("secret", &nonces, "1234", &params, NOW),
+            Err(AUTH_FAILED_CLOSE_CODE)
+        );
+    }
+
+    #[test]
+    fn unknown_nonce_requires_auth() {
+        let nonces = Nonces::default();
+        assert_eq!(
+            verify("secret", &nonces, "1234", &AuthParams::default(), NOW),
+            Err(AUTH_REQUIRED_CLOSE_CODE)
+        );
+
+        let params = AuthParams {
+            nonce: "beef",
+            cnonce: "abcd",
+            ts: "0",
+            sig: "00",
+        };
+        assert_eq!(
+            verify("secret", &nonces, "1234", &params, NOW),
+            Err(AUTH_REQUIRED_CLOSE_CODE)
+        );
+    }
+
+    #[test]
+    fn signed_time_is_accepted_once() {
+        let nonces = Nonces::default();
+        let ts = NOW - 60;
+        let sig = sign("secret", 1234, "", "abcd", ts);
+        let params = AuthParams {
+            nonce: "",
+            cnonce: "abcd",
+            ts: &ts.to_string(),
+            sig: &sig,
+        };
+
+        assert_eq!(verify("secret", &nonces, "1234", &params, NOW), Ok(()));
+
+        // replayed upgrade
+        assert_eq!(
+            verify("secret", &nonces, "1234", &params, NOW),
+            Err(AUTH_REQUIRED_CLOSE_CODE)
+        );
+    }
+
+    #[test]
+    fn stale_or_unsynced_time_requires_auth() {
+        let nonces = Nonces::default();
+        for ts in [
+            0,
+            NOW - AUTH_MAX_CLOCK_SKEW_S - 1,
+            NOW + AUTH_MAX_CLOCK_SKEW_S + 1,
+        ] {
+            let sig = sign("secret", 1234, "", "abcd", ts);
+            let params = AuthParams {
+                nonce: "",
+                cnonce: "abcd",
+                ts: &ts.to_string(),
+                sig: &sig,
+            };
+
+            assert_eq!(
+                verify("secret", &nonces, "1234", &params, NOW),
+                Err(AUTH_REQUIRED_CLOSE_CODE)
+            );
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Mock attendance server for end-to-end testing of the firmware
+//! (without real backend). Run with `cargo run-host -p staff-at-mock-server -- --help`
+
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use ota::OtaImage;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, TcpListener, UdpSocket};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+mod auth;
+mod ntp;
+mod ota;
+mod session;
+
+const MDNS_SERVICE: &str = "_stackmat._tcp.local.";
+
+const USAGE: &str = "Usage: staff-at-mock-server [OPTIONS]
+
+Options:
+  --port <PORT>            Port to listen on (default: 8080)
+  --host <IP>              Address advertised over mDNS (default: detected)
+  --no-mdns                Don't advertise server over mDNS
+  --not-added              Respond with `added: false` in DeviceSettings
+  --card-error <CARD=MSG>  Respond to card (card_id or card_uid) with ApiError
+  --unknown-error <MSG>    Respond to every card not listed with ApiError
+  --silent-card <CARD>     Never respond to card (device should time out)
+  --reset-time             Set should_reset_time in every ApiError
+  --ota <FILE>             Stream firmware image to every connected device
+  --ota-version <VERSION>  Version of OTA image (default: mock)
+  --ota-firmware <NAME>    Firmware name sent in StartUpdate (default: STAFF_ATTENDANCE)
+  --ota-force              Allow downgrade
+  --record <FILE>          Append every received packet (json lines) to file
+  --secret <SECRET>        Require devices to authenticate with shared secret
+  --ntp-port <PORT>        Serve host time over sntp (fallback time source)
+  -h, --help               Print help";
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub port: u16,
+    pub host: Option<IpAddr>,
+    pub mdns: bool,
+    pub added: bool,
+
+    /// Card (card_id or card_uid) => ApiError message
+    pub card_errors: HashMap<String, String>,
+    pub unknown_error: Option<String>,
+    pub silent_cards: Vec<String>,
+    pub reset_time: bool,
+
+    pub ota: Option<OtaImage>,
+    pub record: Option<PathBuf>,
+
+    pub secret: Option<String>,
+    pub nonces: auth::Nonces,
+
+    pub ntp_port: Option<u16>,
+
+    /// Scan ids of marked attendance (retries are acked without new record)
+    pub marked_scans: Mutex<HashSet<String>>,
+}
+
+impl Config {
+    fn from_args() -> Result<Self, String> {
+        let mut config = Config {
+            port: 8080,
+            mdns: true,
+            added: true,
+            ..Default::default()
+        };
+
+        let mut ota_path = None;
+        let mut ota_version = String::from("mock");
+        let mut ota_firmware = String::from("STAFF_ATTENDANCE");
+        let mut ota_force = false;
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {arg}"));
+
+            match arg.as_str() {
+                "--port" => config.port = value()?.parse().map_err(|_| "Invalid port")?,
+                "--host" => config.host = Some(value()?.parse().map_err(|_| "Invalid host ip")?),
+                "--no-mdns" => config.mdns = false,
+                "--not-added" => config.added = false,
+                "--card-error" => {
+                    let value = value()?;
+                    let (card, msg) = value
+                        .split_once('=')
+                        .ok_or("--card-error expects CARD=MSG")?;
+                    config.card_errors.insert(card.into(), msg.into());
+                }
+                "--unknown-error" => config.unknown_error = Some(value()?),
+                "--silent-card" => config.silent_cards.push(value()?),
+                "--reset-time" => config.reset_time = true,
+                "--ota" => ota_path = Some(PathBuf::from(value()?)),
+                "--ota-version" => ota_version = value()?,
+                "--ota-firmware" => ota_firmware = value()?,
+                "--ota-force" => ota_force = true,
+                "--record" => config.record = Some(PathBuf::from(value()?)),
+                "--secret" => config.secret = Some(value()?),
+                "--ntp-port" => {
+                    config.ntp_port = Some(value()?.parse().map_err(|_| "Invalid ntp port")?)
+                }
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("Unknown argument: {arg}")),
+            }
+        }
+
+        if let Some(path) = ota_path {
+            let image = OtaImage::load(&path, ota_version, ota_firmware, ota_force)
+                .map_err(|e| format!("Cannot read ota image {path:?}: {e}"))?;
+
+            config.ota = Some(image);
+        }
+
+        Ok(config)
+    }
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let config = match Config::from_args() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(1);
+        }
+    };
+
+    let listener = TcpListener::bind(("0.0.0.0", config.port)).expect("Cannot bind port");
+    let host = config
+        .host
+        .or_else(local_ip)
+        .expect("Cannot detect local ip (use --host)");
+    let ws_url = format!("ws://{host}:{}", config.port);
+    log::info!("Listening on {ws_url}");
+
+    // daemon has to live as long as server (it's unregistered on drop)
+    let _mdns = config.mdns.then(|| advertise(host, config.port, &ws_url));
+    if let Some(ota) = &config.ota {
+        log::info!(
+            "Serving ota {} ({}b crc: {})",
+            ota.version,
+            ota.data.len(),
+            ota.crc
+        );
+    }
+
+    if let Some(port) = config.ntp_port {
+        let socket = UdpSocket::bind(("0.0.0.0", port)).expect("Cannot bind ntp port");
+        log::info!("Serving sntp on port {port}");
+        std::thread::spawn(move || ntp::serve(socket));
+    }
+
+    let record = Arc::new(Mutex::new(()));
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+
+        let config = config.clone();
+        let record = record.clone();
+        std::thread::spawn(move || {
+            let peer = stream.peer_addr().ok();
+            if let Err(e) = session::handle(stream, &config, &record) {
+                log::warn!("[{peer:?}] Connection closed: {e}");
+            }
+        });
+    }
+}
+
+fn advertise(host: IpAddr, port: u16, ws_url: &str) -> ServiceDaemon {
+    let mdns = ServiceDaemon::new().expect("Cannot start mdns daemon");
+    let service = ServiceInfo::new(
+        MDNS_SERVICE,
+        "staff-at-mock",
+        "staff-at-mock.local.",
+        host,
+        port,
+        &[("ws", ws_url)][..],
+    )
+    .expect("Invalid mdns service info");
+
+    mdns.register(service)
+        .expect("Cannot register mdns service");
+    log::info!("Advertising {MDNS_SERVICE} (ws={ws_url})");
+
+    mdns
+}
+
+/// Address of interface used for default route (nothing is sent)
+fn local_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
@@ -0,0 +1,81 @@
+use staff_at_device::sntp;
+use std::net::UdpSocket;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Answers every sntp request with host time (never returns)
+pub fn serve(socket: UdpSocket) {
+    let mut buf = [0; 512];
+    loop {
+        let Ok((n, peer)) = socket.recv_from(&mut buf) else {
+            continue;
+        };
+
+        let now = unix_ms();
+        let response = sntp::response(&buf[..n], now, unix_ms());
+        if let Err(e) = socket.send_to(&response, peer) {
+            log::warn!("[NTP] Cannot respond to {peer}: {e}");
+            continue;
+        }
+
+        log::debug!("[NTP] Time sent to {peer}");
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn query(server: &UdpSocket, sent_ms: u64) -> Result<u64, sntp::SntpError> {
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        client
+            .send_to(&sntp::request(sent_ms), server.local_addr().unwrap())
+            .unwrap();
+
+        let mut buf = [0; sntp::PACKET_LEN];
+        let (n, _) = client.recv_from(&mut buf).unwrap();
+        sntp::parse_response(&buf[..n], sent_ms, sent_ms + 5)
+    }
+
+    fn spawn_server() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.try_clone().unwrap();
+        std::thread::spawn(move || serve(server));
+        socket
+    }
+
+    #[test]
+    fn loopback_time_matches_host() {
+        let server = spawn_server();
+        let epoch_ms = query(&server, 1234).unwrap();
+
+        assert!(epoch_ms.abs_diff(unix_ms()) < 1000);
+    }
+
+    #[test]
+    fn response_to_other_request_is_rejected() {
+        let response = sntp::response(&sntp::request(1), unix_ms(), unix_ms());
+
+        assert_eq!(
+            sntp::parse_response(&response, 2, 10),
+            Err(sntp::SntpError::OriginMismatch)
+        );
+    }
+
+    #[test]
+    fn timestamp_keeps_millis() {
+        let response = sntp::response(&sntp::request(0), 1_700_000_000_123, 1_700_000_000_123);
+
+        assert_eq!(sntp::parse_response(&response, 0, 0), Ok(1_700_000_000_123));
+    }
+}
@@ -0,0 +1,49 @@
+use std::path::Path;
+
+/// Size of binary frame sent per device ack
+pub const CHUNK_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub struct OtaImage {
+    pub version: String,
+    pub firmware: String,
+    pub force: bool,
+    pub data: Vec<u8>,
+    pub crc: u32,
+}
+
+impl OtaImage {
+    pub fn load(
+        path: &Path,
+        version: String,
+        firmware: String,
+        force: bool,
+    ) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        Ok(Self::new(data, version, firmware, force))
+    }
+
+    pub fn new(data: Vec<u8>, version: String, firmware: String, force: bool) -> Self {
+        Self {
+            version,
+            firmware,
+            force,
+            crc: crc32fast::hash(&data),
+            data,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    /// Returns next chunk starting at offset (None if whole image was sent)
+    pub fn chunk(&self, offset: usize) -> Option<&[u8]> {
+        if offset >= self.data.len() {
+            return None;
+        }
+
+        let end = (offset + CHUNK_SIZE).min(self.data.len());
+        Some(&self.data[offset..end])
+    }
+}
@@ -0,0 +1,441 @@
+use crate::Config;
+use staff_at_protocol::auth::AUTH_REQUIRED_CLOSE_CODE;
+use staff_at_protocol::{ApiError, TimerPacket, TimerPacketInner};
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tungstenite::handshake::server::{Request, Response};
+use tungstenite::protocol::frame::{coding::CloseCode, CloseFrame};
+use tungstenite::{Message, WebSocket};
+
+/// Query params sent by device in upgrade request
+#[derive(Debug, Default)]
+struct DeviceInfo {
+    id: String,
+    ver: String,
+    hw: String,
+    firmware: String,
+
+    nonce: String,
+    cnonce: String,
+    ts: String,
+    sig: String,
+}
+
+impl DeviceInfo {
+    fn from_query(query: &str) -> Self {
+        let mut info = Self::default();
+        for (key, value) in query.split('&').filter_map(|kv| kv.split_once('=')) {
+            match key {
+                "id" => info.id = value.into(),
+                "ver" => info.ver = value.into(),
+                "hw" => info.hw = value.into(),
+                "firmware" => info.firmware = value.into(),
+                "nonce" => info.nonce = value.into(),
+                "cnonce" => info.cnonce = value.into(),
+                "ts" => info.ts = value.into(),
+                "sig" => info.sig = value.into(),
+                _ => {}
+            }
+        }
+
+        info
+    }
+}
+
+struct Session<'a> {
+    ws: WebSocket<TcpStream>,
+    config: &'a Config,
+    record: &'a Mutex<()>,
+    device: DeviceInfo,
+
+    /// Offset of next ota chunk (update in progress)
+    ota_offset: Option<usize>,
+}
+
+// upgrade callback error type is defined by tungstenite
+#[allow(clippy::result_large_err)]
+pub fn handle(stream: TcpStream, config: &Config, record: &Mutex<()>) -> Result<(), String> {
+    let mut device = DeviceInfo::default();
+    let ws = tungstenite::accept_hdr(stream, |req: &Request, resp: Response| {
+        device = DeviceInfo::from_query(req.uri().query().unwrap_or_default());
+        Ok(resp)
+    })
+    .map_err(|e| e.to_string())?;
+
+    log::info!(
+        "[{}] Connected (ver: {}, hw: {}, firmware: {})",
+        device.id,
+        device.ver,
+        device.hw,
+        device.firmware
+    );
+
+    let mut session = Session {
+        ws,
+        config,
+        record,
+        device,
+        ota_offset: None,
+    };
+
+    if let Err(code) = session.authenticate() {
+        log::warn!("[{}] Authentication failed ({code})", session.device.id);
+        return session.close(code);
+    }
+
+    session.on_connect()?;
+    loop {
+        match session.ws.read().map_err(|e| e.to_string())? {
+            Message::Text(text) => match serde_json::from_str::<TimerPacket>(&text) {
+                Ok(packet) => session.on_packet(packet)?,
+                Err(e) => log::error!("[{}] Invalid packet ({e}): {text}", session.device.id),
+            },
+            Message::Binary(_) => session.send_next_chunk()?,
+            Message::Close(frame) => {
+                log::info!("[{}] Closed: {frame:?}", session.device.id);
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Session<'_> {
+    /// Without valid nonce (or signed time) device gets challenge for next attempt
+    fn authenticate(&mut self) -> Result<(), u16> {
+        let Some(secret) = &self.config.secret else {
+            return Ok(());
+        };
+
+        let params = crate::auth::AuthParams {
+            nonce: &self.device.nonce,
+            cnonce: &self.device.cnonce,
+            ts: &self.device.ts,
+            sig: &self.device.sig,
+        };
+        let now_s = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        let res = crate::auth::verify(secret, &self.config.nonces, &self.device.id, &params, now_s);
+
+        if res == Err(AUTH_REQUIRED_CLOSE_CODE) {
+            let nonce = self.config.nonces.issue();
+            _ = self.send(None, TimerPacketInner::AuthChallenge { nonce });
+        }
+
+        res
+    }
+
+    fn close(&mut self, code: u16) -> Result<(), String> {
+        let frame = CloseFrame {
+            code: CloseCode::from(code),
+            reason: "".into(),
+        };
+
+        self.ws.close(Some(frame)).map_err(|e| e.to_string())?;
+        while self.ws.read().is_ok() {}
+        Ok(())
+    }
+
+    fn on_connect(&mut self) -> Result<(), String> {
+        if self.config.secret.is_some() {
+            let nonce = self.config.nonces.issue();
+            self.send(None, TimerPacketInner::AuthChallenge { nonce })?;
+        }
+
+        self.send(
+            None,
+            TimerPacketInner::DeviceSettings {
+                added: self.config.added,
+            },
+        )?;
+
+        self.send_epoch_time()?;
+
+        let Some(ota) = &self.config.ota else {
+            return Ok(());
+        };
+
+        if ota.version == self.device.ver {
+            log::info!("[{}] Already running {}", self.device.id, ota.version);
+            return Ok(());
+        }
+
+        log::info!("[{}] Starting update to {}", self.device.id, ota.version);
+        self.ota_offset = Some(0);
+        self.send(
+            None,
+            TimerPacketInner::StartUpdate {
+                version: ota.version.clone(),
+                build_time: 0,
+                size: ota.size(),
+                crc: ota.crc,
+                firmware: ota.firmware.clone(),
+                force: ota.force,
+            },
+        )
+    }
+
+    fn on_packet(&mut self, packet: TimerPacket) -> Result<(), String> {
+        self.record_packet(&packet);
+
+        let id = &self.device.id;
+        match packet.data {
+            TimerPacketInner::CardInfoRequest {
+                card_id,
+                card_uid,
+                attendance_device,
+                scan_epoch,
+                time_valid,
+                scan_id,
+            } => {
+                log::info!(
+                    "[{id}] Card {card_id} (uid: {card_uid:?}, attendance: {attendance_device:?}, epoch: {scan_epoch:?}, scan: {scan_id:?})"
+                );
+
+                if time_valid == Some(false) {
+                    log::warn!(
+                        "[{id}] Scan time of card {card_id} is not trusted (clock not synced)"
+                    );
+                }
+
+                let card_id = card_id.to_string();
+                let matches = |card: &String| Some(card) == card_uid.as_ref() || *card == card_id;
+                if self.config.silent_cards.iter().any(matches) {
+                    log::warn!("[{id}] Ignoring card {card_id}");
+                    return Ok(());
+                }
+
+                let error = self
+                    .config
+                    .card_errors
+                    .iter()
+                    .find(|(card, _)| matches(card))
+                    .map(|(_, msg)| msg)
+                    .or(self.config.unknown_error.as_ref());
+
+                let resp = match error {
+                    Some(error) => {
+                        TimerPacketInner::ApiError(ApiError::new(error, self.config.reset_time))
+                    }
+                    None => {
+                        let mut marked = self.config.marked_scans.lock().unwrap();
+                        if let Some(scan_id) =
+                            scan_id.filter(|scan_id| !marked.insert(scan_id.clone()))
+                        {
+                            log::warn!("[{id}] Duplicate scan {scan_id}, already marked");
+                        }
+
+                        TimerPacketInner::AttendanceMarked
+                    }
+                };
+
+                self.send(packet.tag, resp)?;
+            }
+            TimerPacketInner::EpochTimeRequest => self.send_epoch_time()?,
+            TimerPacketInner::ClockSync {
+                source,
+                offset_ms,
+                drift_ppm,
+            } => {
+                log::info!(
+                    "[{id}] Clock synced from {source:?} (offset: {offset_ms:?}ms, drift: {drift_ppm:?}ppm)"
+                );
+            }
+            TimerPacketInner::Logs { logs } => {
+                for line in logs {
+                    log::info!("[{id}] LOG: {line}");
+                }
+            }
+            TimerPacketInner::ResetReport { resets } => {
+                for reset in resets {
+                    log::warn!(
+                        "[{id}] Reset: {:?} (reason: {:?}, context: {:?}, uptime: {:?}ms)",
+                        reset.cause,
+                        reset.reason,
+                        reset.context,
+                        reset.uptime_ms
+                    );
+                }
+
+                if packet.tag.is_some() {
+                    self.send(packet.tag, TimerPacketInner::ResetReportAck)?;
+                }
+            }
+            TimerPacketInner::Battery { level, voltage } => {
+                log::info!("[{id}] Battery: {level:?}% {voltage:?}V");
+            }
+            TimerPacketInner::UpdateResume {
+                version,
+                size,
+                crc,
+                offset,
+            } => {
+                let Some(ota) = &self.config.ota else {
+                    log::warn!("[{id}] Resume of {version} requested, but no ota image served");
+                    return Ok(());
+                };
+
+                if ota.version != version || ota.size() != size || ota.crc != crc {
+                    log::warn!("[{id}] Resume of unknown update {version} ({size}b crc: {crc})");
+                    return Ok(());
+                }
+
+                log::info!("[{id}] Resuming update from {offset}b");
+                self.ota_offset = Some(offset as usize);
+                self.send(None, TimerPacketInner::UpdateResumeAck { offset })?;
+            }
+            TimerPacketInner::UpdateFailed(failure) => {
+                log::error!("[{id}] Update failed: {failure:?}");
+                self.ota_offset = None;
+            }
+            data => log::info!("[{id}] {data:?}"),
+        }
+
+        Ok(())
+    }
+
+    /// Device acks every ota chunk (and update start) with empty binary frame
+    fn send_next_chunk(&mut self) -> Result<(), String> {
+        let (Some(offset), Some(ota)) = (self.ota_offset, &self.config.ota) else {
+            return Ok(());
+        };
+
+        let Some(chunk) = ota.chunk(offset) else {
+            log::info!("[{}] Whole image sent", self.device.id);
+            self.ota_offset = None;
+            return Ok(());
+        };
+
+        self.ota_offset = Some(offset + chunk.len());
+        self.ws
+            .send(Message::binary(chunk.to_vec()))
+            .map_err(|e| e.to_string())
+    }
+
+    fn send_epoch_time(&mut self) -> Result<(), String> {
+        let current_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_secs();
+
+        self.send(None, TimerPacketInner::EpochTime { current_epoch })
+    }
+
+    fn send(&mut self, tag: Option<u64>, data: TimerPacketInner) -> Result<(), String> {
+        let json = serde_json::to_string(&TimerPacket { tag, data }).map_err(|e| e.to_string())?;
+        self.ws.send(Message::text(json)).map_err(|e| e.to_string())
+    }
+
+    fn record_packet(&self, packet: &TimerPacket) {
+        let Some(path) = &self.config.record else {
+            return;
+        };
+
+        let line = serde_json::json!({ "device": self.device.id, "packet": packet });
+        let _lock = self.record.lock();
+        let res = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{line}"));
+
+        if let Err(e) = res {
+            log::error!("Cannot record packet: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ota::{OtaImage, CHUNK_SIZE};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    fn text_packet(ws: &mut WebSocket<impl std::io::Read + std::io::Write>) -> TimerPacketInner {
+        match ws.read().unwrap() {
+            Message::Text(text) => serde_json::from_str::<TimerPacket>(&text).unwrap().data,
+            msg => panic!("Expected text frame, got {msg:?}"),
+        }
+    }
+
+    #[test]
+    fn streams_ota_image_after_start_update() {
+        let image: Vec<u8> = (0..CHUNK_SIZE + 100).map(|i| i as u8).collect();
+        let ota = OtaImage::new(
+            image.clone(),
+            "v1.2.3".into(),
+            "STAFF_ATTENDANCE".into(),
+            false,
+        );
+        let crc = ota.crc;
+
+        let config = Arc::new(Config {
+            added: true,
+            ota: Some(ota),
+            ..Default::default()
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle(stream, &config, &Mutex::new(()))
+        });
+
+        let url = format!("ws://{addr}/?id=1234&ver=v1.0.0&hw=v3&firmware=STAFF_ATTENDANCE");
+        let (mut ws, _) = tungstenite::connect(url).unwrap();
+
+        assert!(matches!(
+            text_packet(&mut ws),
+            TimerPacketInner::DeviceSettings { added: true }
+        ));
+        assert!(matches!(
+            text_packet(&mut ws),
+            TimerPacketInner::EpochTime { .. }
+        ));
+
+        match text_packet(&mut ws) {
+            TimerPacketInner::StartUpdate {
+                version,
+                size,
+                crc: start_crc,
+                firmware,
+                force,
+                ..
+            } => {
+                assert_eq!(version, "v1.2.3");
+                assert_eq!(size, image.len() as u32);
+                assert_eq!(start_crc, crc);
+                assert_eq!(firmware, "STAFF_ATTENDANCE");
+                assert!(!force);
+            }
+            data => panic!("Expected StartUpdate, got {data:?}"),
+        }
+
+        // device acks update start and every chunk with empty binary frame
+        let mut received = Vec::new();
+        while received.len() < image.len() {
+            ws.send(Message::binary(Vec::new())).unwrap();
+            match ws.read().unwrap() {
+                Message::Binary(chunk) => {
+                    assert!(chunk.len() <= CHUNK_SIZE);
+                    received.extend_from_slice(&chunk);
+                }
+                msg => panic!("Expected binary frame, got {msg:?}"),
+            }
+        }
+
+        assert_eq!(received, image);
+        assert_eq!(crc32fast::hash(&received), crc);
+
+        ws.close(None).unwrap();
+        while ws.read().is_ok() {}
+        assert_eq!(server.join().unwrap(), Ok(()));
+    }
+}
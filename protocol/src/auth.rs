@@ -0,0 +1,36 @@
+//! Device authentication. Server hands out single use nonce in
+//! [`crate::TimerPacketInner::AuthChallenge`], device answers it in next
+//! upgrade request: `&nonce=<nonce>&cnonce=<random>&ts=<unix s>&sig=<hex HMAC-SHA256>`
+//! (signed [`auth_message`], key is device secret provisioned in setup).
+//!
+//! Nonce is kept only in RAM, so after restart device signs just its synced
+//! time (empty nonce). Server accepts it if `ts` is within
+//! [`AUTH_MAX_CLOCK_SKEW_S`] and `cnonce` wasn't used before.
+
+use alloc::string::String;
+
+/// Upgrade without (or with expired) nonce, challenge is sent before close
+pub const AUTH_REQUIRED_CLOSE_CODE: u16 = 4001;
+
+/// Signature didn't match (wrong device secret)
+pub const AUTH_FAILED_CLOSE_CODE: u16 = 4003;
+
+/// Max difference of device `ts` and server time (without nonce)
+pub const AUTH_MAX_CLOCK_SKEW_S: u64 = 5 * 60;
+
+/// Longest nonce accepted by device
+pub const MAX_NONCE_LEN: usize = 64;
+
+/// Nonce is put into upgrade query as is, so only `[A-Za-z0-9_-]` is allowed
+pub fn is_valid_nonce(nonce: &str) -> bool {
+    !nonce.is_empty()
+        && nonce.len() <= MAX_NONCE_LEN
+        && nonce
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+/// `ts` is 0 if device time isn't synced
+pub fn auth_message(device_id: u32, nonce: &str, cnonce: &str, ts: u64) -> String {
+    alloc::format!("{device_id}:{nonce}:{cnonce}:{ts}")
+}
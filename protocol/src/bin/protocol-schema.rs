@@ -0,0 +1,6 @@
+//! Prints JSON Schema of protocol. Regenerate committed schema with:
+//! `cargo run-host -p staff-at-protocol --features schema > protocol/schema.json`
+
+fn main() {
+    print!("{}", staff_at_protocol::schema_json());
+}
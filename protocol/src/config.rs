@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+
+// Defaults used until server pushes its own config
+pub const SLEEP_AFTER_MS: u64 = 60000 * 15;
+pub const DEEPER_SLEEP_AFTER_MS: u64 = 60000 * 30;
+pub const BATTERY_SEND_INTERVAL_MS: u64 = 60000;
+pub const LOG_SEND_INTERVAL_MS: u64 = 5000;
+pub const RFID_RETRY_INIT_MS: u64 = 1500;
+pub const HEALTH_CHECK_WINDOW_MS: u64 = 180000;
+pub const SCAN_DEBOUNCE_MS: u64 = 5000;
+pub const WS_BACKOFF_BASE_MS: u64 = 1000;
+pub const WS_BACKOFF_MAX_MS: u64 = 60000;
+pub const WS_BACKOFF_RESET_AFTER_MS: u64 = 30000;
+pub const HEARTBEAT_INTERVAL_MS: u64 = 10000;
+pub const HEARTBEAT_MAX_MISSED: u32 = 3;
+
+/// Runtime configuration pushed by server (missing fields fallback to defaults)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct DeviceConfig {
+    pub sleep_after_ms: u64,
+    pub deeper_sleep_after_ms: u64,
+    pub battery_send_interval_ms: u64,
+    pub log_send_interval_ms: u64,
+    pub rfid_retry_init_ms: u64,
+
+    /// Time for freshly updated firmware to pass health checks (counted
+    /// from network being up)
+    pub health_check_window_ms: u64,
+
+    /// Repeated scans of same card within this window are ignored (0 disables)
+    pub scan_debounce_ms: u64,
+
+    /// Ws reconnect delay doubles from base up to max (randomized to 50-100% of it)
+    pub ws_backoff_base_ms: u64,
+    pub ws_backoff_max_ms: u64,
+
+    /// Connection lasting at least this long resets reconnect delay
+    pub ws_backoff_reset_after_ms: u64,
+
+    /// Ws ping interval, connection is dropped after `heartbeat_max_missed`
+    /// pings in a row without pong
+    pub heartbeat_interval_ms: u64,
+    pub heartbeat_max_missed: u32,
+}
+
+impl DeviceConfig {
+    pub const DEFAULT: Self = Self {
+        sleep_after_ms: SLEEP_AFTER_MS,
+        deeper_sleep_after_ms: DEEPER_SLEEP_AFTER_MS,
+        battery_send_interval_ms: BATTERY_SEND_INTERVAL_MS,
+        log_send_interval_ms: LOG_SEND_INTERVAL_MS,
+        rfid_retry_init_ms: RFID_RETRY_INIT_MS,
+        health_check_window_ms: HEALTH_CHECK_WINDOW_MS,
+        scan_debounce_ms: SCAN_DEBOUNCE_MS,
+        ws_backoff_base_ms: WS_BACKOFF_BASE_MS,
+        ws_backoff_max_ms: WS_BACKOFF_MAX_MS,
+        ws_backoff_reset_after_ms: WS_BACKOFF_RESET_AFTER_MS,
+        heartbeat_interval_ms: HEARTBEAT_INTERVAL_MS,
+        heartbeat_max_missed: HEARTBEAT_MAX_MISSED,
+    };
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+        if self.sleep_after_ms < 10000 || self.sleep_after_ms > DAY_MS {
+            return Err("sleep_after_ms out of range (10s - 24h)");
+        }
+
+        if self.deeper_sleep_after_ms <= self.sleep_after_ms || self.deeper_sleep_after_ms > DAY_MS
+        {
+            return Err("deeper_sleep_after_ms must be greater than sleep_after_ms (max 24h)");
+        }
+
+        if self.battery_send_interval_ms < 1000 || self.battery_send_interval_ms > DAY_MS {
+            return Err("battery_send_interval_ms out of range (1s - 24h)");
+        }
+
+        if self.log_send_interval_ms < 500 || self.log_send_interval_ms > 600000 {
+            return Err("log_send_interval_ms out of range (500ms - 10min)");
+        }
+
+        if self.rfid_retry_init_ms < 100 || self.rfid_retry_init_ms > 60000 {
+            return Err("rfid_retry_init_ms out of range (100ms - 60s)");
+        }
+
+        if self.health_check_window_ms < 30000 || self.health_check_window_ms > 1800000 {
+            return Err("health_check_window_ms out of range (30s - 30min)");
+        }
+
+        if self.scan_debounce_ms > 60000 {
+            return Err("scan_debounce_ms out of range (0 - 60s)");
+        }
+
+        if self.ws_backoff_base_ms < 100 || self.ws_backoff_base_ms > 60000 {
+            return Err("ws_backoff_base_ms out of range (100ms - 60s)");
+        }
+
+        if self.ws_backoff_max_ms < self.ws_backoff_base_ms || self.ws_backoff_max_ms > 1800000 {
+            return Err("ws_backoff_max_ms must be at least ws_backoff_base_ms (max 30min)");
+        }
+
+        if self.ws_backoff_reset_after_ms < 1000 || self.ws_backoff_reset_after_ms > 1800000 {
+            return Err("ws_backoff_reset_after_ms out of range (1s - 30min)");
+        }
+
+        if self.heartbeat_interval_ms < 1000 || self.heartbeat_interval_ms > 300000 {
+            return Err("heartbeat_interval_ms out of range (1s - 5min)");
+        }
+
+        if self.heartbeat_max_missed == 0 || self.heartbeat_max_missed > 20 {
+            return Err("heartbeat_max_missed out of range (1 - 20)");
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
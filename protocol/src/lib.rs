@@ -0,0 +1,254 @@
+//! Websocket protocol shared between firmware and server. Every packet is
+//! [`TimerPacket`] serialized as json (enum variants are snake_case).
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+pub mod auth;
+pub mod config;
+pub mod reset;
+pub mod status;
+pub mod telemetry;
+pub mod update;
+
+pub use config::DeviceConfig;
+pub use reset::{ResetReason, ResetRecord};
+pub use status::{
+    DeviceStatus, HealthCheck, RollbackEvent, TimeSource, TlsFailure, TlsFailureKind,
+    TlsVerifyMode, WsCloseInfo, WsCloseKind, WsEndpoint,
+};
+pub use telemetry::{ResetCause, RfidStatus, SleepState, Telemetry};
+pub use update::{UpdateFailure, UpdateFailureKind};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TimerPacket {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<u64>,
+    pub data: TimerPacketInner,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TimerPacketInner {
+    StartUpdate {
+        version: String,
+        build_time: u64, // NOT USED (build time from image metadata is checked instead)
+        size: u32,
+        crc: u32,
+        firmware: String,
+
+        /// Skip downgrade check
+        #[serde(default)]
+        force: bool,
+    },
+    ApiError(ApiError),
+    CardInfoRequest {
+        card_id: u64,
+
+        /// Full uid (4, 7 or 10 bytes) as uppercase hex
+        #[serde(skip_serializing_if = "Option::is_none")]
+        card_uid: Option<String>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        attendance_device: Option<bool>,
+
+        /// Epoch of scan by device clock
+        #[serde(skip_serializing_if = "Option::is_none")]
+        scan_epoch: Option<u64>,
+
+        /// False if device clock wasn't synced when card was scanned
+        /// (`scan_epoch` is then just uptime based and can't be trusted)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        time_valid: Option<bool>,
+
+        /// Same for every retry and replay of one scan (see [`scan_id`])
+        #[serde(skip_serializing_if = "Option::is_none")]
+        scan_id: Option<String>,
+    },
+    AttendanceMarked,
+    DeviceSettings {
+        added: bool,
+    },
+    Logs {
+        logs: Vec<String>,
+    },
+    Battery {
+        level: Option<f64>,
+        voltage: Option<f64>,
+    },
+    Add {
+        firmware: String,
+    },
+    EpochTime {
+        current_epoch: u64,
+    },
+
+    /// Device asks for [`TimerPacketInner::EpochTime`]
+    EpochTimeRequest,
+
+    /// Sent by device after every time sync
+    ClockSync {
+        source: TimeSource,
+
+        /// Server time minus device time at sync (None on first sync)
+        offset_ms: Option<i64>,
+
+        /// Estimated drift of device clock (positive = device clock is slow)
+        drift_ppm: Option<i32>,
+    },
+    OfflineQueue {
+        pending: u32,
+        capacity: u32,
+        dropped: u32,
+        overflow_policy: QueueOverflowPolicy,
+    },
+    DeviceStatus(DeviceStatus),
+
+    /// Sent periodically by device
+    Telemetry(Telemetry),
+
+    /// Resets not reported yet (newest first), sent after connecting
+    /// as tagged request
+    ResetReport {
+        resets: Vec<ResetRecord>,
+    },
+
+    /// Server stored [`TimerPacketInner::ResetReport`] (same tag), device
+    /// doesn't send these resets again
+    ResetReportAck,
+    DeviceConfig(DeviceConfig),
+    UpdateResume {
+        version: String,
+        size: u32,
+        crc: u32,
+        offset: u32,
+    },
+    UpdateResumeAck {
+        offset: u32,
+    },
+    UpdateFailed(UpdateFailure),
+
+    /// Nonce for next upgrade request (see [`auth`])
+    AuthChallenge {
+        nonce: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum QueueOverflowPolicy {
+    DropOldest,
+    DropNewest,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AttendanceMarkedPacket {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ResetReportAckPacket {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ApiError {
+    /// Human readable message (only for display)
+    pub error: String,
+    pub should_reset_time: bool,
+
+    /// Never sent, errors received from server are always [`ApiErrorKind::Server`]
+    #[serde(skip)]
+    pub kind: ApiErrorKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum ApiErrorKind {
+    /// Error sent by server (request was processed)
+    #[default]
+    Server,
+
+    /// Server didn't respond at all (request may not reached it)
+    Timeout,
+
+    /// Server responded with unexpected packet
+    WrongResponse,
+}
+
+impl ApiError {
+    pub fn new(error: &str, should_reset_time: bool) -> Self {
+        Self {
+            error: error.into(),
+            should_reset_time,
+            kind: ApiErrorKind::Server,
+        }
+    }
+
+    pub fn timeout() -> Self {
+        Self {
+            error: "Communication timeout!".into(),
+            should_reset_time: false,
+            kind: ApiErrorKind::Timeout,
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        self.kind == ApiErrorKind::Timeout
+    }
+}
+
+/// Id of single physical scan: device id, monotonic scan counter of device
+/// and epoch of scan. Server uses it to drop duplicate requests
+pub fn scan_id(device_id: u32, counter: u64, epoch: u64) -> String {
+    alloc::format!("{device_id}-{counter}-{epoch}")
+}
+
+pub trait FromPacket: Sized {
+    fn from_packet(packet: TimerPacket) -> Result<Self, ApiError>;
+}
+
+impl FromPacket for AttendanceMarkedPacket {
+    fn from_packet(packet: TimerPacket) -> Result<Self, ApiError> {
+        match packet.data {
+            TimerPacketInner::AttendanceMarked => Ok(AttendanceMarkedPacket {}),
+            TimerPacketInner::ApiError(api_error) => Err(api_error),
+            _ => Err(ApiError {
+                error: alloc::format!("Wrong response type! ({:?})", packet),
+                should_reset_time: false,
+                kind: ApiErrorKind::WrongResponse,
+            }),
+        }
+    }
+}
+
+impl FromPacket for ResetReportAckPacket {
+    fn from_packet(packet: TimerPacket) -> Result<Self, ApiError> {
+        match packet.data {
+            TimerPacketInner::ResetReportAck => Ok(ResetReportAckPacket {}),
+            TimerPacketInner::ApiError(api_error) => Err(api_error),
+            _ => Err(ApiError {
+                error: alloc::format!("Wrong response type! ({:?})", packet),
+                should_reset_time: false,
+                kind: ApiErrorKind::WrongResponse,
+            }),
+        }
+    }
+}
+
+/// JSON Schema of [`TimerPacket`] (committed as `schema.json`)
+#[cfg(feature = "schema")]
+pub fn schema() -> schemars::Schema {
+    schemars::schema_for!(TimerPacket)
+}
+
+/// Pretty printed [`schema`] (same format as `schema.json`)
+#[cfg(feature = "schema")]
+pub fn schema_json() -> String {
+    let mut json = serde_json::to_string_pretty(&schema()).expect("schema is always serializable");
+    json.push('\n');
+    json
+}
@@ -0,0 +1,54 @@
+use crate::telemetry::ResetCause;
+use alloc::string::String;
+use serde::{Deserialize, Serialize};
+
+/// Why firmware restarted itself (recorded before reset)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ResetReason {
+    /// Panic or cpu exception (backtrace is in logs after boot)
+    Panic = 1,
+    OtaComplete = 2,
+
+    /// Updated firmware failed health checks
+    HealthRollback = 3,
+
+    /// Scan woke device from deeper sleep (clock can't be restored)
+    DeeperSleepWake = 4,
+    WifiManagerFailed = 5,
+}
+
+impl ResetReason {
+    /// Code stored in reset journal
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Self::Panic),
+            2 => Some(Self::OtaComplete),
+            3 => Some(Self::HealthRollback),
+            4 => Some(Self::DeeperSleepWake),
+            5 => Some(Self::WifiManagerFailed),
+            _ => None,
+        }
+    }
+}
+
+/// Single boot from reset journal
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ResetRecord {
+    /// Hardware cause read on boot
+    pub cause: ResetCause,
+
+    /// None if reset wasn't requested by firmware (brownout, watchdog...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<ResetReason>,
+
+    /// Reason details (for example version of installed update)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+
+    /// Uptime of previous boot when reset was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime_ms: Option<u64>,
+}
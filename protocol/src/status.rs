@@ -0,0 +1,181 @@
+use alloc::{string::String, vec::Vec};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TlsVerifyMode {
+    #[default]
+    None,
+    Pin,
+    Ca,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DeviceStatus {
+    /// Close frame received in previous session
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_close: Option<WsCloseInfo>,
+
+    pub tls_verify: TlsVerifyMode,
+
+    /// Last tls failure since previous successful connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_tls_error: Option<TlsFailure>,
+
+    /// Updated firmware that failed health checks (and was rolled back)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_rollback: Option<RollbackEvent>,
+
+    /// Scans ignored by debounce window (since boot)
+    pub suppressed_scans: u32,
+
+    /// Lost connections (since boot)
+    pub ws_reconnects: u32,
+
+    /// Failed connection attempts before current connection
+    pub ws_retries: u32,
+
+    /// Last reconnect delay (0 if connected on first try)
+    pub ws_backoff_ms: u64,
+
+    /// Sessions dropped because of missed heartbeats (since boot)
+    pub heartbeat_timeouts: u32,
+
+    /// Last measured ws ping round trip
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ws_rtt_ms: Option<u32>,
+
+    /// Upgrades rejected because of wrong signature (since boot)
+    pub auth_failures: u32,
+
+    /// Server endpoint of current connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ws_endpoint: Option<WsEndpoint>,
+
+    /// Source of last time sync (None if time wasn't synced since boot)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_source: Option<TimeSource>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TimeSource {
+    /// `EpochTime` packet from attendance server
+    Server,
+    Ntp,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WsEndpoint {
+    /// Position in configured list (mdns is after configured urls)
+    pub index: u32,
+    pub url: String,
+
+    /// Url was discovered over mdns
+    pub mdns: bool,
+}
+
+impl DeviceStatus {
+    /// Clears events that should be reported only once
+    pub fn clear_session_events(&mut self) {
+        self.last_close = None;
+        self.last_tls_error = None;
+        self.last_rollback = None;
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RollbackEvent {
+    pub version: String,
+    pub failed_checks: Vec<HealthCheck>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum HealthCheck {
+    RfidInit = 0,
+    WsUpgrade = 1,
+    DeviceSettings = 2,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TlsFailure {
+    pub kind: TlsFailureKind,
+    pub detail: String,
+}
+
+impl TlsFailure {
+    pub fn new(kind: TlsFailureKind, detail: &str) -> Self {
+        Self {
+            kind,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TlsFailureKind {
+    BadConfig,
+    HandshakeFailed,
+    CertificateInvalid,
+    PinMismatch,
+    SignatureInvalid,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WsCloseInfo {
+    pub code: u16,
+    pub reason: String,
+    pub kind: WsCloseKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum WsCloseKind {
+    Normal,
+    GoingAway,
+    DeviceRejected,
+    ServerError,
+    ServiceRestart,
+    TryAgainLater,
+    AuthRequired,
+    AuthFailed,
+    Other,
+}
+
+impl WsCloseKind {
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            1000 => Self::Normal,
+            1001 => Self::GoingAway,
+            1008 => Self::DeviceRejected,
+            1011 => Self::ServerError,
+            1012 => Self::ServiceRestart,
+            1013 => Self::TryAgainLater,
+            crate::auth::AUTH_REQUIRED_CLOSE_CODE => Self::AuthRequired,
+            crate::auth::AUTH_FAILED_CLOSE_CODE => Self::AuthFailed,
+            _ => Self::Other,
+        }
+    }
+
+    /// Code echoed back in close handshake. Codes that mustn't be sent
+    /// (1005 no status, 1006 abnormal close, 1015 tls failure) or aren't
+    /// assigned are replaced with 1000 (RFC 6455 7.4)
+    pub fn echo_code(code: u16) -> u16 {
+        match code {
+            1000..=1003 | 1007..=1014 | 3000..=4999 => code,
+            _ => 1000,
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+/// Periodic device health snapshot (counters are since boot)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Telemetry {
+    pub uptime_ms: u64,
+
+    /// Heap usage in bytes (peak is highest usage since boot)
+    pub heap_used: u32,
+    pub heap_free: u32,
+    pub heap_peak: u32,
+
+    /// Signal of connected access point (dBm)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<i8>,
+
+    pub ws_reconnects: u32,
+
+    /// Hardware cause of last reset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset_cause: Option<ResetCause>,
+
+    /// Scans delivered or queued (suppressed duplicates aren't counted)
+    pub scans: u32,
+    pub suppressed_scans: u32,
+
+    /// Requests without response in time (every retry is counted)
+    pub failed_requests: u32,
+
+    /// Average time to response of recent requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_latency_ms: Option<u32>,
+
+    pub rfid_status: RfidStatus,
+    pub sleep_state: SleepState,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ResetCause {
+    PowerOn,
+
+    /// Restart requested by firmware (also after panic)
+    Software,
+    DeepSleep,
+    Watchdog,
+    Brownout,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum RfidStatus {
+    #[default]
+    Initializing,
+    Ok,
+
+    /// Reader didn't respond to init (retried until it does)
+    InitFailed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum SleepState {
+    #[default]
+    Awake,
+    Sleep,
+    DeeperSleep,
+}
@@ -0,0 +1,32 @@
+use alloc::string::String;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UpdateFailure {
+    pub kind: UpdateFailureKind,
+    pub detail: String,
+}
+
+impl UpdateFailure {
+    pub fn new(kind: UpdateFailureKind, detail: &str) -> Self {
+        Self {
+            kind,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateFailureKind {
+    NoSession,
+    FirmwareMismatch,
+    HardwareMismatch,
+    Downgrade,
+    InvalidMetadata,
+    SignatureMissing,
+    SignatureInvalid,
+    VerifyFailed,
+}
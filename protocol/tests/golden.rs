@@ -0,0 +1,473 @@
+//! Golden json of every packet variant. If one of these fails, wire format
+//! changed and server has to be updated too.
+
+use staff_at_protocol::*;
+
+fn assert_golden(data: TimerPacketInner, golden: &str) {
+    let packet = TimerPacket { tag: Some(1), data };
+
+    let json = serde_json::to_string(&packet).unwrap();
+    assert_eq!(json, golden);
+
+    let parsed: TimerPacket = serde_json::from_str(golden).unwrap();
+    assert_eq!(serde_json::to_string(&parsed).unwrap(), golden);
+}
+
+#[test]
+fn start_update() {
+    assert_golden(
+        TimerPacketInner::StartUpdate {
+            version: "v1.2.3".into(),
+            build_time: 1700000000,
+            size: 123456,
+            crc: 3735928559,
+            firmware: "STAFF".into(),
+            force: false,
+        },
+        r#"{"tag":1,"data":{"start_update":{"version":"v1.2.3","build_time":1700000000,"size":123456,"crc":3735928559,"firmware":"STAFF","force":false}}}"#,
+    );
+}
+
+#[test]
+fn start_update_without_force() {
+    let packet: TimerPacket = serde_json::from_str(
+        r#"{"data":{"start_update":{"version":"v1","build_time":0,"size":1,"crc":2,"firmware":"STAFF"}}}"#,
+    )
+    .unwrap();
+
+    assert!(matches!(
+        packet.data,
+        TimerPacketInner::StartUpdate { force: false, .. }
+    ));
+}
+
+#[test]
+fn api_error() {
+    assert_golden(
+        TimerPacketInner::ApiError(ApiError::new("Card not found", true)),
+        r#"{"tag":1,"data":{"api_error":{"error":"Card not found","should_reset_time":true}}}"#,
+    );
+}
+
+#[test]
+fn card_info_request() {
+    assert_golden(
+        TimerPacketInner::CardInfoRequest {
+            card_id: 3004425529,
+            card_uid: Some("04A1B2C3D4E5F6".into()),
+            attendance_device: Some(true),
+            scan_epoch: Some(1700000000),
+            time_valid: Some(true),
+            scan_id: Some(scan_id(123456, 42, 1700000000)),
+        },
+        r#"{"tag":1,"data":{"card_info_request":{"card_id":3004425529,"card_uid":"04A1B2C3D4E5F6","attendance_device":true,"scan_epoch":1700000000,"time_valid":true,"scan_id":"123456-42-1700000000"}}}"#,
+    );
+}
+
+#[test]
+fn card_info_request_minimal() {
+    assert_golden(
+        TimerPacketInner::CardInfoRequest {
+            card_id: 3004425529,
+            card_uid: None,
+            attendance_device: None,
+            scan_epoch: None,
+            time_valid: None,
+            scan_id: None,
+        },
+        r#"{"tag":1,"data":{"card_info_request":{"card_id":3004425529}}}"#,
+    );
+}
+
+#[test]
+fn attendance_marked() {
+    assert_golden(
+        TimerPacketInner::AttendanceMarked,
+        r#"{"tag":1,"data":"attendance_marked"}"#,
+    );
+}
+
+#[test]
+fn device_settings() {
+    assert_golden(
+        TimerPacketInner::DeviceSettings { added: true },
+        r#"{"tag":1,"data":{"device_settings":{"added":true}}}"#,
+    );
+}
+
+#[test]
+fn logs() {
+    assert_golden(
+        TimerPacketInner::Logs {
+            logs: vec!["INFO - boot".into(), "WARN - low battery".into()],
+        },
+        r#"{"tag":1,"data":{"logs":{"logs":["INFO - boot","WARN - low battery"]}}}"#,
+    );
+}
+
+#[test]
+fn battery() {
+    assert_golden(
+        TimerPacketInner::Battery {
+            level: Some(87.0),
+            voltage: Some(3.95),
+        },
+        r#"{"tag":1,"data":{"battery":{"level":87.0,"voltage":3.95}}}"#,
+    );
+
+    assert_golden(
+        TimerPacketInner::Battery {
+            level: None,
+            voltage: None,
+        },
+        r#"{"tag":1,"data":{"battery":{"level":null,"voltage":null}}}"#,
+    );
+}
+
+#[test]
+fn add() {
+    assert_golden(
+        TimerPacketInner::Add {
+            firmware: "STAFF".into(),
+        },
+        r#"{"tag":1,"data":{"add":{"firmware":"STAFF"}}}"#,
+    );
+}
+
+#[test]
+fn epoch_time() {
+    assert_golden(
+        TimerPacketInner::EpochTime {
+            current_epoch: 1700000000,
+        },
+        r#"{"tag":1,"data":{"epoch_time":{"current_epoch":1700000000}}}"#,
+    );
+}
+
+#[test]
+fn epoch_time_request() {
+    assert_golden(
+        TimerPacketInner::EpochTimeRequest,
+        r#"{"tag":1,"data":"epoch_time_request"}"#,
+    );
+}
+
+#[test]
+fn clock_sync() {
+    assert_golden(
+        TimerPacketInner::ClockSync {
+            source: TimeSource::Ntp,
+            offset_ms: Some(-1500),
+            drift_ppm: Some(42),
+        },
+        r#"{"tag":1,"data":{"clock_sync":{"source":"ntp","offset_ms":-1500,"drift_ppm":42}}}"#,
+    );
+}
+
+#[test]
+fn offline_queue() {
+    assert_golden(
+        TimerPacketInner::OfflineQueue {
+            pending: 3,
+            capacity: 32,
+            dropped: 1,
+            overflow_policy: QueueOverflowPolicy::DropOldest,
+        },
+        r#"{"tag":1,"data":{"offline_queue":{"pending":3,"capacity":32,"dropped":1,"overflow_policy":"drop_oldest"}}}"#,
+    );
+}
+
+#[test]
+fn device_status() {
+    assert_golden(
+        TimerPacketInner::DeviceStatus(DeviceStatus {
+            last_close: Some(WsCloseInfo {
+                code: 1008,
+                reason: "unknown device".into(),
+                kind: WsCloseKind::from_code(1008),
+            }),
+            tls_verify: TlsVerifyMode::Pin,
+            last_tls_error: Some(TlsFailure::new(
+                TlsFailureKind::PinMismatch,
+                "InvalidCertificate",
+            )),
+            last_rollback: Some(RollbackEvent {
+                version: "v1.2.3".into(),
+                failed_checks: vec![HealthCheck::WsUpgrade, HealthCheck::DeviceSettings],
+            }),
+            suppressed_scans: 2,
+            ws_reconnects: 3,
+            ws_retries: 4,
+            ws_backoff_ms: 7310,
+            heartbeat_timeouts: 1,
+            ws_rtt_ms: Some(42),
+            auth_failures: 1,
+            ws_endpoint: Some(WsEndpoint {
+                index: 1,
+                url: "wss://standby.example.com".into(),
+                mdns: false,
+            }),
+            time_source: Some(TimeSource::Server),
+        }),
+        r#"{"tag":1,"data":{"device_status":{"last_close":{"code":1008,"reason":"unknown device","kind":"device_rejected"},"tls_verify":"pin","last_tls_error":{"kind":"pin_mismatch","detail":"InvalidCertificate"},"last_rollback":{"version":"v1.2.3","failed_checks":["ws_upgrade","device_settings"]},"suppressed_scans":2,"ws_reconnects":3,"ws_retries":4,"ws_backoff_ms":7310,"heartbeat_timeouts":1,"ws_rtt_ms":42,"auth_failures":1,"ws_endpoint":{"index":1,"url":"wss://standby.example.com","mdns":false},"time_source":"server"}}}"#,
+    );
+}
+
+#[test]
+fn device_status_minimal() {
+    assert_golden(
+        TimerPacketInner::DeviceStatus(DeviceStatus::default()),
+        r#"{"tag":1,"data":{"device_status":{"tls_verify":"none","suppressed_scans":0,"ws_reconnects":0,"ws_retries":0,"ws_backoff_ms":0,"heartbeat_timeouts":0,"auth_failures":0}}}"#,
+    );
+}
+
+#[test]
+fn telemetry() {
+    assert_golden(
+        TimerPacketInner::Telemetry(Telemetry {
+            uptime_ms: 3_600_000,
+            heap_used: 48_000,
+            heap_free: 80_000,
+            heap_peak: 62_000,
+            rssi: Some(-61),
+            ws_reconnects: 2,
+            reset_cause: Some(ResetCause::Brownout),
+            scans: 120,
+            suppressed_scans: 4,
+            failed_requests: 1,
+            avg_latency_ms: Some(85),
+            rfid_status: RfidStatus::Ok,
+            sleep_state: SleepState::Awake,
+        }),
+        r#"{"tag":1,"data":{"telemetry":{"uptime_ms":3600000,"heap_used":48000,"heap_free":80000,"heap_peak":62000,"rssi":-61,"ws_reconnects":2,"reset_cause":"brownout","scans":120,"suppressed_scans":4,"failed_requests":1,"avg_latency_ms":85,"rfid_status":"ok","sleep_state":"awake"}}}"#,
+    );
+}
+
+#[test]
+fn telemetry_minimal() {
+    assert_golden(
+        TimerPacketInner::Telemetry(Telemetry::default()),
+        r#"{"tag":1,"data":{"telemetry":{"uptime_ms":0,"heap_used":0,"heap_free":0,"heap_peak":0,"ws_reconnects":0,"scans":0,"suppressed_scans":0,"failed_requests":0,"rfid_status":"initializing","sleep_state":"awake"}}}"#,
+    );
+}
+
+#[test]
+fn reset_report() {
+    assert_golden(
+        TimerPacketInner::ResetReport {
+            resets: vec![
+                ResetRecord {
+                    cause: ResetCause::Software,
+                    reason: Some(ResetReason::OtaComplete),
+                    context: Some("v1.2.3".into()),
+                    uptime_ms: Some(93_000),
+                },
+                ResetRecord {
+                    cause: ResetCause::Brownout,
+                    reason: None,
+                    context: None,
+                    uptime_ms: None,
+                },
+            ],
+        },
+        r#"{"tag":1,"data":{"reset_report":{"resets":[{"cause":"software","reason":"ota_complete","context":"v1.2.3","uptime_ms":93000},{"cause":"brownout"}]}}}"#,
+    );
+}
+
+#[test]
+fn reset_report_ack() {
+    assert_golden(
+        TimerPacketInner::ResetReportAck,
+        r#"{"tag":1,"data":"reset_report_ack"}"#,
+    );
+}
+
+#[test]
+fn reset_reason_codes() {
+    for reason in [
+        ResetReason::Panic,
+        ResetReason::OtaComplete,
+        ResetReason::HealthRollback,
+        ResetReason::DeeperSleepWake,
+        ResetReason::WifiManagerFailed,
+    ] {
+        assert_eq!(ResetReason::from_code(reason as u8), Some(reason));
+    }
+
+    assert_eq!(ResetReason::from_code(0), None);
+    assert_eq!(ResetReason::from_code(0xff), None);
+}
+
+#[test]
+fn device_config() {
+    assert_golden(
+        TimerPacketInner::DeviceConfig(DeviceConfig::DEFAULT),
+        r#"{"tag":1,"data":{"device_config":{"sleep_after_ms":900000,"deeper_sleep_after_ms":1800000,"battery_send_interval_ms":60000,"log_send_interval_ms":5000,"rfid_retry_init_ms":1500,"health_check_window_ms":180000,"scan_debounce_ms":5000,"ws_backoff_base_ms":1000,"ws_backoff_max_ms":60000,"ws_backoff_reset_after_ms":30000,"heartbeat_interval_ms":10000,"heartbeat_max_missed":3}}}"#,
+    );
+}
+
+#[test]
+fn device_config_partial() {
+    let packet: TimerPacket =
+        serde_json::from_str(r#"{"data":{"device_config":{"sleep_after_ms":60000}}}"#).unwrap();
+
+    let TimerPacketInner::DeviceConfig(config) = packet.data else {
+        panic!("wrong variant");
+    };
+
+    assert_eq!(config.sleep_after_ms, 60000);
+    assert_eq!(
+        config.scan_debounce_ms,
+        DeviceConfig::DEFAULT.scan_debounce_ms
+    );
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn update_resume() {
+    assert_golden(
+        TimerPacketInner::UpdateResume {
+            version: "v1.2.3".into(),
+            size: 123456,
+            crc: 42,
+            offset: 65536,
+        },
+        r#"{"tag":1,"data":{"update_resume":{"version":"v1.2.3","size":123456,"crc":42,"offset":65536}}}"#,
+    );
+}
+
+#[test]
+fn update_resume_ack() {
+    assert_golden(
+        TimerPacketInner::UpdateResumeAck { offset: 65536 },
+        r#"{"tag":1,"data":{"update_resume_ack":{"offset":65536}}}"#,
+    );
+}
+
+#[test]
+fn update_failed() {
+    assert_golden(
+        TimerPacketInner::UpdateFailed(UpdateFailure::new(
+            UpdateFailureKind::SignatureInvalid,
+            "signature mismatch",
+        )),
+        r#"{"tag":1,"data":{"update_failed":{"kind":"signature_invalid","detail":"signature mismatch"}}}"#,
+    );
+}
+
+#[test]
+fn auth_challenge() {
+    assert_golden(
+        TimerPacketInner::AuthChallenge {
+            nonce: "3f1c2a9b".into(),
+        },
+        r#"{"tag":1,"data":{"auth_challenge":{"nonce":"3f1c2a9b"}}}"#,
+    );
+
+    assert_eq!(
+        WsCloseKind::from_code(auth::AUTH_FAILED_CLOSE_CODE),
+        WsCloseKind::AuthFailed
+    );
+    assert_eq!(
+        auth::auth_message(42, "3f1c2a9b", "a1b2", 1_700_000_000),
+        "42:3f1c2a9b:a1b2:1700000000"
+    );
+}
+
+#[test]
+fn nonce_validation() {
+    assert!(auth::is_valid_nonce("3f1c2a9b"));
+    assert!(auth::is_valid_nonce("Ab_9-z"));
+
+    for nonce in [
+        "",
+        "a&b=c",
+        "a#b",
+        "a b",
+        "a\r\nHost: evil",
+        "ä",
+        &"a".repeat(65),
+    ] {
+        assert!(!auth::is_valid_nonce(nonce), "{nonce:?}");
+    }
+}
+
+#[test]
+fn close_echo_code() {
+    for code in [
+        1000,
+        1001,
+        1008,
+        1011,
+        1013,
+        auth::AUTH_REQUIRED_CLOSE_CODE,
+        3000,
+    ] {
+        assert_eq!(WsCloseKind::echo_code(code), code);
+    }
+
+    // reserved (and absent) codes can't be sent back
+    for code in [0, 999, 1004, 1005, 1006, 1015, 2000, 5000] {
+        assert_eq!(WsCloseKind::echo_code(code), 1000);
+    }
+}
+
+#[test]
+fn untagged_packet() {
+    let packet = TimerPacket {
+        tag: None,
+        data: TimerPacketInner::AttendanceMarked,
+    };
+
+    assert_eq!(
+        serde_json::to_string(&packet).unwrap(),
+        r#"{"data":"attendance_marked"}"#
+    );
+}
+
+#[test]
+fn from_packet() {
+    let ok = AttendanceMarkedPacket::from_packet(TimerPacket {
+        tag: Some(1),
+        data: TimerPacketInner::AttendanceMarked,
+    });
+    assert!(ok.is_ok());
+
+    let err = AttendanceMarkedPacket::from_packet(TimerPacket {
+        tag: Some(1),
+        data: TimerPacketInner::ApiError(ApiError::new("Card not found", false)),
+    });
+    assert_eq!(err.unwrap_err().kind, ApiErrorKind::Server);
+
+    let err = AttendanceMarkedPacket::from_packet(TimerPacket {
+        tag: Some(1),
+        data: TimerPacketInner::Add {
+            firmware: "STAFF_ATTENDANCE".into(),
+        },
+    });
+    assert_eq!(err.unwrap_err().kind, ApiErrorKind::WrongResponse);
+}
+
+#[test]
+fn server_error_is_never_timeout() {
+    let timeout = ApiError::timeout();
+    let json = serde_json::to_string(&TimerPacketInner::ApiError(timeout.clone())).unwrap();
+    let TimerPacketInner::ApiError(received) = serde_json::from_str(&json).unwrap() else {
+        panic!("wrong packet");
+    };
+
+    // same text from server doesn't make request retryable
+    assert_eq!(received.error, timeout.error);
+    assert!(timeout.is_timeout());
+    assert!(!received.is_timeout());
+}
+
+#[cfg(feature = "schema")]
+#[test]
+fn schema_is_up_to_date() {
+    let committed = include_str!("../schema.json");
+    assert!(
+        committed == schema_json(),
+        "schema.json is outdated, regenerate it with `cargo run-host -p staff-at-protocol --features schema > protocol/schema.json`"
+    );
+}
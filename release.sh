@@ -23,14 +23,20 @@ while [ -z "$RELEASE_VERSION" ]; do
     read RELEASE_VERSION
 done
 
-source ~/export-esp.sh
-RELEASE_BUILD="$RELEASE_VERSION" cargo build -r
+if [ -z "$OTA_SIGNING_KEY" ]; then
+    echo "OTA_SIGNING_KEY not set (devices reject unsigned images)"
+    exit 1
+fi
 
+source ~/export-esp.sh
 EPOCH=$(date +%s)
+RELEASE_BUILD="$RELEASE_VERSION" BUILD_TIME="$EPOCH" cargo build -r
 
 espflash save-image --chip esp32c3 ./target/riscv32imc-unknown-none-elf/release/staff-at-firmware "/tmp/fkm-build/v3_STAFF_ATTENDANCE_${RELEASE_VERSION}.bin"
 ./append_metadata.sh "/tmp/fkm-build/v3_STAFF_ATTENDANCE_${RELEASE_VERSION}.bin" "$RELEASE_VERSION" "STAFF_ATTENDANCE" "v3" "$EPOCH"
 
+./sign_image.sh "/tmp/fkm-build/v3_STAFF_ATTENDANCE_${RELEASE_VERSION}.bin" "$OTA_SIGNING_KEY"
+
 cd $SCRIPT_DIR
 echo "Version: $RELEASE_VERSION"
 
@@ -0,0 +1,35 @@
+use crate::CardUid;
+
+const CACHE_SIZE: usize = 8;
+
+/// Remembers when recently seen cards were scanned, to ignore card held on reader
+pub struct ScanDebouncer {
+    recent: heapless::Vec<(CardUid, u64), CACHE_SIZE>,
+}
+
+impl ScanDebouncer {
+    pub fn new() -> Self {
+        Self {
+            recent: heapless::Vec::new(),
+        }
+    }
+
+    /// Returns true if card was already seen within window (window is extended
+    /// on every scan, so card held on reader is suppressed until removed)
+    pub fn is_duplicate(&mut self, uid: &CardUid, now_ms: u64, window_ms: u64) -> bool {
+        self.recent
+            .retain(|(_, seen)| now_ms.saturating_sub(*seen) < window_ms);
+
+        if let Some((_, seen)) = self.recent.iter_mut().find(|(u, _)| u == uid) {
+            *seen = now_ms;
+            return true;
+        }
+
+        if self.recent.is_full() {
+            self.recent.remove(0);
+        }
+
+        _ = self.recent.push((*uid, now_ms));
+        false
+    }
+}
@@ -0,0 +1,16 @@
+//! Hardware independent card scanning logic (sleep, debounce and offline
+//! queueing) driven through [`CardReader`]
+
+#![no_std]
+
+extern crate alloc;
+
+mod debounce;
+mod machine;
+mod reader;
+
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+pub use machine::{init_reader, ScanAction, Scanner, ScannerConfig, SleepLevel};
+pub use reader::{uid_len_from_atqa, CardReader, CardUid, MAX_UID_LEN};
@@ -0,0 +1,331 @@
+use crate::debounce::ScanDebouncer;
+use crate::{CardReader, CardUid};
+use core::future::Future;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScannerConfig {
+    pub sleep_after_ms: u64,
+    pub deeper_sleep_after_ms: u64,
+
+    /// Repeated scans of same card within this window are ignored (0 disables)
+    pub debounce_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SleepLevel {
+    Awake,
+    Sleep,
+    DeeperSleep,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanAction {
+    /// Nothing to do (no card)
+    Idle,
+    EnterSleep,
+    EnterDeeperSleep,
+
+    /// Same card scanned again within debounce window
+    Suppressed(CardUid),
+
+    /// Send scan to server right away
+    Deliver(CardUid),
+
+    /// Store scan in offline queue (server unreachable or older scans waiting)
+    Queue(CardUid),
+
+    /// Scan woke device from deeper sleep, queue it and restart
+    QueueAndRestart(CardUid),
+}
+
+/// Scan state machine. Time is passed in by caller (ms since boot)
+pub struct Scanner {
+    last_scan_ms: u64,
+    sleep: SleepLevel,
+    debouncer: ScanDebouncer,
+}
+
+impl Scanner {
+    pub fn new(now_ms: u64) -> Self {
+        Self {
+            last_scan_ms: now_ms,
+            sleep: SleepLevel::Awake,
+            debouncer: ScanDebouncer::new(),
+        }
+    }
+
+    pub fn sleep_level(&self) -> SleepLevel {
+        self.sleep
+    }
+
+    /// Single iteration of scan loop. `can_deliver` should be false when server
+    /// is not connected or offline queue is not empty (to keep scans in order)
+    pub async fn poll<R: CardReader>(
+        &mut self,
+        reader: &mut R,
+        config: &ScannerConfig,
+        now_ms: u64,
+        can_deliver: bool,
+    ) -> ScanAction {
+        let idle_ms = now_ms.saturating_sub(self.last_scan_ms);
+        if idle_ms >= config.sleep_after_ms && self.sleep == SleepLevel::Awake {
+            self.sleep = SleepLevel::Sleep;
+            return ScanAction::EnterSleep;
+        }
+
+        if idle_ms >= config.deeper_sleep_after_ms && self.sleep != SleepLevel::DeeperSleep {
+            self.sleep = SleepLevel::DeeperSleep;
+            return ScanAction::EnterDeeperSleep;
+        }
+
+        if !reader.is_card_present().await {
+            return ScanAction::Idle;
+        }
+
+        let Some(uid) = reader.read_uid().await else {
+            return ScanAction::Idle;
+        };
+
+        let duplicate = self
+            .debouncer
+            .is_duplicate(&uid, now_ms, config.debounce_ms);
+        reader.halt().await;
+
+        // card left lying on reader mustn't keep device awake
+        if duplicate {
+            return ScanAction::Suppressed(uid);
+        }
+
+        self.last_scan_ms = now_ms;
+
+        match self.sleep {
+            SleepLevel::DeeperSleep => return ScanAction::QueueAndRestart(uid),
+            SleepLevel::Sleep => {
+                log::info!("Sleep done!");
+                self.sleep = SleepLevel::Awake;
+            }
+            SleepLevel::Awake => {}
+        }
+
+        if can_deliver {
+            ScanAction::Deliver(uid)
+        } else {
+            ScanAction::Queue(uid)
+        }
+    }
+}
+
+/// Initializes reader, `on_failure` (called with number of failed tries)
+/// should wait before next try. Returns number of failed tries
+pub async fn init_reader<R, F, Fut>(reader: &mut R, mut on_failure: F) -> u32
+where
+    R: CardReader,
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut failures = 0;
+    while !reader.init().await {
+        failures += 1;
+        log::error!("MFRC522 init failed! Try to power cycle to module! Retrying...");
+        on_failure(failures).await;
+    }
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockReader;
+    use embassy_futures::block_on;
+
+    const CONFIG: ScannerConfig = ScannerConfig {
+        sleep_after_ms: 1000,
+        deeper_sleep_after_ms: 5000,
+        debounce_ms: 300,
+    };
+
+    fn uid(b: u8) -> CardUid {
+        CardUid::new(&[b, 0x01, 0x02, 0x03], b as u64)
+    }
+
+    #[test]
+    fn scan_is_delivered_and_halted() {
+        let mut reader = MockReader::new([None, Some(uid(1))]);
+        let mut scanner = Scanner::new(0);
+
+        let actions = [
+            block_on(scanner.poll(&mut reader, &CONFIG, 10, true)),
+            block_on(scanner.poll(&mut reader, &CONFIG, 20, true)),
+        ];
+
+        assert_eq!(actions, [ScanAction::Idle, ScanAction::Deliver(uid(1))]);
+        assert_eq!(reader.halts, 1);
+    }
+
+    #[test]
+    fn scan_is_queued_when_offline() {
+        let mut reader = MockReader::new([Some(uid(1))]);
+        let mut scanner = Scanner::new(0);
+
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 10, false));
+        assert_eq!(action, ScanAction::Queue(uid(1)));
+    }
+
+    #[test]
+    fn held_card_is_suppressed_until_removed() {
+        let mut reader = MockReader::new([Some(uid(1)), Some(uid(1)), Some(uid(1)), None]);
+        let mut scanner = Scanner::new(0);
+
+        let mut actions = alloc::vec::Vec::new();
+        for now in [10, 200, 400, 500] {
+            actions.push(block_on(scanner.poll(&mut reader, &CONFIG, now, true)));
+        }
+
+        // window is extended on every scan
+        assert_eq!(
+            actions,
+            [
+                ScanAction::Deliver(uid(1)),
+                ScanAction::Suppressed(uid(1)),
+                ScanAction::Suppressed(uid(1)),
+                ScanAction::Idle
+            ]
+        );
+
+        reader.push(Some(uid(1)));
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 1000, true));
+        assert_eq!(action, ScanAction::Deliver(uid(1)));
+    }
+
+    #[test]
+    fn held_card_does_not_prevent_sleep() {
+        let mut reader = MockReader::new([Some(uid(1)); 6]);
+        let mut scanner = Scanner::new(0);
+
+        assert_eq!(
+            block_on(scanner.poll(&mut reader, &CONFIG, 10, true)),
+            ScanAction::Deliver(uid(1))
+        );
+        for now in [200, 400, 600, 800, 1000] {
+            let action = block_on(scanner.poll(&mut reader, &CONFIG, now, true));
+            assert_eq!(action, ScanAction::Suppressed(uid(1)));
+        }
+
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 1010, true));
+        assert_eq!(action, ScanAction::EnterSleep);
+    }
+
+    #[test]
+    fn different_cards_are_not_suppressed() {
+        let mut reader = MockReader::new([Some(uid(1)), Some(uid(2))]);
+        let mut scanner = Scanner::new(0);
+
+        block_on(scanner.poll(&mut reader, &CONFIG, 10, true));
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 20, true));
+        assert_eq!(action, ScanAction::Deliver(uid(2)));
+    }
+
+    #[test]
+    fn long_uids_are_compared_fully() {
+        let a = CardUid::new(&[1, 2, 3, 4, 5, 6, 7], 0);
+        let b = CardUid::new(&[1, 2, 3, 4, 5, 6, 8], 0);
+        let mut reader = MockReader::new([Some(a), Some(b)]);
+        let mut scanner = Scanner::new(0);
+
+        block_on(scanner.poll(&mut reader, &CONFIG, 10, true));
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 20, true));
+        assert_eq!(action, ScanAction::Deliver(b));
+        assert_eq!(b.to_hex(), "01020304050608");
+    }
+
+    #[test]
+    fn double_size_uid_is_read_whole() {
+        let card = CardUid::new(&[0x04, 0x52, 0x1A, 0x7B, 0x2C, 0x59, 0x80], 0x2C7B1A52);
+        let mut reader = MockReader::new([Some(card)]);
+        let mut scanner = Scanner::new(0);
+
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 10, true));
+        assert_eq!(action, ScanAction::Deliver(card));
+        if let ScanAction::Deliver(uid) = action {
+            assert_eq!(uid.to_hex(), "04521A7B2C5980");
+        }
+
+        // single presence poll, card isn't woken up again between cascade levels
+        assert_eq!(reader.polls, 1);
+        assert_eq!(reader.halts, 1);
+    }
+
+    #[test]
+    fn sleep_and_wake() {
+        let mut reader = MockReader::new([]);
+        let mut scanner = Scanner::new(0);
+
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 1000, true));
+        assert_eq!(action, ScanAction::EnterSleep);
+        assert_eq!(scanner.sleep_level(), SleepLevel::Sleep);
+
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 1010, true));
+        assert_eq!(action, ScanAction::Idle);
+
+        reader.push(Some(uid(1)));
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 2000, true));
+        assert_eq!(action, ScanAction::Deliver(uid(1)));
+        assert_eq!(scanner.sleep_level(), SleepLevel::Awake);
+    }
+
+    #[test]
+    fn deeper_sleep_scan_is_queued_for_restart() {
+        let mut reader = MockReader::new([]);
+        let mut scanner = Scanner::new(0);
+
+        block_on(scanner.poll(&mut reader, &CONFIG, 1000, true));
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 5000, true));
+        assert_eq!(action, ScanAction::EnterDeeperSleep);
+
+        reader.push(Some(uid(1)));
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 6000, true));
+        assert_eq!(action, ScanAction::QueueAndRestart(uid(1)));
+        assert_eq!(scanner.sleep_level(), SleepLevel::DeeperSleep);
+    }
+
+    #[test]
+    fn failed_read_is_ignored() {
+        let mut reader = MockReader::new([Some(uid(1))]);
+        reader.fail_reads = 1;
+        let mut scanner = Scanner::new(0);
+
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 10, true));
+        assert_eq!(action, ScanAction::Idle);
+        assert_eq!(reader.halts, 0);
+    }
+
+    #[test]
+    fn init_retries_until_reader_responds() {
+        let mut reader = MockReader::new([Some(uid(1))]);
+        reader.fail_inits = 2;
+
+        let mut waits = alloc::vec::Vec::new();
+        let failures = block_on(init_reader(&mut reader, |failures| {
+            waits.push(failures);
+            core::future::ready(())
+        }));
+
+        assert_eq!(failures, 2);
+        assert_eq!(waits, [1, 2]);
+
+        let mut scanner = Scanner::new(0);
+        let action = block_on(scanner.poll(&mut reader, &CONFIG, 10, true));
+        assert_eq!(action, ScanAction::Deliver(uid(1)));
+    }
+
+    #[test]
+    fn responding_reader_is_initialized_right_away() {
+        let mut reader = MockReader::new([]);
+        let failures = block_on(init_reader(&mut reader, |_| async {
+            panic!("reader responded");
+        }));
+
+        assert_eq!(failures, 0);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{uid_len_from_atqa, CardReader, CardUid};
+use alloc::collections::VecDeque;
+
+/// Scripted reader. Every presence poll consumes next script entry
+/// (`None` means no card in field), empty script means no card.
+/// Like MFRC522 reader, uid length is taken from ATQA of last poll
+pub struct MockReader {
+    script: VecDeque<Option<CardUid>>,
+    selected: Option<CardUid>,
+    atqa: Option<[u8; 2]>,
+
+    /// Number of presence polls (REQA)
+    pub polls: usize,
+
+    /// Number of `init` calls that should fail
+    pub fail_inits: usize,
+
+    /// Number of `read_uid` calls that should fail
+    pub fail_reads: usize,
+    pub halts: usize,
+}
+
+impl MockReader {
+    pub fn new(script: impl IntoIterator<Item = Option<CardUid>>) -> Self {
+        Self {
+            script: script.into_iter().collect(),
+            selected: None,
+            atqa: None,
+            polls: 0,
+            fail_inits: 0,
+            fail_reads: 0,
+            halts: 0,
+        }
+    }
+
+    pub fn push(&mut self, card: Option<CardUid>) {
+        self.script.push_back(card);
+    }
+}
+
+impl CardReader for MockReader {
+    async fn init(&mut self) -> bool {
+        if self.fail_inits > 0 {
+            self.fail_inits -= 1;
+            return false;
+        }
+
+        true
+    }
+
+    async fn is_card_present(&mut self) -> bool {
+        self.polls += 1;
+        self.selected = self.script.pop_front().flatten();
+        self.atqa = self.selected.map(|uid| atqa(uid.bytes().len()));
+        self.selected.is_some()
+    }
+
+    async fn read_uid(&mut self) -> Option<CardUid> {
+        if self.fail_reads > 0 {
+            self.fail_reads -= 1;
+            return None;
+        }
+
+        // whole uid is selected at once, ATQA is valid only until then
+        let len = uid_len_from_atqa(self.atqa.take()?)?;
+        self.selected.filter(|uid| uid.bytes().len() == len)
+    }
+
+    async fn halt(&mut self) {
+        self.selected = None;
+        self.halts += 1;
+    }
+}
+
+/// ATQA of card with uid of given length (mifare classic / ultralight style)
+fn atqa(uid_len: usize) -> [u8; 2] {
+    let size_bits = match uid_len {
+        4 => 0x00,
+        7 => 0x40,
+        _ => 0x80,
+    };
+
+    [size_bits | 0x04, 0x00]
+}
@@ -0,0 +1,85 @@
+use alloc::string::String;
+
+pub const MAX_UID_LEN: usize = 10;
+
+/// Card reader driver (MFRC522 on device, scripted mock in tests)
+#[allow(async_fn_in_trait)]
+pub trait CardReader {
+    /// Returns true if reader was initialized (and responds)
+    async fn init(&mut self) -> bool;
+
+    /// Returns true if new card entered the field
+    async fn is_card_present(&mut self) -> bool;
+
+    /// Selects present card and returns its full uid
+    async fn read_uid(&mut self) -> Option<CardUid>;
+
+    /// Halts selected card (it won't be reported again until it leaves the field)
+    async fn halt(&mut self);
+}
+
+/// Uid length announced by card in ATQA (bits 7-8 of its first byte), None
+/// for reserved value
+pub fn uid_len_from_atqa(atqa: [u8; 2]) -> Option<usize> {
+    match atqa[0] >> 6 {
+        0 => Some(4),
+        1 => Some(7),
+        2 => Some(10),
+        _ => None,
+    }
+}
+
+/// Full card uid (4, 7 or 10 bytes)
+#[derive(Debug, Clone, Copy)]
+pub struct CardUid {
+    bytes: [u8; MAX_UID_LEN],
+    len: usize,
+
+    /// Numeric id (same as sent before 7/10 byte support, truncated for 10 byte uids)
+    pub card_id: u64,
+}
+
+impl CardUid {
+    pub fn new(uid: &[u8], card_id: u64) -> Self {
+        let len = uid.len().min(MAX_UID_LEN);
+        let mut bytes = [0; MAX_UID_LEN];
+        bytes[..len].copy_from_slice(&uid[..len]);
+
+        Self {
+            bytes,
+            len,
+            card_id,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.bytes()
+            .iter()
+            .map(|b| alloc::format!("{b:02X}"))
+            .collect()
+    }
+}
+
+impl PartialEq for CardUid {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes() == other.bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uid_len_is_read_from_atqa() {
+        // mifare classic, ultralight and triple size uid
+        assert_eq!(uid_len_from_atqa([0x04, 0x00]), Some(4));
+        assert_eq!(uid_len_from_atqa([0x44, 0x00]), Some(7));
+        assert_eq!(uid_len_from_atqa([0x84, 0x00]), Some(10));
+        assert_eq!(uid_len_from_atqa([0xC4, 0x00]), None);
+    }
+}
@@ -0,0 +1,50 @@
+#!/bin/bash
+set -e
+
+# Appends ed25519 signature trailer to (already metadata appended) image:
+# 64-byte signature of SHA-256 of whole file, followed by "SIG1" magic.
+#
+# Key generation:
+#   openssl genpkey -algorithm ed25519 -out ota_key.pem
+# Public key for OTA_PUBLIC_KEY (.env):
+#   openssl pkey -in ota_key.pem -pubout -outform der | tail -c 32 | xxd -p -c 64
+
+usage() {
+    echo "Usage: $0 <binary_file> <private_key.pem>"
+    exit 1
+}
+
+if [ $# -ne 2 ]; then
+    usage
+fi
+
+binary_file="$1"
+private_key="$2"
+
+if [ ! -f "$binary_file" ]; then
+    echo "Error: File '$binary_file' does not exist"
+    exit 1
+fi
+
+if [ ! -f "$private_key" ]; then
+    echo "Error: Key '$private_key' does not exist"
+    exit 1
+fi
+
+digest_file=$(mktemp)
+sig_file=$(mktemp)
+
+openssl dgst -sha256 -binary "$binary_file" > "$digest_file"
+openssl pkeyutl -sign -inkey "$private_key" -rawin -in "$digest_file" -out "$sig_file"
+
+if [ "$(stat -c %s "$sig_file")" -ne 64 ]; then
+    echo "Error: Unexpected signature size"
+    rm "$digest_file" "$sig_file"
+    exit 1
+fi
+
+cat "$sig_file" >> "$binary_file"
+printf "SIG1" >> "$binary_file"
+echo "Successfully signed '$binary_file'"
+
+rm "$digest_file" "$sig_file"
@@ -0,0 +1,59 @@
+use embassy_time::Instant;
+use staff_at_device::battery::BatteryAdc;
+
+/// Simulated battery adc. Voltage is linearly interpolated between
+/// points of curve (`<ms>:<mV>`, time since simulator start)
+pub struct SimAdc {
+    curve: Vec<(u64, f64)>,
+    start: Instant,
+}
+
+impl SimAdc {
+    /// Parses curve like `0:4150,600000:3300`
+    pub fn parse(curve: &str) -> Result<Self, String> {
+        let mut points = curve
+            .split(',')
+            .map(|point| {
+                let (ms, mv) = point.trim().split_once(':')?;
+                Some((ms.parse().ok()?, mv.parse().ok()?))
+            })
+            .collect::<Option<Vec<(u64, f64)>>>()
+            .filter(|points| !points.is_empty())
+            .ok_or(format!("Invalid battery curve: {curve}"))?;
+
+        points.sort_by_key(|p| p.0);
+        Ok(Self {
+            curve: points,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn voltage_mv(&self) -> f64 {
+        let now = (Instant::now() - self.start).as_millis();
+        let (first, last) = (self.curve[0], self.curve[self.curve.len() - 1]);
+        if now <= first.0 {
+            return first.1;
+        }
+
+        for window in self.curve.windows(2) {
+            let ((t1, v1), (t2, v2)) = (window[0], window[1]);
+            if now <= t2 {
+                return v1 + (v2 - v1) * (now - t1) as f64 / (t2 - t1) as f64;
+            }
+        }
+
+        last.1
+    }
+
+    /// Raw reading that device would get for current voltage
+    /// (inverse of `battery_curve::calculate`)
+    pub fn read_oneshot(&self) -> u16 {
+        ((self.voltage_mv() - 276.754) / 1.18323).max(0.0) as u16
+    }
+}
+
+impl BatteryAdc for SimAdc {
+    async fn read_raw(&mut self) -> u16 {
+        self.read_oneshot()
+    }
+}
@@ -0,0 +1,14 @@
+use crate::adc::SimAdc;
+use crate::state::SimState;
+use staff_at_device::battery::battery_loop;
+
+/// Shared battery loop (same as `battery_read_task` on device) reading from simulated adc
+#[embassy_executor::task]
+pub async fn battery_read_task(mut adc: SimAdc, state: SimState) {
+    let base_freq = 2.0;
+    let sample_freq = 1000.0;
+    let sensitivity = 0.5;
+    let mut smoother = dyn_smooth::DynamicSmootherEcoF32::new(base_freq, sample_freq, sensitivity);
+
+    battery_loop(&*state, &mut adc, |read| smoother.tick(read)).await
+}
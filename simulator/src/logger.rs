@@ -0,0 +1,48 @@
+use crate::state::SimState;
+
+/// Prints logs (env_logger) and collects them for `logger_task` like `FkmLogger`
+pub struct SimLogger {
+    inner: env_logger::Logger,
+}
+
+impl SimLogger {
+    pub fn set_logger() {
+        let inner =
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+                .build();
+
+        log::set_max_level(inner.filter());
+        _ = log::set_boxed_logger(Box::new(SimLogger { inner }));
+    }
+}
+
+impl log::Log for SimLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.inner.matches(record) {
+            return;
+        }
+
+        self.inner.log(record);
+
+        // simulator messages are not sent to server
+        let msg = record.args().to_string();
+        if msg.starts_with("[SIM]") {
+            return;
+        }
+
+        staff_at_device::logs::push(format!("{} - {msg}", record.level()));
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+#[embassy_executor::task]
+pub async fn logger_task(state: SimState) {
+    staff_at_device::logs::send_loop(&*state).await
+}
@@ -0,0 +1,242 @@
+//! Host simulator running firmware tasks (scanning, battery, logs, ws) against
+//! simulated peripherals: scripted card reader, battery voltage curve and
+//! in-memory nvs. Task loops (connection, scanning, battery, logs) and queue
+//! and time logic are shared with device (`staff_at_device`). Network goes
+//! through TAP interface (`simulator/tap.sh`).
+//!
+//! Usage (with mock server on host side of tap):
+//! `cargo run-host -p staff-at-mock-server -- --host 192.168.69.100`
+//! `cargo run-host -p staff-at-simulator -- --cards cards.txt --run-for 60000`
+
+use adc::SimAdc;
+use embassy_executor::Spawner;
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+use embassy_net_tuntap::TunTapDevice;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Instant, Timer};
+use rand_core::RngCore;
+use reader::ScriptedReader;
+use staff_at_protocol::DeviceConfig;
+use state::{SimState, SimStateInner};
+use static_cell::StaticCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use ws_framer::{WsUrl, WsUrlOwned};
+
+mod adc;
+mod battery;
+mod logger;
+mod nvs;
+mod queue;
+mod reader;
+mod rfid;
+mod state;
+mod time;
+mod ws;
+
+const USAGE: &str = "Usage: staff-at-simulator [OPTIONS]
+
+Options:
+  --tap <IFACE>            TAP interface (default: tap0)
+  --ip <IP/PREFIX>         Static address (default: 192.168.69.2/24, `dhcp` for dhcp)
+  --gateway <IP>           Gateway for static address (default: 192.168.69.100)
+  --ws-url <URL>           Server url (default: ws://192.168.69.100:8080)
+  --id <ID>                Device id sent in upgrade request (default: 1)
+  --cards <FILE>           Card script (`<at_ms> <uid hex> [hold_ms]` per line)
+  --battery <CURVE>        Battery voltage curve `<ms>:<mV>,...` (default: 0:4000)
+  --rfid-init-failures <N> Number of failed reader inits before it works
+  --config <JSON>          Device config used instead of defaults/saved one
+  --ntp <IP:PORT>          Fallback sntp server (mock server `--ntp-port`)
+  --nvs <FILE>             Persist nvs to file (keeps queue/config across restarts)
+  --run-for <MS>           Exit (code 0) after given time
+  -h, --help               Print help
+
+Simulated device restart exits with code 3.";
+
+struct Config {
+    tap: String,
+    net: embassy_net::Config,
+    ws_url: String,
+    device_id: u32,
+    cards: Vec<reader::CardEvent>,
+    adc: SimAdc,
+    rfid_init_failures: usize,
+    device_config: Option<DeviceConfig>,
+    ntp: Option<IpEndpoint>,
+    nvs: Option<PathBuf>,
+    run_for: Option<u64>,
+}
+
+impl Config {
+    fn from_args() -> Result<Self, String> {
+        let mut config = Config {
+            tap: String::from("tap0"),
+            net: embassy_net::Config::dhcpv4(Default::default()),
+            ws_url: String::from("ws://192.168.69.100:8080"),
+            device_id: 1,
+            cards: Vec::new(),
+            adc: SimAdc::parse("0:4000")?,
+            rfid_init_failures: 0,
+            device_config: None,
+            ntp: None,
+            nvs: None,
+            run_for: None,
+        };
+
+        let mut ip = String::from("192.168.69.2/24");
+        let mut gateway = Ipv4Address::new(192, 168, 69, 100);
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {arg}"));
+
+            match arg.as_str() {
+                "--tap" => config.tap = value()?,
+                "--ip" => ip = value()?,
+                "--gateway" => gateway = value()?.parse().map_err(|_| "Invalid gateway")?,
+                "--ws-url" => config.ws_url = value()?,
+                "--id" => config.device_id = value()?.parse().map_err(|_| "Invalid id")?,
+                "--cards" => {
+                    let path = value()?;
+                    let script = std::fs::read_to_string(&path)
+                        .map_err(|e| format!("Cannot read card script {path}: {e}"))?;
+                    config.cards = reader::parse_script(&script)?;
+                }
+                "--battery" => config.adc = SimAdc::parse(&value()?)?,
+                "--rfid-init-failures" => {
+                    config.rfid_init_failures = value()?.parse().map_err(|_| "Invalid count")?
+                }
+                "--config" => {
+                    let device_config: DeviceConfig = serde_json::from_str(&value()?)
+                        .map_err(|e| format!("Invalid device config: {e}"))?;
+                    device_config
+                        .validate()
+                        .map_err(|e| format!("Invalid device config: {e}"))?;
+                    config.device_config = Some(device_config);
+                }
+                "--ntp" => {
+                    let addr: std::net::SocketAddrV4 = value()?
+                        .parse()
+                        .map_err(|_| "Invalid ntp server (expected IP:PORT)")?;
+                    config.ntp = Some(IpEndpoint::new(
+                        IpAddress::Ipv4(addr.ip().octets().into()),
+                        addr.port(),
+                    ));
+                }
+                "--nvs" => config.nvs = Some(PathBuf::from(value()?)),
+                "--run-for" => config.run_for = Some(value()?.parse().map_err(|_| "Invalid time")?),
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("Unknown argument: {arg}")),
+            }
+        }
+
+        if ip == "dhcp" {
+            config.net = embassy_net::Config::dhcpv4(Default::default());
+        } else {
+            let address: Ipv4Cidr = ip.parse().map_err(|_| "Invalid ip (expected IP/PREFIX)")?;
+            config.net = embassy_net::Config::ipv4_static(StaticConfigV4 {
+                address,
+                gateway: Some(gateway),
+                dns_servers: heapless::Vec::new(),
+            });
+        }
+
+        WsUrl::from_str(&config.ws_url).ok_or("Invalid ws url")?;
+        Ok(config)
+    }
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    logger::SimLogger::set_logger();
+
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(1);
+        }
+    };
+
+    let device = TunTapDevice::new(&config.tap).unwrap_or_else(|e| {
+        eprintln!(
+            "Cannot open tap {} ({e}), create it with simulator/tap.sh",
+            config.tap
+        );
+        std::process::exit(1);
+    });
+
+    static RESOURCES: StaticCell<StackResources<5>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(
+        device,
+        config.net,
+        RESOURCES.init(StackResources::new()),
+        rand_core::OsRng.next_u64(),
+    );
+    spawner.must_spawn(net_task(runner));
+
+    let state: SimState = Rc::new(
+        SimStateInner::new(
+            config.device_id,
+            nvs::MemNvs::new(config.nvs),
+            config.device_config,
+        )
+        .await,
+    );
+
+    let ws_connect_signal = Rc::new(Signal::new());
+    let ws_sleep_sig = Rc::new(Signal::<CriticalSectionRawMutex, bool>::new());
+    let ws_url = WsUrlOwned::new(&WsUrl::from_str(&config.ws_url).expect("validated in args"));
+
+    spawner.must_spawn(rfid::rfid_task(
+        ScriptedReader::new(config.cards, config.rfid_init_failures),
+        state.clone(),
+    ));
+    spawner.must_spawn(battery::battery_read_task(config.adc, state.clone()));
+    spawner.must_spawn(queue::queue_replay_task(
+        state.clone(),
+        ws_connect_signal.clone(),
+    ));
+    spawner.must_spawn(ws::ws_task(
+        stack,
+        ws_url,
+        state.clone(),
+        ws_sleep_sig.clone(),
+        ws_connect_signal,
+    ));
+    if let Some(ntp) = config.ntp {
+        spawner.must_spawn(time::sntp_task(stack, ntp, state.clone()));
+    }
+    spawner.must_spawn(logger::logger_task(state.clone()));
+
+    let start = Instant::now();
+    let mut last_sleep = false;
+    loop {
+        Timer::after_millis(100).await;
+        if state.sleep.get() != last_sleep {
+            last_sleep = state.sleep.get();
+            if last_sleep {
+                log::info!("[SIM] Radio stopped");
+            } else {
+                log::info!("[SIM] Radio restarted");
+            }
+            ws_sleep_sig.signal(last_sleep);
+        }
+
+        if config
+            .run_for
+            .is_some_and(|run_for| (Instant::now() - start).as_millis() >= run_for)
+        {
+            log::info!("[SIM] Finished");
+            std::process::exit(0);
+        }
+    }
+}
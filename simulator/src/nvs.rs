@@ -0,0 +1,93 @@
+use serde::{de::DeserializeOwned, Serialize};
+use staff_at_device::KvStore;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// In-memory NVS (same api as `esp_hal_wifimanager::Nvs`). If backed by file,
+/// contents are saved after every write, so restarts can be simulated.
+pub struct MemNvs {
+    keys: RefCell<BTreeMap<String, Vec<u8>>>,
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct NvsError;
+
+impl MemNvs {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let keys = path
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+
+        Self {
+            keys: RefCell::new(keys),
+            path,
+        }
+    }
+
+    pub async fn get_key(&self, key: &[u8], buf: &mut [u8]) -> Result<(), NvsError> {
+        let keys = self.keys.borrow();
+        let value = keys.get(&key_str(key)).ok_or(NvsError)?;
+        if value.len() > buf.len() {
+            return Err(NvsError);
+        }
+
+        buf[..value.len()].copy_from_slice(value);
+        Ok(())
+    }
+
+    pub async fn append_key(&self, key: &[u8], data: &[u8]) -> Result<(), NvsError> {
+        self.keys
+            .borrow_mut()
+            .entry(key_str(key))
+            .or_default()
+            .extend_from_slice(data);
+
+        self.persist()
+    }
+
+    pub async fn invalidate_key(&self, key: &[u8]) -> Result<(), NvsError> {
+        self.keys.borrow_mut().remove(&key_str(key));
+        self.persist()
+    }
+
+    fn persist(&self) -> Result<(), NvsError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let json = serde_json::to_vec_pretty(&*self.keys.borrow()).map_err(|_| NvsError)?;
+        std::fs::write(path, json).map_err(|e| {
+            log::error!("[NVS] Cannot save {path:?}: {e}");
+            NvsError
+        })
+    }
+}
+
+/// Values are stored as plain json (device prefixes them with len)
+impl KvStore for MemNvs {
+    async fn load<T: DeserializeOwned>(&self, key: &[u8]) -> Option<T> {
+        let keys = self.keys.borrow();
+        serde_json::from_slice(keys.get(&key_str(key))?).ok()
+    }
+
+    async fn store<T: Serialize>(&self, key: &[u8], value: &T) -> bool {
+        let Ok(json) = serde_json::to_vec(value) else {
+            return false;
+        };
+
+        _ = self.invalidate_key(key).await;
+        self.append_key(key, &json).await.is_ok()
+    }
+
+    async fn remove(&self, key: &[u8]) {
+        _ = self.invalidate_key(key).await;
+    }
+}
+
+fn key_str(key: &[u8]) -> String {
+    String::from_utf8_lossy(key).into_owned()
+}
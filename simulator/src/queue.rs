@@ -0,0 +1,30 @@
+use crate::state::SimState;
+use crate::ws::SimLink;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::Timer;
+use staff_at_device::Platform;
+use std::rc::Rc;
+
+// Same as device (src/consts.rs)
+const OFFLINE_QUEUE_RETRY_MS: u64 = 10000;
+
+/// Same loop as `queue_replay_task` on device
+#[embassy_executor::task]
+pub async fn queue_replay_task(
+    state: SimState,
+    ws_connect_signal: Rc<Signal<CriticalSectionRawMutex, ()>>,
+) {
+    loop {
+        _ = embassy_futures::select::select(
+            ws_connect_signal.wait(),
+            Timer::after_millis(OFFLINE_QUEUE_RETRY_MS),
+        )
+        .await;
+
+        if state.sleep.get() || !state.server_connected().await {
+            continue;
+        }
+
+        staff_at_device::queue::replay(&state.offline_queue, &state.nvs, &SimLink(&state)).await;
+    }
+}
@@ -0,0 +1,149 @@
+use embassy_time::Instant;
+use staff_at_scanner::mock::MockReader;
+use staff_at_scanner::{CardReader, CardUid};
+
+/// Halted card that is still held is selected again after this time
+/// (same thing MFRC522 does when card is not removed)
+const REWAKE_MS: u64 = 500;
+
+/// Card put on reader at `at_ms` (since simulator start) and held for `hold_ms`
+#[derive(Debug, Clone)]
+pub struct CardEvent {
+    pub at_ms: u64,
+    pub hold_ms: u64,
+    pub uid: CardUid,
+}
+
+/// Parses card script. Every line is `<at_ms> <uid hex> [hold_ms]`,
+/// empty lines and lines starting with `#` are ignored
+pub fn parse_script(script: &str) -> Result<Vec<CardEvent>, String> {
+    let mut events = Vec::new();
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let err = |msg: &str| format!("Card script line {}: {msg}", i + 1);
+        let mut parts = line.split_whitespace();
+        let at_ms = parts
+            .next()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| err("invalid time"))?;
+
+        let uid = parts
+            .next()
+            .and_then(parse_uid)
+            .ok_or_else(|| err("uid should be 4, 7 or 10 bytes of hex"))?;
+
+        let hold_ms = match parts.next() {
+            Some(hold) => hold.parse().map_err(|_| err("invalid hold time"))?,
+            None => 0,
+        };
+
+        events.push(CardEvent {
+            at_ms,
+            hold_ms,
+            uid,
+        });
+    }
+
+    events.sort_by_key(|e| e.at_ms);
+    Ok(events)
+}
+
+fn parse_uid(hex: &str) -> Option<CardUid> {
+    if !matches!(hex.len(), 8 | 14 | 20) {
+        return None;
+    }
+
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    // same as `PiccUid::get_number` (big endian, truncated to u64)
+    let card_id = bytes.iter().fold(0u128, |n, b| (n << 8) | *b as u128) as u64;
+    Some(CardUid::new(&bytes, card_id))
+}
+
+/// Feeds [`MockReader`] from timed card script
+pub struct ScriptedReader {
+    inner: MockReader,
+    events: Vec<CardEvent>,
+    next: usize,
+    start: Instant,
+
+    /// Card in field (with time it's removed)
+    current: Option<(CardUid, u64)>,
+    halted_at: Option<u64>,
+    read: bool,
+}
+
+impl ScriptedReader {
+    pub fn new(events: Vec<CardEvent>, fail_inits: usize) -> Self {
+        let mut inner = MockReader::new([]);
+        inner.fail_inits = fail_inits;
+
+        Self {
+            inner,
+            events,
+            next: 0,
+            start: Instant::now(),
+            current: None,
+            halted_at: None,
+            read: false,
+        }
+    }
+
+    fn now_ms(&self) -> u64 {
+        (Instant::now() - self.start).as_millis()
+    }
+
+    /// Card that should be reported by this presence poll
+    fn card_in_field(&mut self) -> Option<CardUid> {
+        let now = self.now_ms();
+        while let Some(event) = self.events.get(self.next).filter(|e| e.at_ms <= now) {
+            log::debug!("[SIM] Card {} placed on reader", event.uid.to_hex());
+            self.current = Some((event.uid, event.at_ms + event.hold_ms));
+            self.halted_at = None;
+            self.read = false;
+            self.next += 1;
+        }
+
+        let (uid, until) = self.current?;
+        if self.read && now > until {
+            log::debug!("[SIM] Card {} removed", uid.to_hex());
+            self.current = None;
+            return None;
+        }
+
+        match self.halted_at {
+            Some(halted) if now - halted < REWAKE_MS => None,
+            _ => Some(uid),
+        }
+    }
+}
+
+impl CardReader for ScriptedReader {
+    async fn init(&mut self) -> bool {
+        self.inner.init().await
+    }
+
+    async fn is_card_present(&mut self) -> bool {
+        let card = self.card_in_field();
+        self.inner.push(card);
+        self.inner.is_card_present().await
+    }
+
+    async fn read_uid(&mut self) -> Option<CardUid> {
+        let uid = self.inner.read_uid().await;
+        self.read |= uid.is_some();
+        uid
+    }
+
+    async fn halt(&mut self) {
+        self.halted_at = Some(self.now_ms());
+        self.inner.halt().await;
+    }
+}
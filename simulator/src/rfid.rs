@@ -0,0 +1,8 @@
+use crate::reader::ScriptedReader;
+use crate::state::SimState;
+
+/// Shared scan loop (same as `rfid_task` on device) reading from scripted reader
+#[embassy_executor::task]
+pub async fn rfid_task(mut reader: ScriptedReader, state: SimState) {
+    staff_at_device::scan::scan_loop(&*state, &mut reader).await
+}
@@ -0,0 +1,190 @@
+use crate::nvs::MemNvs;
+use crate::ws::SimLink;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embassy_time::Instant;
+use rand_core::RngCore;
+use staff_at_device::{
+    clock_drift::DriftEstimator, queue::OfflineQueue, scan::ScanCounter, Clock, KvStore, Link,
+    Platform,
+};
+use staff_at_protocol::{DeviceConfig, DeviceStatus, ResetReason};
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Exit code used when device would restart (wrapper script can start it again)
+pub const RESTART_EXIT_CODE: i32 = 3;
+
+// Same as device (src/consts.rs)
+const OFFLINE_QUEUE_CAPACITY: usize = 32;
+const OFFLINE_QUEUE_DROP_OLDEST: bool = true;
+const SCAN_COUNTER_RESERVE: u64 = 32;
+
+const DEVICE_CONFIG_KEY: &[u8] = b"DEVICE_CONFIG_V1";
+
+/// Simulated device state (`GlobalState` on device)
+pub type SimState = Rc<SimStateInner>;
+pub struct SimStateInner {
+    pub device_id: u32,
+    pub nvs: MemNvs,
+    pub state: Mutex<CriticalSectionRawMutex, ConnState>,
+    pub offline_queue: Mutex<CriticalSectionRawMutex, OfflineQueue>,
+    pub scan_counter: Mutex<CriticalSectionRawMutex, ScanCounter>,
+    pub device_status: Mutex<CriticalSectionRawMutex, DeviceStatus>,
+    pub clock_drift: Mutex<CriticalSectionRawMutex, DriftEstimator>,
+
+    /// Unix time (ms) at boot
+    pub epoch_base_ms: Cell<u64>,
+    pub time_valid: Cell<bool>,
+    pub sleep: Cell<bool>,
+    pub deeper_sleep: Cell<bool>,
+    config: Cell<DeviceConfig>,
+}
+
+#[derive(Debug, Default)]
+pub struct ConnState {
+    pub device_added: Option<bool>,
+    pub server_connected: Option<bool>,
+}
+
+impl SimStateInner {
+    pub async fn new(device_id: u32, nvs: MemNvs, config: Option<DeviceConfig>) -> Self {
+        let saved = nvs
+            .load::<DeviceConfig>(DEVICE_CONFIG_KEY)
+            .await
+            .filter(|c| c.validate().is_ok());
+
+        let mut offline_queue =
+            OfflineQueue::new(OFFLINE_QUEUE_CAPACITY, OFFLINE_QUEUE_DROP_OLDEST);
+        offline_queue.load(&nvs).await;
+
+        let mut scan_counter = ScanCounter::new(SCAN_COUNTER_RESERVE);
+        scan_counter.load(&nvs).await;
+
+        Self {
+            device_id,
+            nvs,
+            state: Mutex::new(ConnState::default()),
+            offline_queue: Mutex::new(offline_queue),
+            scan_counter: Mutex::new(scan_counter),
+            device_status: Mutex::new(DeviceStatus::default()),
+            clock_drift: Mutex::new(DriftEstimator::new()),
+            epoch_base_ms: Cell::new(0),
+            time_valid: Cell::new(false),
+            sleep: Cell::new(false),
+            deeper_sleep: Cell::new(false),
+            config: Cell::new(config.or(saved).unwrap_or_default()),
+        }
+    }
+}
+
+impl Clock for SimStateInner {
+    fn uptime_ms(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+
+    fn epoch_base_ms(&self) -> u64 {
+        self.epoch_base_ms.get()
+    }
+
+    fn set_epoch_base_ms(&self, epoch_base_ms: u64) {
+        self.epoch_base_ms.set(epoch_base_ms);
+        self.time_valid.set(true);
+    }
+}
+
+impl Platform for SimStateInner {
+    type RawMutex = CriticalSectionRawMutex;
+
+    fn clock(&self) -> impl Clock + '_ {
+        self
+    }
+
+    fn kv(&self) -> impl KvStore + '_ {
+        &self.nvs
+    }
+
+    fn link(&self) -> impl Link + '_ {
+        SimLink(self)
+    }
+
+    fn device_id(&self) -> u32 {
+        self.device_id
+    }
+
+    fn firmware(&self) -> &str {
+        "STAFF"
+    }
+
+    fn random_u32(&self) -> u32 {
+        rand_core::OsRng.next_u32()
+    }
+
+    fn config(&self) -> DeviceConfig {
+        self.config.get()
+    }
+
+    async fn update_config(&self, config: DeviceConfig) -> DeviceConfig {
+        if let Err(e) = config.validate() {
+            log::error!("Device config rejected: {e}");
+            return self.config.get();
+        }
+
+        if !self.nvs.store(DEVICE_CONFIG_KEY, &config).await {
+            log::error!("Failed to persist device config!");
+        }
+
+        self.config.set(config);
+        log::info!("Device config updated: {config:?}");
+
+        config
+    }
+
+    fn offline_queue(&self) -> &Mutex<CriticalSectionRawMutex, OfflineQueue> {
+        &self.offline_queue
+    }
+
+    fn scan_counter(&self) -> &Mutex<CriticalSectionRawMutex, ScanCounter> {
+        &self.scan_counter
+    }
+
+    fn device_status(&self) -> &Mutex<CriticalSectionRawMutex, DeviceStatus> {
+        &self.device_status
+    }
+
+    fn clock_drift(&self) -> &Mutex<CriticalSectionRawMutex, DriftEstimator> {
+        &self.clock_drift
+    }
+
+    fn time_valid(&self) -> bool {
+        self.time_valid.get()
+    }
+
+    fn sleep_state(&self) -> bool {
+        self.sleep.get()
+    }
+
+    fn set_sleep_state(&self, sleep: bool) {
+        self.sleep.set(sleep);
+    }
+
+    fn enter_deeper_sleep(&self) {
+        self.deeper_sleep.set(true);
+    }
+
+    async fn server_connected(&self) -> bool {
+        self.state.lock().await.server_connected == Some(true)
+    }
+
+    async fn set_server_connected(&self, connected: bool) {
+        self.state.lock().await.server_connected = Some(connected);
+    }
+
+    async fn set_device_added(&self, added: bool) {
+        self.state.lock().await.device_added = Some(added);
+    }
+
+    fn restart(&self, reason: ResetReason) -> ! {
+        log::warn!("[SIM] Device restart ({reason:?}, exit code {RESTART_EXIT_CODE})");
+        std::process::exit(RESTART_EXIT_CODE);
+    }
+}
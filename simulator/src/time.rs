@@ -0,0 +1,78 @@
+use crate::state::SimState;
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Stack,
+};
+use embassy_time::{Duration, Instant, Timer, WithTimeout};
+use staff_at_device::{sntp, time::set_platform_time};
+use staff_at_protocol::TimeSource;
+
+const NTP_CHECK_INTERVAL_MS: u64 = 30000;
+const NTP_RESYNC_INTERVAL_MS: u64 = 60 * 60 * 1000;
+const NTP_TIMEOUT_MS: u64 = 3000;
+
+/// Fallback time source (single server, device also tries servers
+/// provided by dhcp and public pool)
+#[embassy_executor::task]
+pub async fn sntp_task(stack: Stack<'static>, server: IpEndpoint, state: SimState) {
+    let mut rx_buffer = [0; 128];
+    let mut tx_buffer = [0; 128];
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    if let Err(e) = socket.bind(0) {
+        log::error!("[NTP] Bind failed: {e:?}");
+        return;
+    }
+
+    loop {
+        stack.wait_config_up().await;
+
+        let last_sync = state.clock_drift.lock().await.last_sync_ms();
+        let sync_due = last_sync
+            .map(|last| Instant::now().as_millis() - last >= NTP_RESYNC_INTERVAL_MS)
+            .unwrap_or(true);
+
+        if !state.sleep.get() && (!state.time_valid.get() || sync_due) {
+            match query(&socket, server).await {
+                Some(epoch_ms) => set_platform_time(&*state, epoch_ms, TimeSource::Ntp).await,
+                None => log::warn!("[NTP] No response from {server}"),
+            }
+        }
+
+        Timer::after_millis(NTP_CHECK_INTERVAL_MS).await;
+    }
+}
+
+async fn query(socket: &UdpSocket<'_>, endpoint: IpEndpoint) -> Option<u64> {
+    let sent_ms = Instant::now().as_millis();
+    socket
+        .send_to(&sntp::request(sent_ms), endpoint)
+        .await
+        .ok()?;
+
+    let mut buf = [0; sntp::PACKET_LEN];
+    let res = async {
+        loop {
+            let Ok((n, _)) = socket.recv_from(&mut buf).await else {
+                continue;
+            };
+
+            match sntp::parse_response(&buf[..n], sent_ms, Instant::now().as_millis()) {
+                Ok(epoch_ms) => return epoch_ms,
+                Err(e) => log::warn!("[NTP] Invalid response from {endpoint}: {e:?}"),
+            }
+        }
+    }
+    .with_timeout(Duration::from_millis(NTP_TIMEOUT_MS))
+    .await;
+
+    res.ok()
+}
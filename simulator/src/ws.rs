@@ -0,0 +1,272 @@
+use crate::state::{SimState, SimStateInner};
+use embassy_net::{tcp::TcpSocket, IpAddress, Stack};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::Duration;
+use embedded_io_async::Write;
+use rand_core::RngCore;
+use staff_at_device::{
+    deliver_with_retries,
+    session::{
+        connection_loop, ConnectError, Connector, Frame, Mailbox, SessionHandler, WsConnection,
+        REQUEST_TIMEOUT_MS,
+    },
+    Link,
+};
+use staff_at_protocol::{
+    ApiError, FromPacket, TimerPacket, TimerPacketInner, UpdateFailure, UpdateFailureKind,
+};
+use std::rc::Rc;
+use std::str::FromStr;
+use ws_framer::{WsFrame, WsFrameOwned, WsRxFramer, WsTxFramer, WsUrlOwned};
+
+static MAILBOX: Mailbox = Mailbox::new();
+
+/// Shared connection loop (same as `ws_task` on device), plain ws only, updates are refused
+#[embassy_executor::task]
+pub async fn ws_task(
+    stack: Stack<'static>,
+    ws_url: WsUrlOwned,
+    state: SimState,
+    ws_sleep_sig: Rc<Signal<CriticalSectionRawMutex, bool>>,
+    ws_connect_signal: Rc<Signal<CriticalSectionRawMutex, ()>>,
+) {
+    log::debug!("ws_url: {ws_url:?}");
+
+    let mut connector = SimConnector {
+        stack,
+        ws_url,
+        device_id: state.device_id,
+        rx_buf: vec![0; 8192],
+        tx_buf: vec![0; 8192],
+        ws_rx_buf: vec![0; 8192],
+        ws_tx_buf: vec![0; 8192],
+    };
+
+    stack.wait_config_up().await;
+    connection_loop(
+        &*state,
+        &MAILBOX,
+        &mut connector,
+        &mut UpdateRefuser,
+        &*ws_sleep_sig,
+        &*ws_connect_signal,
+    )
+    .await
+}
+
+/// Connects to single server url (redirects aren't followed by simulator)
+struct SimConnector {
+    stack: Stack<'static>,
+    ws_url: WsUrlOwned,
+    device_id: u32,
+    rx_buf: Vec<u8>,
+    tx_buf: Vec<u8>,
+    ws_rx_buf: Vec<u8>,
+    ws_tx_buf: Vec<u8>,
+}
+
+impl Connector for SimConnector {
+    type Connection<'a>
+        = SimConnection<'a>
+    where
+        Self: 'a;
+
+    async fn connect(&mut self) -> Result<SimConnection<'_>, ConnectError> {
+        let ws_url = self.ws_url.as_ref();
+        let ip = if let Ok(addr) = embassy_net::Ipv4Address::from_str(ws_url.ip) {
+            addr
+        } else {
+            let dns_resolver = embassy_net::dns::DnsSocket::new(self.stack);
+            let res = dns_resolver
+                .query(ws_url.ip, embassy_net::dns::DnsQueryType::A)
+                .await;
+
+            let Ok(res) = res else {
+                log::error!("[WS]Dns resolver error: {:?}", res.expect_err(""));
+                return Err(ConnectError::Failed);
+            };
+
+            let Some(IpAddress::Ipv4(addr)) = res.first() else {
+                log::error!("[WS]Dns resolver empty vec");
+                return Err(ConnectError::Failed);
+            };
+            *addr
+        };
+
+        let mut socket = TcpSocket::new(self.stack, &mut self.rx_buf, &mut self.tx_buf);
+        socket.set_timeout(Some(Duration::from_secs(15)));
+
+        if let Err(e) = socket.connect((ip, ws_url.port)).await {
+            log::error!("connect error: {:?}", e);
+            return Err(ConnectError::Failed);
+        }
+
+        let mut tx_framer = WsTxFramer::new(true, &mut self.ws_tx_buf);
+        let mut rx_framer = WsRxFramer::new(&mut self.ws_rx_buf);
+
+        let path = format!(
+            "{}?id={}&ver=sim&hw=sim&firmware=STAFF",
+            ws_url.path, self.device_id,
+        );
+
+        socket
+            .write_all(tx_framer.generate_http_upgrade(ws_url.host, &path, None))
+            .await
+            .map_err(|_| ())?;
+
+        let code = loop {
+            let n = socket.read(rx_framer.mut_buf()).await.map_err(|_| ())?;
+            if n == 0 {
+                log::error!("error while reading http response");
+                return Err(ConnectError::Failed);
+            }
+
+            if let Some(code) = rx_framer.process_http_response(n) {
+                break code;
+            }
+        };
+
+        log::info!("http_resp_code: {code}");
+        if code != 101 {
+            log::error!("[WS] Upgrade failed ({code})!");
+            return Err(ConnectError::Failed);
+        }
+
+        Ok(SimConnection {
+            socket,
+            rx_framer,
+            tx_framer,
+        })
+    }
+}
+
+/// Plain tcp ws connection, frames are converted to owned [`Frame`]
+struct SimConnection<'a> {
+    socket: TcpSocket<'a>,
+    rx_framer: WsRxFramer<'a>,
+    tx_framer: WsTxFramer<'a>,
+}
+
+impl WsConnection for SimConnection<'_> {
+    async fn read_frame(&mut self) -> Result<Frame, ()> {
+        loop {
+            if let Some(frame) = self.rx_framer.process_data() {
+                let frame = match frame {
+                    WsFrame::Text(text) => Frame::Text(text.to_string()),
+                    WsFrame::Binary(data) => Frame::Binary(data.to_vec()),
+                    WsFrame::Close(code, reason) => Frame::Close(code, reason.to_string()),
+                    WsFrame::Ping(payload) => Frame::Ping(payload.to_vec()),
+                    WsFrame::Pong(payload) => Frame::Pong(payload.to_vec()),
+                    _ => continue,
+                };
+
+                return Ok(frame);
+            }
+
+            let n = self
+                .socket
+                .read(self.rx_framer.mut_buf())
+                .await
+                .map_err(|_| ())?;
+            if n == 0 {
+                log::warn!("read_n: 0");
+                return Err(());
+            }
+
+            self.rx_framer.revolve_write_offset(n);
+        }
+    }
+
+    async fn write_frame(&mut self, frame: Frame) -> Result<(), ()> {
+        let frame = match frame {
+            Frame::Text(text) => WsFrameOwned::Text(text),
+            Frame::Binary(data) => WsFrameOwned::Binary(data),
+            Frame::Close(code, reason) => WsFrameOwned::Close(code, reason),
+            Frame::Ping(payload) => WsFrameOwned::Ping(payload),
+            Frame::Pong(payload) => WsFrameOwned::Pong(payload),
+        };
+
+        let data = self.tx_framer.frame(frame.into_ref());
+        self.socket.write_all(data).await.map_err(|_| ())
+    }
+}
+
+/// Simulator can't be updated, every update is refused
+struct UpdateRefuser;
+
+impl SessionHandler for UpdateRefuser {
+    async fn packet(
+        &mut self,
+        conn: &mut impl WsConnection,
+        packet: TimerPacket,
+    ) -> Result<(), ()> {
+        let TimerPacketInner::StartUpdate { version, .. } = packet.data else {
+            return Ok(());
+        };
+
+        log::warn!("[SIM] Refusing update to {version}");
+        let failure = TimerPacket {
+            tag: None,
+            data: TimerPacketInner::UpdateFailed(UpdateFailure::new(
+                UpdateFailureKind::HardwareMismatch,
+                "simulator",
+            )),
+        };
+        conn.write_packet(&failure).await
+    }
+}
+
+pub async fn request_epoch_time() {
+    send_packet(TimerPacket {
+        tag: None,
+        data: TimerPacketInner::EpochTimeRequest,
+    })
+    .await;
+}
+
+/// Server says device time is wrong, it isn't trusted until next sync
+pub async fn reset_time(state: &SimStateInner) {
+    log::warn!("[TIME] Time rejected by server, requesting sync");
+    state.time_valid.set(false);
+    request_epoch_time().await;
+}
+
+pub async fn send_packet(packet: TimerPacket) {
+    MAILBOX.send_packet(packet).await;
+}
+
+/// [`Link`] used by shared device logic (same as `WsLink` on device)
+pub struct SimLink<'a>(pub &'a SimStateInner);
+
+impl Link for SimLink<'_> {
+    async fn send_packet(&self, packet: TimerPacket) {
+        send_packet(packet).await;
+    }
+
+    async fn send_request<T: FromPacket>(
+        &self,
+        packet: TimerPacketInner,
+        tries: usize,
+    ) -> Result<T, ApiError> {
+        send_request_with_retries(packet, tries).await
+    }
+
+    async fn reset_time(&self) {
+        reset_time(self.0).await;
+    }
+}
+
+/// Same as on device: every try uses the same tag, only timeouts are retried
+pub async fn send_request_with_retries<T>(
+    packet: TimerPacketInner,
+    tries: usize,
+) -> Result<T, ApiError>
+where
+    T: FromPacket,
+{
+    let tag = rand_core::OsRng.next_u64();
+    deliver_with_retries(tries, || {
+        MAILBOX.request(tag, packet.clone(), Some(REQUEST_TIMEOUT_MS))
+    })
+    .await
+}
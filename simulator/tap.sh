@@ -0,0 +1,9 @@
+#!/bin/sh
+# Creates TAP interface used by simulator (host side gets 192.168.69.100).
+# Usage: sudo ./simulator/tap.sh [tap0]
+set -e
+
+TAP="${1:-tap0}"
+ip tuntap add name "$TAP" mode tap user "${SUDO_USER:-$USER}"
+ip link set "$TAP" up
+ip addr add 192.168.69.100/24 dev "$TAP"
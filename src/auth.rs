@@ -0,0 +1,69 @@
+use crate::structs::auth::{auth_message, is_valid_nonce};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Last nonce received in `AuthChallenge` (single use)
+static SERVER_NONCE: Mutex<CriticalSectionRawMutex, RefCell<Option<String>>> =
+    Mutex::new(RefCell::new(None));
+
+/// Nonce is put into upgrade query, so anything else than
+/// [`is_valid_nonce`] allows is rejected
+pub fn set_server_nonce(nonce: String) {
+    if !is_valid_nonce(&nonce) {
+        log::error!("[AUTH] Invalid nonce in challenge rejected");
+        return;
+    }
+
+    SERVER_NONCE.lock(|n| *n.borrow_mut() = Some(nonce));
+}
+
+pub fn has_server_nonce() -> bool {
+    SERVER_NONCE.lock(|n| n.borrow().is_some())
+}
+
+/// Device secret provisioned in setup (hmac key)
+pub struct DeviceAuth {
+    secret: Vec<u8>,
+}
+
+impl DeviceAuth {
+    pub fn new(secret: Option<&str>) -> Option<Self> {
+        let secret = secret.map(str::trim).filter(|s| !s.is_empty())?;
+        Some(Self {
+            secret: secret.as_bytes().to_vec(),
+        })
+    }
+
+    /// Query params answering last server challenge, or signing synced time
+    /// if there is no nonce (without either server responds with new challenge
+    /// and closes connection)
+    pub fn query_params(&self, device_id: u32, epoch: Option<u64>) -> String {
+        let nonce = SERVER_NONCE.lock(|n| n.borrow_mut().take());
+        if nonce.is_none() && epoch.is_none() {
+            return String::new();
+        }
+
+        let nonce = nonce.unwrap_or_default();
+        let ts = epoch.unwrap_or_default();
+
+        let mut cnonce = [0; 16];
+        _ = getrandom::getrandom(&mut cnonce);
+        let cnonce = to_hex(&cnonce);
+
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(&self.secret) else {
+            return String::new();
+        };
+        mac.update(auth_message(device_id, &nonce, &cnonce, ts).as_bytes());
+        let sig = to_hex(&mac.finalize().into_bytes());
+
+        alloc::format!("&nonce={nonce}&cnonce={cnonce}&ts={ts}&sig={sig}")
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| alloc::format!("{b:02x}")).collect()
+}
@@ -0,0 +1,48 @@
+use crate::structs::DeviceConfig;
+use crate::utils::nvs_json;
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use esp_hal_wifimanager::Nvs;
+
+/// Bump only if meaning of existing fields changes (new fields use serde defaults)
+const DEVICE_CONFIG_KEY: &[u8] = b"DEVICE_CONFIG_V1";
+
+static DEVICE_CONFIG: Mutex<CriticalSectionRawMutex, Cell<DeviceConfig>> =
+    Mutex::new(Cell::new(DeviceConfig::DEFAULT));
+
+#[inline(always)]
+pub fn device_config() -> DeviceConfig {
+    DEVICE_CONFIG.lock(|c| c.get())
+}
+
+pub async fn load_device_config(nvs: &Nvs) {
+    let Some(config) = nvs_json::load::<DeviceConfig>(nvs, DEVICE_CONFIG_KEY).await else {
+        return;
+    };
+
+    match config.validate() {
+        Ok(_) => {
+            log::info!("Loaded device config: {config:?}");
+            DEVICE_CONFIG.lock(|c| c.set(config));
+        }
+        Err(e) => log::error!("Saved device config invalid ({e}), using defaults!"),
+    }
+}
+
+/// Validates, persists and applies config pushed by server.
+/// Returns effective config (old one if validation failed)
+pub async fn update_device_config(nvs: &Nvs, config: DeviceConfig) -> DeviceConfig {
+    if let Err(e) = config.validate() {
+        log::error!("Device config rejected: {e}");
+        return device_config();
+    }
+
+    if config != device_config() && !nvs_json::store(nvs, DEVICE_CONFIG_KEY, &config).await {
+        log::error!("Failed to persist device config!");
+    }
+
+    DEVICE_CONFIG.lock(|c| c.set(config));
+    log::info!("Device config updated: {config:?}");
+
+    config
+}
@@ -1,12 +1,31 @@
-pub const SLEEP_AFTER_MS: u64 = 60000 * 15;
-pub const DEEPER_SLEEP_AFTER_MS: u64 = 60000 * 30;
-
-pub const LOG_SEND_INTERVAL_MS: u64 = 5000;
+// Defaults of values configurable by server are in `staff_at_protocol::config`
 pub const PRINT_HEAP_INTERVAL_MS: u64 = 30000;
+pub const TELEMETRY_INTERVAL_MS: u64 = 60000;
 
-pub const BATTERY_SEND_INTERVAL_MS: u64 = 60000;
-
-pub const RFID_RETRY_INIT_MS: u64 = 1500;
-pub const WS_RETRY_MS: u64 = 1000;
+/// Number of recent requests in average latency
+pub const REQUEST_LATENCY_SAMPLES: usize = 16;
 
 pub const MDNS_RESEND_INTERVAL: u64 = 500;
+
+/// Used if no ntp server is configured (after servers provided by dhcp)
+pub const DEFAULT_NTP_SERVER: &str = "pool.ntp.org";
+pub const NTP_CHECK_INTERVAL_MS: u64 = 30000;
+pub const NTP_RESYNC_INTERVAL_MS: u64 = 60 * 60 * 1000;
+pub const NTP_TIMEOUT_MS: u64 = 3000;
+
+pub const WS_MAX_REDIRECTS: u8 = 5;
+/// Backoff base multiplier after 5xx upgrade response (server overloaded)
+pub const WS_SERVER_ERROR_BACKOFF_MULTIPLIER: u64 = 8;
+pub const UNAUTHORIZED_BLINK_INTERVAL_MS: u64 = 2000;
+
+pub const OFFLINE_QUEUE_CAPACITY: usize = 32;
+pub const OFFLINE_QUEUE_DROP_OLDEST: bool = true;
+pub const OFFLINE_QUEUE_RETRY_MS: u64 = 10000;
+
+/// Scan counter is persisted once per this many scans
+pub const SCAN_COUNTER_RESERVE: u64 = 32;
+
+/// Must match ota_0 / ota_1 offsets from partitions.csv
+pub const OTA_PARTITION_OFFSETS: [u32; 2] = [0x10000, 0x200000];
+pub const OTA_PERSIST_INTERVAL: u32 = 64 * 1024;
+pub const OTA_RESUME_TIMEOUT_MS: u64 = 10000;
@@ -0,0 +1,132 @@
+use crate::structs::{ConnSettings, WsEndpoint};
+use crate::utils::nvs_json;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use embassy_net::Stack;
+use embassy_time::{Duration, WithTimeout};
+use esp_hal_wifimanager::Nvs;
+use ws_framer::{WsUrl, WsUrlOwned};
+
+/// Endpoint of last successful connection (tried first after boot)
+const LAST_ENDPOINT_KEY: &[u8] = b"WS_LAST_ENDPOINT";
+const MDNS_TIMEOUT_MS: u64 = 10000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Url(String),
+    Mdns,
+}
+
+impl Endpoint {
+    fn key(&self) -> &str {
+        match self {
+            Endpoint::Url(url) => url,
+            Endpoint::Mdns => "mdns",
+        }
+    }
+}
+
+/// Ordered list of server endpoints. Failed connection moves to next one,
+/// working endpoint is kept (and remembered across restarts)
+pub struct Endpoints {
+    list: Vec<Endpoint>,
+    current: usize,
+    working: Option<usize>,
+}
+
+impl Endpoints {
+    /// Configured urls (invalid ones are skipped), then mdns if enabled.
+    /// Falls back to mdns if there is no valid url. Settings have to be
+    /// migrated first ([`ConnSettings::migrate`])
+    pub fn from_conn_settings(settings: &ConnSettings) -> Self {
+        let mut list = Vec::new();
+        for url in &settings.ws_urls {
+            let url = url.trim();
+            if url.is_empty() || list.contains(&Endpoint::Url(url.to_string())) {
+                continue;
+            }
+
+            match WsUrl::from_str(url) {
+                Some(_) => list.push(Endpoint::Url(url.to_string())),
+                None => log::error!("[WS] Invalid server url skipped: {url}"),
+            }
+        }
+
+        if settings.mdns || list.is_empty() {
+            list.push(Endpoint::Mdns);
+        }
+
+        Self {
+            list,
+            current: 0,
+            working: None,
+        }
+    }
+
+    pub async fn restore(&mut self, nvs: &Nvs) {
+        let Some(last) = nvs_json::load::<String>(nvs, LAST_ENDPOINT_KEY).await else {
+            return;
+        };
+
+        if let Some(idx) = self.list.iter().position(|e| e.key() == last) {
+            log::info!("[WS] Starting with last working endpoint: {last}");
+            self.current = idx;
+            self.working = Some(idx);
+        }
+    }
+
+    pub fn current(&self) -> &Endpoint {
+        &self.list[self.current]
+    }
+
+    /// Connection to current endpoint failed, try next one
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.list.len();
+    }
+
+    pub async fn mark_working(&mut self, nvs: &Nvs) {
+        if self.working == Some(self.current) {
+            return;
+        }
+
+        self.working = Some(self.current);
+        if !nvs_json::store(nvs, LAST_ENDPOINT_KEY, &self.current().key()).await {
+            log::error!("[WS] Failed to persist last endpoint!");
+        }
+    }
+
+    /// Returns url of current endpoint (mdns lookup if needed)
+    pub async fn resolve(&self, stack: Stack<'static>) -> Option<(WsUrlOwned, WsEndpoint)> {
+        let url = match self.current() {
+            Endpoint::Url(url) => url.clone(),
+            Endpoint::Mdns => {
+                log::info!("Start mdns lookup...");
+                let res = crate::mdns::mdns_query(stack)
+                    .with_timeout(Duration::from_millis(MDNS_TIMEOUT_MS))
+                    .await;
+
+                let Ok(res) = res else {
+                    log::error!("[WS] Mdns lookup timed out!");
+                    return None;
+                };
+
+                log::info!("Mdns result: {:?}", res);
+                res.to_string()
+            }
+        };
+
+        let Some(ws_url) = WsUrl::from_str(&url) else {
+            log::error!("[WS] Cannot parse ws url: {url}");
+            return None;
+        };
+
+        let ws_url = WsUrlOwned::new(&ws_url);
+        let endpoint = WsEndpoint {
+            index: self.current as u32,
+            mdns: self.current() == &Endpoint::Mdns,
+            url,
+        };
+
+        Some((ws_url, endpoint))
+    }
+}
@@ -0,0 +1,106 @@
+use crate::config::device_config;
+use crate::state::GlobalState;
+use crate::structs::{HealthCheck, ResetReason, RollbackEvent};
+use crate::utils::{nvs_json, reset_journal};
+use core::cell::Cell;
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal_ota::{Ota, OtaImgState};
+use esp_hal_wifimanager::Nvs;
+use esp_storage::FlashStorage;
+
+const ROLLBACK_KEY: &[u8] = b"OTA_ROLLBACK";
+
+const ALL_CHECKS: [HealthCheck; 3] = [
+    HealthCheck::RfidInit,
+    HealthCheck::WsUpgrade,
+    HealthCheck::DeviceSettings,
+];
+
+static PASSED_CHECKS: Mutex<CriticalSectionRawMutex, Cell<u8>> = Mutex::new(Cell::new(0));
+
+pub fn check_passed(check: HealthCheck) {
+    PASSED_CHECKS.lock(|c| c.set(c.get() | (1 << check as u8)));
+}
+
+fn failed_checks() -> alloc::vec::Vec<HealthCheck> {
+    let passed = PASSED_CHECKS.lock(|c| c.get());
+    ALL_CHECKS
+        .into_iter()
+        .filter(|check| passed & (1 << *check as u8) == 0)
+        .collect()
+}
+
+/// Loads rollback event (saved by previous firmware) into device status
+pub async fn load_rollback_event(global_state: &GlobalState) {
+    if let Some(event) = nvs_json::load::<RollbackEvent>(&global_state.nvs, ROLLBACK_KEY).await {
+        log::error!("Firmware {} was rolled back! {event:?}", event.version);
+        global_state.device_status.lock().await.last_rollback = Some(event);
+    }
+}
+
+pub async fn clear_rollback_event(nvs: &Nvs) {
+    nvs_json::remove(nvs, ROLLBACK_KEY).await;
+}
+
+/// Marks freshly updated image as valid only after rfid, ws upgrade and device settings
+/// checks pass within configured window. Otherwise rolls back to previous partition.
+/// Window starts once network is up (wifi setup can take any time), image that never
+/// gets there stays pending and is rolled back by bootloader on next restart
+#[embassy_executor::task]
+pub async fn health_check_task(global_state: GlobalState, stack: Stack<'static>) {
+    let Ok(mut ota) = Ota::new(FlashStorage::new()) else {
+        log::error!("[HEALTH] Ota init failed!");
+        return;
+    };
+
+    let pending = matches!(
+        ota.get_ota_image_state(),
+        Ok(OtaImgState::EspOtaImgNew | OtaImgState::EspOtaImgPendingVerify)
+    );
+
+    if !pending {
+        if let Err(e) = ota.ota_mark_app_valid() {
+            log::error!("Ota mark app valid failed: {e:?}");
+        }
+
+        return;
+    }
+
+    log::info!("[HEALTH] New firmware, waiting for network");
+    stack.wait_config_up().await;
+
+    let window = device_config().health_check_window_ms;
+    log::info!("[HEALTH] Network up, waiting {window}ms for health checks");
+
+    let deadline = Instant::now() + Duration::from_millis(window);
+    while Instant::now() < deadline {
+        if failed_checks().is_empty() {
+            log::info!("[HEALTH] All checks passed! Marking app valid");
+            if let Err(e) = ota.ota_mark_app_valid() {
+                log::error!("Ota mark app valid failed: {e:?}");
+            }
+
+            return;
+        }
+
+        Timer::after_millis(500).await;
+    }
+
+    let failed = failed_checks();
+    log::error!("[HEALTH] Checks failed: {failed:?}! Rolling back..");
+
+    let event = RollbackEvent {
+        version: alloc::string::ToString::to_string(crate::version::VERSION),
+        failed_checks: failed,
+    };
+    _ = nvs_json::store(&global_state.nvs, ROLLBACK_KEY, &event).await;
+
+    if let Err(e) = ota.ota_mark_app_invalid_rollback() {
+        log::error!("Ota rollback failed: {e:?}");
+    }
+
+    Timer::after_millis(100).await;
+    reset_journal::restart(ResetReason::HealthRollback, crate::version::VERSION);
+}
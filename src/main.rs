@@ -3,9 +3,7 @@
 #![feature(impl_trait_in_assoc_type)]
 
 use alloc::rc::Rc;
-use alloc::string::{String, ToString};
-use alloc::vec::Vec;
-use consts::{LOG_SEND_INTERVAL_MS, PRINT_HEAP_INTERVAL_MS};
+use consts::{PRINT_HEAP_INTERVAL_MS, UNAUTHORIZED_BLINK_INTERVAL_MS};
 use embassy_executor::Spawner;
 use embassy_sync::signal::Signal;
 use embassy_time::{Instant, Timer};
@@ -15,20 +13,27 @@ use esp_hal::gpio::{Level, Output, Pin};
 use esp_hal::rng::Rng;
 use esp_hal::timer::systimer::SystemTimer;
 use esp_hal::timer::timg::TimerGroup;
-use esp_hal_wifimanager::{Nvs, WIFI_NVS_KEY};
-use esp_storage::FlashStorage;
-use state::{deeper_sleep_state, ota_state, sleep_state, GlobalState, GlobalStateInner};
+use esp_hal_wifimanager::Nvs;
+use state::{deeper_sleep_state, sleep_state, unauthorized_state, GlobalState, GlobalStateInner};
 use structs::ConnSettings;
 use utils::logger::FkmLogger;
 use utils::set_brownout_detection;
-use ws_framer::{WsUrl, WsUrlOwned};
 
+mod auth;
 mod battery;
+mod config;
 mod consts;
+mod endpoints;
+mod health;
 mod mdns;
+mod ota;
+mod queue;
 mod rfid;
 mod state;
 mod structs;
+mod telemetry;
+mod time;
+mod tls;
 mod utils;
 mod version;
 mod ws;
@@ -86,7 +91,23 @@ async fn main(spawner: Spawner) {
 
     let led = Output::new(peripherals.GPIO3, Level::Low, Default::default());
     let nvs = Nvs::new_from_part_table().expect("Wrong partition configuration!");
+    // before anything can restart device and overwrite pending reset record
+    utils::reset_journal::load();
     let global_state = Rc::new(GlobalStateInner::new(&nvs, led));
+    config::load_device_config(&nvs).await;
+    global_state
+        .offline_queue
+        .lock()
+        .await
+        .load(&global_state.kv())
+        .await;
+    global_state
+        .scan_counter
+        .lock()
+        .await
+        .load(&global_state.kv())
+        .await;
+    queue::migrate_legacy_card(&global_state).await;
     let wifi_setup_sig = Rc::new(Signal::new());
     let ws_connect_signal = Rc::new(Signal::new());
 
@@ -110,6 +131,10 @@ async fn main(spawner: Spawner) {
         peripherals.SPI2,
         peripherals.DMA_CH0,
         global_state.clone(),
+    ));
+
+    spawner.must_spawn(queue::queue_replay_task(
+        global_state.clone(),
         ws_connect_signal.clone(),
     ));
 
@@ -124,15 +149,7 @@ async fn main(spawner: Spawner) {
         format_args!("FKM-SA-{:X}", crate::utils::get_efuse_u32()),
     );
 
-    // mark ota as valid
-    {
-        if let Ok(mut ota) = esp_hal_ota::Ota::new(FlashStorage::new()) {
-            let res = ota.ota_mark_app_valid();
-            if let Err(e) = res {
-                log::error!("Ota mark app valid failed: {e:?}");
-            }
-        }
-    }
+    health::load_rollback_event(&global_state).await;
 
     let rng = Rng::new(peripherals.RNG);
     let timg0 = TimerGroup::new(peripherals.TIMG0);
@@ -152,58 +169,57 @@ async fn main(spawner: Spawner) {
     let Ok(mut wifi_res) = wifi_res else {
         log::error!("WifiManager failed!!! Restarting in 1s!");
         Timer::after_millis(1000).await;
-        esp_hal::system::software_reset();
+        utils::reset_journal::restart(structs::ResetReason::WifiManagerFailed, "");
     };
 
-    let conn_settings: ConnSettings = wifi_res
+    // mark ota as valid (after health checks)
+    spawner.must_spawn(health::health_check_task(
+        global_state.clone(),
+        wifi_res.sta_stack,
+    ));
+
+    let mut conn_settings: ConnSettings = wifi_res
         .data
         .take()
         .and_then(|d| serde_json::from_value(d).ok())
         .unwrap_or_default();
+    conn_settings.migrate();
 
-    let mut parse_retry_count = 0;
-    let ws_url = loop {
-        let url = if conn_settings.mdns || conn_settings.ws_url.is_none() || parse_retry_count > 0 {
-            log::info!("Start mdns lookup...");
-            let mdns_res = mdns::mdns_query(wifi_res.sta_stack).await;
-            log::info!("Mdns result: {:?}", mdns_res);
-
-            mdns_res.to_string()
-        } else {
-            conn_settings.ws_url.clone().expect("")
-        };
-
-        let ws_url = WsUrl::from_str(&url);
-        match ws_url {
-            Some(ws_url) => break WsUrlOwned::new(&ws_url),
-            None => {
-                parse_retry_count += 1;
-                log::error!("Mdns parse failed! Retry ({parse_retry_count})..");
-                Timer::after_millis(1000).await;
-                if parse_retry_count > 3 {
-                    log::error!("Cannot parse wsurl! Reseting wifi configuration!");
-                    _ = nvs.invalidate_key(WIFI_NVS_KEY).await;
-                    Timer::after_millis(1000).await;
-
-                    esp_hal::system::software_reset();
-                }
-
-                continue;
-            }
-        }
-    };
+    let mut endpoints = endpoints::Endpoints::from_conn_settings(&conn_settings);
+    endpoints.restore(&nvs).await;
+
+    let tls_settings = tls::TlsSettings::from_conn_settings(&conn_settings);
+    if let Err(e) = &tls_settings {
+        log::error!("Tls settings invalid: {}", e.detail);
+    }
+    global_state.device_status.lock().await.tls_verify = conn_settings.tls_verify;
+
+    let device_auth = auth::DeviceAuth::new(conn_settings.device_secret.as_deref());
+    if device_auth.is_none() {
+        log::warn!("Device secret not set, connecting without authentication!");
+    }
 
     utils::backtrace_store::read_saved_backtrace().await;
+    global_state.telemetry.lock().await.reset_cause = Some(utils::reset_cause());
     let ws_sleep_sig = Rc::new(Signal::new());
     spawner.must_spawn(ws::ws_task(
         wifi_res.sta_stack,
-        ws_url,
+        endpoints,
+        tls_settings,
+        device_auth,
         global_state.clone(),
         ws_sleep_sig.clone(),
         ws_connect_signal,
     ));
 
+    spawner.must_spawn(time::sntp_task(
+        wifi_res.sta_stack,
+        conn_settings.ntp_servers.clone(),
+        global_state.clone(),
+    ));
+
     spawner.must_spawn(logger_task(global_state.clone()));
+    spawner.must_spawn(telemetry::telemetry_task(global_state.clone()));
     set_brownout_detection(true);
 
     let mut last_led_blink = Instant::now();
@@ -234,40 +250,38 @@ async fn main(spawner: Spawner) {
             led.set_level(initial_level);
             last_led_blink = Instant::now();
         }
-    }
-}
-
-#[embassy_executor::task]
-async fn logger_task(global_state: GlobalState) {
-    let mut heap_start = Instant::now();
-    loop {
-        Timer::after_millis(LOG_SEND_INTERVAL_MS).await;
-
-        let mut tmp_logs: Vec<String> = Vec::new();
-        while let Ok(msg) = utils::logger::LOGS_CHANNEL.try_receive() {
-            tmp_logs.push(msg);
-        }
 
-        if ota_state() || sleep_state() {
-            continue;
-        }
+        if !sleep_state()
+            && unauthorized_state()
+            && (Instant::now() - last_led_blink).as_millis() >= UNAUTHORIZED_BLINK_INTERVAL_MS
+        {
+            let mut led = global_state.output_led.lock().await;
+            let initial_level = led.output_level();
 
-        if !tmp_logs.is_empty() {
-            tmp_logs.reverse();
+            for _ in 0..4 {
+                led.set_high();
+                Timer::after_millis(50).await;
+                led.set_low();
+                Timer::after_millis(50).await;
+            }
 
-            ws::send_packet(structs::TimerPacket {
-                tag: None,
-                data: structs::TimerPacketInner::Logs { logs: tmp_logs },
-            })
-            .await;
+            led.set_level(initial_level);
+            last_led_blink = Instant::now();
         }
+    }
+}
 
-        if (Instant::now() - heap_start).as_millis() >= PRINT_HEAP_INTERVAL_MS {
+#[embassy_executor::task]
+async fn logger_task(global_state: GlobalState) {
+    let heap_fut = async {
+        loop {
+            Timer::after_millis(PRINT_HEAP_INTERVAL_MS).await;
             if global_state.state.lock().await.server_connected == Some(true) {
                 log::info!("{}", esp_alloc::HEAP.stats());
             }
-
-            heap_start = Instant::now();
         }
-    }
+    };
+
+    embassy_futures::select::select(staff_at_device::logs::send_loop(&*global_state), heap_fut)
+        .await;
 }
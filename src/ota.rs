@@ -0,0 +1,393 @@
+use crate::consts::{OTA_PARTITION_OFFSETS, OTA_PERSIST_INTERVAL};
+use crate::structs::{UpdateFailure, UpdateFailureKind};
+use crate::utils::nvs_json;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use core::cell::Cell;
+use embedded_storage::{ReadStorage, Storage};
+use esp_hal_ota::Ota;
+use esp_hal_wifimanager::Nvs;
+use esp_storage::{FlashStorage, FlashStorageError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const OTA_SESSION_KEY: &[u8] = b"OTA_SESSION";
+const SECTOR_SIZE: usize = 4096;
+const METADATA_SIZE: usize = 72;
+
+/// Ed25519 signature (of SHA-256 of everything before it) followed by magic
+const SIGNATURE_SIZE: usize = 64;
+const SIGNATURE_MAGIC: &[u8; 4] = b"SIG1";
+const SIGNATURE_TRAILER_SIZE: usize = SIGNATURE_SIZE + SIGNATURE_MAGIC.len();
+
+/// Persisted state of currently running update
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OtaSession {
+    pub version: String,
+    pub size: u32,
+    pub crc: u32,
+    pub target_partition: usize,
+    pub written: u32,
+
+    /// Allow downgrades (older build time)
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Trailer appended to every release image by `append_metadata.sh`
+#[derive(Debug, Clone)]
+pub struct FirmwareMetadata {
+    pub version: String,
+    pub firmware: String,
+    pub hw: String,
+    pub build_time: u64,
+}
+
+impl FirmwareMetadata {
+    pub fn parse(buf: &[u8; METADATA_SIZE]) -> Option<Self> {
+        fn padded_str(data: &[u8]) -> Option<String> {
+            let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+            core::str::from_utf8(&data[..end])
+                .ok()
+                .map(|s| s.to_string())
+        }
+
+        Some(Self {
+            version: padded_str(&buf[..32])?,
+            firmware: padded_str(&buf[32..48])?,
+            hw: padded_str(&buf[48..64])?,
+            build_time: u64::from_be_bytes(buf[64..72].try_into().ok()?),
+        })
+    }
+
+    fn validate(&self, force: bool) -> Result<(), UpdateFailure> {
+        if self.firmware != crate::version::FIRMWARE {
+            return Err(UpdateFailure::new(
+                UpdateFailureKind::FirmwareMismatch,
+                &alloc::format!("{} != {}", self.firmware, crate::version::FIRMWARE),
+            ));
+        }
+
+        if self.hw != crate::version::HW_VER {
+            return Err(UpdateFailure::new(
+                UpdateFailureKind::HardwareMismatch,
+                &alloc::format!("{} != {}", self.hw, crate::version::HW_VER),
+            ));
+        }
+
+        if !force && self.build_time < crate::version::BUILD_TIME {
+            return Err(UpdateFailure::new(
+                UpdateFailureKind::Downgrade,
+                &alloc::format!(
+                    "{} ({}) is older than {} ({})",
+                    self.version,
+                    self.build_time,
+                    crate::version::VERSION,
+                    crate::version::BUILD_TIME
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Flash used by [`Ota`]. Writes that end below `skip_until` (absolute
+/// offset) are dropped, so data already in partition can be fed back to
+/// rebuild ota state without erasing and rewriting it
+struct OtaFlash {
+    flash: FlashStorage,
+    skip_until: Rc<Cell<u32>>,
+}
+
+impl ReadStorage for OtaFlash {
+    type Error = FlashStorageError;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl Storage for OtaFlash {
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        if offset + bytes.len() as u32 <= self.skip_until.get() {
+            return Ok(());
+        }
+
+        self.flash.write(offset, bytes)
+    }
+}
+
+/// Wrapper around [`Ota`] that outlives single ws connection, so update can be
+/// resumed after reconnect (or after restart, from last persisted offset)
+pub struct OtaUpdater {
+    ota: Ota<OtaFlash>,
+    skip_until: Rc<Cell<u32>>,
+    session: Option<OtaSession>,
+    last_persisted: u32,
+
+    /// Hash of image without signature trailer (computed while streaming)
+    hasher: Sha256,
+}
+
+impl OtaUpdater {
+    pub fn new() -> Result<Self, ()> {
+        let skip_until = Rc::new(Cell::new(0));
+        let flash = OtaFlash {
+            flash: FlashStorage::new(),
+            skip_until: skip_until.clone(),
+        };
+        let ota = Ota::new(flash).map_err(|_| ())?;
+
+        Ok(Self {
+            ota,
+            skip_until,
+            session: None,
+            last_persisted: 0,
+            hasher: Sha256::new(),
+        })
+    }
+
+    pub fn session(&self) -> Option<&OtaSession> {
+        self.session.as_ref()
+    }
+
+    pub async fn begin(
+        &mut self,
+        nvs: &Nvs,
+        version: String,
+        size: u32,
+        crc: u32,
+        force: bool,
+    ) -> Result<(), ()> {
+        self.ota.ota_begin(size, crc).map_err(|_| ())?;
+
+        let session = OtaSession {
+            version,
+            size,
+            crc,
+            target_partition: self.ota.get_next_ota_partition().ok_or(())?,
+            written: 0,
+            force,
+        };
+
+        self.last_persisted = 0;
+        self.hasher = Sha256::new();
+        if !nvs_json::store(nvs, OTA_SESSION_KEY, &session).await {
+            log::error!("[OTA] Failed to persist session!");
+        }
+
+        self.session = Some(session);
+        unsafe {
+            crate::state::OTA_STATE = true;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if whole image was written. Persisted offset never
+    /// includes chunk that wasn't written to flash
+    pub async fn write_chunk(&mut self, nvs: &Nvs, data: &[u8]) -> Result<bool, ()> {
+        let res = self.feed(data)?;
+
+        let Some(session) = self.session.as_ref() else {
+            return Err(());
+        };
+
+        if !res && session.written - self.last_persisted >= OTA_PERSIST_INTERVAL {
+            self.last_persisted = session.written;
+            _ = nvs_json::store(nvs, OTA_SESSION_KEY, session).await;
+        }
+
+        Ok(res)
+    }
+
+    /// Writes chunk to flash and updates image hash (only after successful
+    /// write, so failed chunk can be sent again)
+    fn feed(&mut self, data: &[u8]) -> Result<bool, ()> {
+        let Some(session) = self.session.as_mut() else {
+            return Err(());
+        };
+
+        let res = self.ota.ota_write_chunk(data).map_err(|_| ())?;
+
+        let signed_len = session.size.saturating_sub(SIGNATURE_TRAILER_SIZE as u32);
+        if session.written < signed_len {
+            let hashed = ((signed_len - session.written) as usize).min(data.len());
+            self.hasher.update(&data[..hashed]);
+        }
+        session.written += data.len() as u32;
+
+        Ok(res)
+    }
+
+    pub fn progress(&self) -> u8 {
+        (self.ota.get_ota_progress() * 100.0) as u8
+    }
+
+    /// Validates written image and marks it bootable. On error current
+    /// partition stays active (and session is cleared)
+    pub async fn finish(&mut self, nvs: &Nvs) -> Result<(), UpdateFailure> {
+        let res = self.validate_and_flush();
+        self.clear(nvs).await;
+
+        res
+    }
+
+    fn validate_and_flush(&mut self) -> Result<(), UpdateFailure> {
+        let Some(session) = self.session.as_ref() else {
+            return Err(UpdateFailure::new(
+                UpdateFailureKind::NoSession,
+                "no update in progress",
+            ));
+        };
+
+        let mut trailer = [0; SIGNATURE_TRAILER_SIZE];
+        self.read_image_end(session, session.size, &mut trailer)
+            .ok_or_else(|| {
+                UpdateFailure::new(UpdateFailureKind::InvalidMetadata, "image too small")
+            })?;
+
+        let (signature, metadata_end) = if trailer.ends_with(SIGNATURE_MAGIC) {
+            let signature: Option<[u8; SIGNATURE_SIZE]> = trailer[..SIGNATURE_SIZE].try_into().ok();
+            (signature, session.size - SIGNATURE_TRAILER_SIZE as u32)
+        } else {
+            (None, session.size)
+        };
+
+        self.verify_signature(signature)?;
+
+        let mut buf = [0; METADATA_SIZE];
+        let metadata = self
+            .read_image_end(session, metadata_end, &mut buf)
+            .and_then(|_| FirmwareMetadata::parse(&buf))
+            .ok_or_else(|| {
+                UpdateFailure::new(UpdateFailureKind::InvalidMetadata, "cannot parse trailer")
+            })?;
+
+        log::info!("[OTA] Image metadata: {metadata:?}");
+        metadata.validate(session.force)?;
+
+        self.ota.ota_flush(true, true).map_err(|e| {
+            UpdateFailure::new(UpdateFailureKind::VerifyFailed, &alloc::format!("{e:?}"))
+        })
+    }
+
+    fn verify_signature(
+        &self,
+        signature: Option<[u8; SIGNATURE_SIZE]>,
+    ) -> Result<(), UpdateFailure> {
+        // release builds always have key (enforced by build.rs)
+        let Some(public_key) = crate::version::OTA_PUBLIC_KEY else {
+            return Err(UpdateFailure::new(
+                UpdateFailureKind::SignatureMissing,
+                "no ota public key compiled in",
+            ));
+        };
+
+        let Some(signature) = signature else {
+            return Err(UpdateFailure::new(
+                UpdateFailureKind::SignatureMissing,
+                "image is not signed",
+            ));
+        };
+
+        let digest = self.hasher.clone().finalize();
+        ed25519_compact::PublicKey::new(public_key)
+            .verify(digest, &ed25519_compact::Signature::new(signature))
+            .map_err(|e| {
+                UpdateFailure::new(
+                    UpdateFailureKind::SignatureInvalid,
+                    &alloc::format!("{e:?}"),
+                )
+            })
+    }
+
+    /// Reads `buf.len()` bytes of written image that end at `end` offset
+    fn read_image_end(&self, session: &OtaSession, end: u32, buf: &mut [u8]) -> Option<()> {
+        let partition_offset = *OTA_PARTITION_OFFSETS.get(session.target_partition)?;
+        let offset = partition_offset + end.checked_sub(buf.len() as u32)?;
+
+        FlashStorage::new().read(offset, buf).ok()
+    }
+
+    pub async fn abort(&mut self, nvs: &Nvs) {
+        if let Some(session) = self.session.as_ref() {
+            log::warn!(
+                "[OTA] Aborting update {} at {}b",
+                session.version,
+                session.written
+            );
+        }
+
+        self.clear(nvs).await;
+    }
+
+    async fn clear(&mut self, nvs: &Nvs) {
+        self.session = None;
+        nvs_json::remove(nvs, OTA_SESSION_KEY).await;
+        unsafe {
+            crate::state::OTA_STATE = false;
+        }
+    }
+
+    /// Restores session saved before restart. Already written data is read
+    /// back (from target partition) and fed to rebuild ota state (crc,
+    /// progress, hash), flash isn't written until new data arrives
+    pub async fn restore(&mut self, nvs: &Nvs) {
+        let Some(mut session) = nvs_json::load::<OtaSession>(nvs, OTA_SESSION_KEY).await else {
+            return;
+        };
+
+        let written = session.written;
+        session.written = 0;
+
+        let same_target = self.ota.get_next_ota_partition() == Some(session.target_partition);
+        let Some(&partition_offset) = OTA_PARTITION_OFFSETS.get(session.target_partition) else {
+            self.abort(nvs).await;
+            return;
+        };
+
+        if !same_target || self.ota.ota_begin(session.size, session.crc).is_err() {
+            log::error!("[OTA] Cannot restore saved session!");
+            self.abort(nvs).await;
+            return;
+        }
+
+        log::info!(
+            "[OTA] Restoring {} ({written}/{}b)",
+            session.version,
+            session.size
+        );
+        self.hasher = Sha256::new();
+        self.session = Some(session);
+
+        let mut flash = FlashStorage::new();
+        let mut buf = alloc::vec![0; SECTOR_SIZE];
+        let mut restored = 0;
+        self.skip_until.set(partition_offset + written);
+        while restored < written {
+            let len = SECTOR_SIZE.min((written - restored) as usize);
+            let offset = partition_offset + restored;
+
+            if flash.read(offset, &mut buf[..len]).is_err() || self.feed(&buf[..len]).is_err() {
+                log::error!("[OTA] Restore failed at {restored}b");
+                self.skip_until.set(0);
+                self.abort(nvs).await;
+                return;
+            }
+
+            restored += len as u32;
+        }
+        self.skip_until.set(0);
+
+        self.last_persisted = restored;
+        unsafe {
+            crate::state::OTA_STATE = true;
+        }
+    }
+}
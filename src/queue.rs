@@ -0,0 +1,81 @@
+use crate::consts::OFFLINE_QUEUE_RETRY_MS;
+use crate::state::{current_epoch, sleep_state, GlobalState};
+use crate::ws::WsLink;
+use alloc::rc::Rc;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Timer;
+use staff_at_device::queue::QueuedScan;
+
+/// Card scanned in deeper sleep by older firmware (raw big endian u128)
+const LEGACY_DEEP_SLEEP_CARD_KEY: &[u8] = b"DEEP_SLEEP_CARD";
+
+/// Moves card saved by older firmware before deeper sleep restart into offline queue
+pub async fn migrate_legacy_card(global_state: &GlobalState) {
+    let mut key_buf = [0; 16];
+    if global_state
+        .nvs
+        .get_key(LEGACY_DEEP_SLEEP_CARD_KEY, &mut key_buf)
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    let card_id = u128::from_be_bytes(key_buf) as u64;
+    log::info!("[QUEUE] Migrating legacy deeper sleep card {card_id}");
+
+    // scan time is unknown, uptime based epoch is marked as invalid
+    let scan_epoch = current_epoch();
+    let scan_id = global_state
+        .scan_counter
+        .lock()
+        .await
+        .next_id(
+            &global_state.kv(),
+            crate::utils::get_efuse_u32(),
+            scan_epoch,
+        )
+        .await;
+
+    let scan = QueuedScan {
+        card_id,
+        card_uid: None,
+        scan_epoch,
+        time_valid: Some(false),
+        scan_id: Some(scan_id),
+    };
+
+    global_state
+        .offline_queue
+        .lock()
+        .await
+        .push(&global_state.kv(), scan)
+        .await;
+    _ = global_state
+        .nvs
+        .invalidate_key(LEGACY_DEEP_SLEEP_CARD_KEY)
+        .await;
+}
+
+/// Replays offline queue after (re)connect and periodically while connected
+#[embassy_executor::task]
+pub async fn queue_replay_task(
+    global_state: GlobalState,
+    ws_connect_signal: Rc<Signal<CriticalSectionRawMutex, ()>>,
+) {
+    loop {
+        _ = embassy_futures::select::select(
+            ws_connect_signal.wait(),
+            Timer::after_millis(OFFLINE_QUEUE_RETRY_MS),
+        )
+        .await;
+
+        if sleep_state() || global_state.state.lock().await.server_connected != Some(true) {
+            continue;
+        }
+
+        staff_at_device::queue::replay(&global_state.offline_queue, &global_state.kv(), &WsLink)
+            .await;
+    }
+}
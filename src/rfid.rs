@@ -1,11 +1,4 @@
-use crate::consts::{DEEPER_SLEEP_AFTER_MS, RFID_RETRY_INIT_MS, SLEEP_AFTER_MS};
-use crate::state::{deeper_sleep_state, sleep_state, GlobalState, SLEEP_STATE};
-use crate::structs::AttendanceMarkedPacket;
-use crate::utils::deeper_sleep;
-use alloc::rc::Rc;
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::signal::Signal;
-use embassy_time::{Duration, Instant, Timer};
+use crate::state::GlobalState;
 use esp_hal::time::Rate;
 use esp_hal::{
     dma::{DmaRxBuf, DmaTxBuf},
@@ -14,6 +7,69 @@ use esp_hal::{
     spi::{master::Spi, Mode},
 };
 use esp_hal_mfrc522::consts::UidSize;
+use staff_at_scanner::{uid_len_from_atqa, CardReader, CardUid};
+
+/// Set in SAK when uid is not complete (card has next cascade level)
+const SAK_CASCADE_BIT: u8 = 0x04;
+
+/// [`CardReader`] implementation for MFRC522
+pub struct Mfrc522Reader<S: embedded_hal_async::spi::SpiDevice> {
+    mfrc522: esp_hal_mfrc522::MFRC522<S>,
+
+    /// ATQA of card found by last presence poll
+    atqa: Option<[u8; 2]>,
+}
+
+impl<S: embedded_hal_async::spi::SpiDevice> CardReader for Mfrc522Reader<S> {
+    async fn init(&mut self) -> bool {
+        _ = self.mfrc522.pcd_init().await;
+        if !self.mfrc522.pcd_is_init().await {
+            return false;
+        }
+
+        log::debug!("PCD ver: {:?}", self.mfrc522.pcd_get_version().await);
+        true
+    }
+
+    async fn is_card_present(&mut self) -> bool {
+        let mut atqa = [0; 2];
+        let mut atqa_len = atqa.len() as u8;
+        let present = self
+            .mfrc522
+            .picc_request_a(&mut atqa, &mut atqa_len)
+            .await
+            .is_ok();
+
+        self.atqa = present.then_some(atqa);
+        present
+    }
+
+    /// Uid size is known from ATQA, so all cascade levels are selected at once
+    /// (another REQA between levels would put the card back to idle)
+    async fn read_uid(&mut self) -> Option<CardUid> {
+        let size = match uid_len_from_atqa(self.atqa.take()?)? {
+            4 => UidSize::Four,
+            7 => UidSize::Seven,
+            _ => UidSize::Ten,
+        };
+
+        let card = self.mfrc522.get_card(size).await.ok()?;
+        if card.sak & SAK_CASCADE_BIT != 0 {
+            log::warn!("[RFID] Card uid is longer than announced in ATQA");
+            return None;
+        }
+
+        let len = (card.size as usize).min(card.uid_bytes.len());
+        Some(CardUid::new(
+            &card.uid_bytes[..len],
+            card.get_number() as u64,
+        ))
+    }
+
+    async fn halt(&mut self) {
+        _ = self.mfrc522.picc_halta().await;
+    }
+}
 
 #[embassy_executor::task]
 pub async fn rfid_task(
@@ -24,7 +80,6 @@ pub async fn rfid_task(
     spi: esp_hal::peripherals::SPI2,
     dma_chan: esp_hal::dma::DmaChannel0,
     global_state: GlobalState,
-    ws_connect_signal: Rc<Signal<CriticalSectionRawMutex, ()>>,
 ) {
     let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) = dma_buffers!(512);
     let dma_tx_buf = DmaTxBuf::new(tx_descriptors, tx_buffer).expect("Dma tx buf failed");
@@ -44,141 +99,15 @@ pub async fn rfid_task(
     .with_buffers(dma_rx_buf, dma_tx_buf)
     .into_async();
 
-    let mut mfrc522 = {
+    let mut reader = {
         let spi = embedded_hal_bus::spi::ExclusiveDevice::new(spi, cs_pin, embassy_time::Delay)
             .expect("Spi bus init failed (cs set high failed)");
 
-        esp_hal_mfrc522::MFRC522::new(spi)
-    };
-
-    loop {
-        _ = mfrc522.pcd_init().await;
-        if mfrc522.pcd_is_init().await {
-            break;
-        }
-
-        log::error!("MFRC522 init failed! Try to power cycle to module! Retrying...");
-        Timer::after(Duration::from_millis(RFID_RETRY_INIT_MS)).await;
-    }
-    log::debug!("PCD ver: {:?}", mfrc522.pcd_get_version().await);
-
-    // wait for ws connection
-    ws_connect_signal.wait().await;
-
-    let mut key_buf = [0; 16];
-    if global_state
-        .nvs
-        .get_key(b"DEEP_SLEEP_CARD", &mut key_buf)
-        .await
-        .is_ok()
-    {
-        let card_uid = u128::from_be_bytes(key_buf.into());
-        log::info!("Card UID: {card_uid}");
-        global_state.led_blink(2, 100).await;
-
-        let resp = crate::ws::send_request::<AttendanceMarkedPacket>(
-            crate::structs::TimerPacketInner::CardInfoRequest {
-                card_id: card_uid as u64,
-                attendance_device: Some(true),
-            },
-        )
-        .await;
-        global_state.led(true).await;
-
-        match resp {
-            Ok(resp) => {
-                log::info!("Attendance card response: {resp:?}");
-            }
-            Err(e) => {
-                log::error!(
-                    "[RFID] Resp_error: ({}): {:?}",
-                    e.should_reset_time,
-                    e.error
-                );
-            }
-        }
-
-        _ = global_state.nvs.invalidate_key(b"DEEP_SLEEP_CARD").await;
-    }
-
-    let mut last_scan = Instant::now();
-    //let mut rfid_sleep = false;
-    loop {
-        Timer::after(Duration::from_millis(10)).await;
-        if (Instant::now() - last_scan).as_millis() >= SLEEP_AFTER_MS && !sleep_state() {
-            log::info!("Going into sleep!");
-            unsafe {
-                SLEEP_STATE = true;
-            }
-        }
-
-        if (Instant::now() - last_scan).as_millis() >= DEEPER_SLEEP_AFTER_MS
-            && !deeper_sleep_state()
-        {
-            log::info!("Going into depper sleep!");
-            deeper_sleep();
-        }
-
-        if mfrc522.picc_is_new_card_present().await.is_err() {
-            continue;
-        }
-
-        let Ok(card_uid) = mfrc522
-            .get_card(UidSize::Four)
-            .await
-            .map(|c| c.get_number())
-        else {
-            continue;
-        };
-
-        log::info!("Card UID: {card_uid}");
-        global_state.led_blink(2, 100).await;
-        last_scan = Instant::now();
-
-        if sleep_state() {
-            log::info!("Sleep done!");
-            unsafe {
-                SLEEP_STATE = false;
-            }
-        }
-
-        if deeper_sleep_state() {
-            log::info!("Deeper sleep done!");
-            _ = global_state.nvs.invalidate_key(b"DEEP_SLEEP_CARD").await;
-            _ = global_state
-                .nvs
-                .append_key(b"DEEP_SLEEP_CARD", &card_uid.to_be_bytes())
-                .await;
-
-            Timer::after_millis(100).await;
-            esp_hal::system::software_reset();
-        }
-
-        for _ in 0..3 {
-            let resp = crate::ws::send_request::<AttendanceMarkedPacket>(
-                crate::structs::TimerPacketInner::CardInfoRequest {
-                    card_id: card_uid as u64,
-                    attendance_device: Some(true),
-                },
-            )
-            .await;
-            global_state.led(true).await;
-
-            match resp {
-                Ok(resp) => {
-                    log::info!("Attendance card response: {resp:?}");
-                    break;
-                }
-                Err(e) => {
-                    log::error!(
-                        "[RFID] Resp_error: ({}): {:?}",
-                        e.should_reset_time,
-                        e.error
-                    );
-                }
-            }
+        Mfrc522Reader {
+            mfrc522: esp_hal_mfrc522::MFRC522::new(spi),
+            atqa: None,
         }
+    };
 
-        _ = mfrc522.picc_halta().await;
-    }
+    staff_at_device::scan::scan_loop(&*global_state, &mut reader).await
 }
@@ -1,19 +1,40 @@
+use crate::consts::{OFFLINE_QUEUE_CAPACITY, OFFLINE_QUEUE_DROP_OLDEST, SCAN_COUNTER_RESERVE};
+use crate::structs::{DeviceConfig, DeviceStatus, HealthCheck, ResetReason, RfidStatus, Telemetry};
+use crate::utils::nvs_json::JsonNvs;
 use crate::utils::signaled_mutex::SignaledMutex;
+use crate::ws::WsLink;
 use alloc::rc::Rc;
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 use embassy_time::{Instant, Timer};
 use esp_hal::gpio::Output;
 use esp_hal_wifimanager::Nvs;
+use staff_at_device::{
+    clock_drift::DriftEstimator, queue::OfflineQueue, scan::ScanCounter, Clock, KvStore, Link,
+    Platform,
+};
 
-pub static mut EPOCH_BASE: u64 = 0;
+/// Unix time (ms) at boot
+pub static mut EPOCH_BASE_MS: u64 = 0;
 pub static mut SLEEP_STATE: bool = false;
 pub static mut DEEPER_SLEEP: bool = false;
 pub static mut OTA_STATE: bool = false;
+pub static mut UNAUTHORIZED_STATE: bool = false;
+pub static mut TIME_VALID: bool = false;
 
 #[inline(always)]
-#[allow(dead_code)]
 pub fn current_epoch() -> u64 {
-    unsafe { EPOCH_BASE + Instant::now().as_secs() }
+    current_epoch_ms() / 1000
+}
+
+#[inline(always)]
+pub fn current_epoch_ms() -> u64 {
+    unsafe { EPOCH_BASE_MS + Instant::now().as_millis() }
+}
+
+/// True if `EPOCH_BASE_MS` was synced (and server didn't reject it since)
+#[inline(always)]
+pub fn time_valid() -> bool {
+    unsafe { TIME_VALID }
 }
 
 #[inline(always)]
@@ -31,10 +52,43 @@ pub fn ota_state() -> bool {
     unsafe { OTA_STATE }
 }
 
+/// Server refused device (401/403 upgrade or failed authentication)
+#[inline(always)]
+pub fn unauthorized_state() -> bool {
+    unsafe { UNAUTHORIZED_STATE }
+}
+
+/// [`Clock`] over `EPOCH_BASE_MS` (used by shared device logic)
+pub struct DeviceClock;
+
+impl Clock for DeviceClock {
+    fn uptime_ms(&self) -> u64 {
+        Instant::now().as_millis()
+    }
+
+    fn epoch_base_ms(&self) -> u64 {
+        unsafe { EPOCH_BASE_MS }
+    }
+
+    fn set_epoch_base_ms(&self, epoch_base_ms: u64) {
+        unsafe {
+            EPOCH_BASE_MS = epoch_base_ms;
+            TIME_VALID = true;
+        }
+    }
+}
+
 pub type GlobalState = Rc<GlobalStateInner>;
 pub struct GlobalStateInner {
     pub state: SignaledMutex<CriticalSectionRawMutex, SignaledGlobalStateInner>,
     pub nvs: Nvs,
+    pub offline_queue: Mutex<CriticalSectionRawMutex, OfflineQueue>,
+    pub scan_counter: Mutex<CriticalSectionRawMutex, ScanCounter>,
+    pub device_status: Mutex<CriticalSectionRawMutex, DeviceStatus>,
+    pub clock_drift: Mutex<CriticalSectionRawMutex, DriftEstimator>,
+
+    /// Snapshot fields (heap, rssi, uptime...) are filled before sending
+    pub telemetry: Mutex<CriticalSectionRawMutex, Telemetry>,
 
     pub output_led: Mutex<CriticalSectionRawMutex, Output<'static>>,
 }
@@ -44,10 +98,22 @@ impl GlobalStateInner {
         Self {
             state: SignaledMutex::new(SignaledGlobalStateInner::new()),
             nvs: nvs.clone(),
+            offline_queue: Mutex::new(OfflineQueue::new(
+                OFFLINE_QUEUE_CAPACITY,
+                OFFLINE_QUEUE_DROP_OLDEST,
+            )),
+            scan_counter: Mutex::new(ScanCounter::new(SCAN_COUNTER_RESERVE)),
+            device_status: Mutex::new(DeviceStatus::default()),
+            clock_drift: Mutex::new(DriftEstimator::new()),
+            telemetry: Mutex::new(Telemetry::default()),
             output_led: Mutex::new(output_led),
         }
     }
 
+    pub fn kv(&self) -> JsonNvs<'_> {
+        JsonNvs(&self.nvs)
+    }
+
     pub async fn led(&self, state: bool) {
         let mut output_led = self.output_led.lock().await;
         output_led.set_level(if state {
@@ -69,6 +135,134 @@ impl GlobalStateInner {
     }
 }
 
+impl Platform for GlobalStateInner {
+    type RawMutex = CriticalSectionRawMutex;
+
+    fn clock(&self) -> impl Clock + '_ {
+        DeviceClock
+    }
+
+    fn kv(&self) -> impl KvStore + '_ {
+        JsonNvs(&self.nvs)
+    }
+
+    fn link(&self) -> impl Link + '_ {
+        WsLink
+    }
+
+    fn device_id(&self) -> u32 {
+        crate::utils::get_efuse_u32()
+    }
+
+    fn firmware(&self) -> &str {
+        crate::version::FIRMWARE
+    }
+
+    fn random_u32(&self) -> u32 {
+        let mut random = [0; 4];
+        _ = getrandom::getrandom(&mut random);
+        u32::from_be_bytes(random)
+    }
+
+    fn config(&self) -> DeviceConfig {
+        crate::config::device_config()
+    }
+
+    async fn update_config(&self, config: DeviceConfig) -> DeviceConfig {
+        crate::config::update_device_config(&self.nvs, config).await
+    }
+
+    fn offline_queue(&self) -> &Mutex<CriticalSectionRawMutex, OfflineQueue> {
+        &self.offline_queue
+    }
+
+    fn scan_counter(&self) -> &Mutex<CriticalSectionRawMutex, ScanCounter> {
+        &self.scan_counter
+    }
+
+    fn device_status(&self) -> &Mutex<CriticalSectionRawMutex, DeviceStatus> {
+        &self.device_status
+    }
+
+    fn clock_drift(&self) -> &Mutex<CriticalSectionRawMutex, DriftEstimator> {
+        &self.clock_drift
+    }
+
+    fn time_valid(&self) -> bool {
+        time_valid()
+    }
+
+    fn sleep_state(&self) -> bool {
+        sleep_state()
+    }
+
+    fn set_sleep_state(&self, sleep: bool) {
+        unsafe { SLEEP_STATE = sleep };
+    }
+
+    fn enter_deeper_sleep(&self) {
+        crate::utils::deeper_sleep();
+    }
+
+    fn ota_state(&self) -> bool {
+        ota_state()
+    }
+
+    async fn server_connected(&self) -> bool {
+        self.state.lock().await.server_connected == Some(true)
+    }
+
+    async fn set_server_connected(&self, connected: bool) {
+        self.led(connected).await;
+        self.state.lock().await.server_connected = Some(connected);
+    }
+
+    async fn set_device_added(&self, added: bool) {
+        crate::health::check_passed(HealthCheck::DeviceSettings);
+        self.state.lock().await.device_added = Some(added);
+    }
+
+    fn restart(&self, reason: ResetReason) -> ! {
+        crate::utils::reset_journal::restart(reason, "")
+    }
+
+    async fn rfid_init(&self, ok: bool) {
+        if ok {
+            crate::health::check_passed(HealthCheck::RfidInit);
+        }
+
+        self.telemetry.lock().await.rfid_status = if ok {
+            RfidStatus::Ok
+        } else {
+            RfidStatus::InitFailed
+        };
+    }
+
+    async fn card_read(&self) {
+        self.telemetry.lock().await.scans += 1;
+        self.led_blink(2, 100).await;
+    }
+
+    async fn scan_suppressed(&self) {
+        self.led_blink(1, 300).await;
+    }
+
+    async fn scan_sent(&self) {
+        self.led(true).await;
+    }
+
+    async fn status_reported(&self, status: &DeviceStatus) {
+        if status.last_rollback.is_some() {
+            crate::health::clear_rollback_event(&self.nvs).await;
+        }
+    }
+
+    async fn session_task(&self) {
+        // reset report waits for server ack, so it runs next to connection
+        crate::ws::send_reset_report().await;
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct SignaledGlobalStateInner {
     pub device_added: Option<bool>,
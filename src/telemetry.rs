@@ -0,0 +1,48 @@
+use crate::consts::TELEMETRY_INTERVAL_MS;
+use crate::state::{deeper_sleep_state, ota_state, sleep_state, GlobalState};
+use crate::structs::{SleepState, Telemetry, TimerPacket, TimerPacketInner};
+use embassy_time::{Instant, Timer};
+
+#[embassy_executor::task]
+pub async fn telemetry_task(global_state: GlobalState) {
+    loop {
+        Timer::after_millis(TELEMETRY_INTERVAL_MS).await;
+        if ota_state() || global_state.state.lock().await.server_connected != Some(true) {
+            continue;
+        }
+
+        let telemetry = snapshot(&global_state).await;
+        crate::ws::send_packet(TimerPacket {
+            tag: None,
+            data: TimerPacketInner::Telemetry(telemetry),
+        })
+        .await;
+    }
+}
+
+async fn snapshot(global_state: &GlobalState) -> Telemetry {
+    let heap = esp_alloc::HEAP.stats();
+    let (failed_requests, avg_latency_ms) = crate::ws::request_stats().await;
+    let (ws_reconnects, suppressed_scans) = {
+        let status = global_state.device_status.lock().await;
+        (status.ws_reconnects, status.suppressed_scans)
+    };
+
+    let mut telemetry = global_state.telemetry.lock().await;
+    telemetry.uptime_ms = Instant::now().as_millis();
+    telemetry.heap_used = heap.current_usage as u32;
+    telemetry.heap_free = heap.size.saturating_sub(heap.current_usage) as u32;
+    telemetry.heap_peak = heap.max_usage as u32;
+    telemetry.rssi = crate::utils::wifi_rssi();
+    telemetry.ws_reconnects = ws_reconnects;
+    telemetry.suppressed_scans = suppressed_scans;
+    telemetry.failed_requests = failed_requests;
+    telemetry.avg_latency_ms = avg_latency_ms;
+    telemetry.sleep_state = match (sleep_state(), deeper_sleep_state()) {
+        (_, true) => SleepState::DeeperSleep,
+        (true, false) => SleepState::Sleep,
+        (false, false) => SleepState::Awake,
+    };
+
+    telemetry.clone()
+}
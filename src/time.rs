@@ -0,0 +1,194 @@
+use crate::consts::{
+    DEFAULT_NTP_SERVER, NTP_CHECK_INTERVAL_MS, NTP_RESYNC_INTERVAL_MS, NTP_TIMEOUT_MS,
+};
+use crate::state::{sleep_state, time_valid, GlobalState};
+use crate::structs::TimeSource;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::str::FromStr;
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    HardwareAddress, IpAddress, IpEndpoint, Ipv4Address, Stack,
+};
+use embassy_time::{Duration, Instant, Timer, WithTimeout};
+use staff_at_device::{dhcp, sntp, time::set_platform_time};
+
+/// Fallback time source. Syncs when time isn't valid (server unreachable
+/// or time rejected) or when last sync (from any source) is too old.
+/// Configured servers are tried first, otherwise servers provided by dhcp
+/// (option 42) and public pool
+#[embassy_executor::task]
+pub async fn sntp_task(stack: Stack<'static>, servers: Vec<String>, global_state: GlobalState) {
+    let mut rx_buffer = [0; 128];
+    let mut tx_buffer = [0; 128];
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    if let Err(e) = socket.bind(0) {
+        log::error!("[NTP] Bind failed: {e:?}");
+        return;
+    }
+
+    loop {
+        stack.wait_config_up().await;
+
+        let last_sync = global_state.clock_drift.lock().await.last_sync_ms();
+        let sync_due = last_sync
+            .map(|last| Instant::now().as_millis() - last >= NTP_RESYNC_INTERVAL_MS)
+            .unwrap_or(true);
+
+        if !sleep_state() && (!time_valid() || sync_due) {
+            match sync(&socket, stack, &servers).await {
+                Some(epoch_ms) => {
+                    set_platform_time(&*global_state, epoch_ms, TimeSource::Ntp).await
+                }
+                None => log::warn!("[NTP] No server responded"),
+            }
+        }
+
+        Timer::after_millis(NTP_CHECK_INTERVAL_MS).await;
+    }
+}
+
+async fn sync(socket: &UdpSocket<'_>, stack: Stack<'static>, servers: &[String]) -> Option<u64> {
+    let mut candidates = servers.to_vec();
+    if candidates.is_empty() {
+        candidates.extend(dhcp_ntp_servers(stack).await);
+        candidates.push(DEFAULT_NTP_SERVER.to_string());
+    }
+
+    for server in candidates {
+        let Some(endpoint) = resolve(stack, &server).await else {
+            log::warn!("[NTP] Cannot resolve {server}");
+            continue;
+        };
+
+        if let Some(epoch_ms) = query(socket, endpoint).await {
+            log::info!("[NTP] Time from {server}");
+            return Some(epoch_ms);
+        }
+    }
+
+    None
+}
+
+/// Ntp servers from dhcp server. Embassy-net dhcp client doesn't request
+/// option 42, so it's asked for separately using DHCPINFORM (sent from
+/// ephemeral port, port 68 is owned by stack dhcp socket)
+async fn dhcp_ntp_servers(stack: Stack<'static>) -> Vec<String> {
+    let (Some(config), HardwareAddress::Ethernet(mac)) =
+        (stack.config_v4(), stack.hardware_address())
+    else {
+        return Vec::new();
+    };
+
+    let mut rx_buffer = [0; 576];
+    let mut tx_buffer = [0; 300];
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+
+    if let Err(e) = socket.bind(0) {
+        log::error!("[NTP] Dhcp bind failed: {e:?}");
+        return Vec::new();
+    }
+
+    let xid = Instant::now().as_ticks() as u32;
+    let request = dhcp::inform(xid, mac.0, config.address.address().octets());
+    let server = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::BROADCAST), dhcp::SERVER_PORT);
+    if let Err(e) = socket.send_to(&request, server).await {
+        log::error!("[NTP] Dhcp inform failed: {e:?}");
+        return Vec::new();
+    }
+
+    let mut buf = [0; 576];
+    let res = async {
+        loop {
+            let Ok((n, _)) = socket.recv_from(&mut buf).await else {
+                continue;
+            };
+
+            match dhcp::parse_ntp_servers(&buf[..n], xid) {
+                Ok(servers) => return servers,
+                Err(e) => log::warn!("[NTP] Invalid dhcp response: {e:?}"),
+            }
+        }
+    }
+    .with_timeout(Duration::from_millis(NTP_TIMEOUT_MS))
+    .await;
+
+    let servers: Vec<String> = res
+        .unwrap_or_default()
+        .into_iter()
+        .map(|ip| Ipv4Address::from(ip).to_string())
+        .collect();
+
+    log::info!("[NTP] Dhcp servers: {servers:?}");
+    servers
+}
+
+/// Server is `host[:port]`
+async fn resolve(stack: Stack<'static>, server: &str) -> Option<IpEndpoint> {
+    let (host, port) = match server.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (server, sntp::NTP_PORT),
+    };
+
+    let ip = match Ipv4Address::from_str(host) {
+        Ok(ip) => ip,
+        Err(_) => {
+            let dns_resolver = embassy_net::dns::DnsSocket::new(stack);
+            let res = dns_resolver
+                .query(host, embassy_net::dns::DnsQueryType::A)
+                .await
+                .ok()?;
+
+            match res.first() {
+                Some(IpAddress::Ipv4(ip)) => *ip,
+                _ => return None,
+            }
+        }
+    };
+
+    Some(IpEndpoint::new(IpAddress::Ipv4(ip), port))
+}
+
+async fn query(socket: &UdpSocket<'_>, endpoint: IpEndpoint) -> Option<u64> {
+    let sent_ms = Instant::now().as_millis();
+    socket
+        .send_to(&sntp::request(sent_ms), endpoint)
+        .await
+        .ok()?;
+
+    let mut buf = [0; sntp::PACKET_LEN];
+    let res = async {
+        loop {
+            let Ok((n, _)) = socket.recv_from(&mut buf).await else {
+                continue;
+            };
+
+            // late responses to previous queries are skipped
+            match sntp::parse_response(&buf[..n], sent_ms, Instant::now().as_millis()) {
+                Ok(epoch_ms) => return epoch_ms,
+                Err(e) => log::warn!("[NTP] Invalid response from {endpoint}: {e:?}"),
+            }
+        }
+    }
+    .with_timeout(Duration::from_millis(NTP_TIMEOUT_MS))
+    .await;
+
+    res.ok()
+}
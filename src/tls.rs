@@ -0,0 +1,219 @@
+use crate::structs::{ConnSettings, TlsFailure, TlsFailureKind, TlsVerifyMode};
+use alloc::vec::Vec;
+use base64::Engine;
+use embassy_net::tcp::TcpSocket;
+use embedded_tls::{
+    webpki::{CertVerifier, TlsClock},
+    Aes128GcmSha256, Certificate, CertificateEntryRef, CertificateRef, HandshakeVerifyRef,
+    NoVerify, SignatureScheme, TlsCipherSuite, TlsConfig, TlsConnection, TlsContext, TlsError,
+    TlsVerifier,
+};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+
+const MAX_CERT_SIZE: usize = 4096;
+
+pub struct TlsSettings {
+    pub mode: TlsVerifyMode,
+
+    /// DER encoded CA certificate (ca mode) or SHA-256 of server public key (pin mode)
+    pub cert: Vec<u8>,
+}
+
+impl TlsSettings {
+    pub fn from_conn_settings(settings: &ConnSettings) -> Result<Self, TlsFailure> {
+        let cert = match settings.tls_verify {
+            TlsVerifyMode::None => Vec::new(),
+            _ => settings
+                .tls_cert
+                .as_ref()
+                .and_then(|c| {
+                    base64::engine::general_purpose::STANDARD
+                        .decode(c.trim())
+                        .ok()
+                })
+                .ok_or_else(|| {
+                    TlsFailure::new(TlsFailureKind::BadConfig, "tls_cert is not valid base64")
+                })?,
+        };
+
+        if settings.tls_verify == TlsVerifyMode::Pin && cert.len() != 32 {
+            return Err(TlsFailure::new(
+                TlsFailureKind::BadConfig,
+                "pin must be base64 encoded SHA-256 (32 bytes)",
+            ));
+        }
+
+        Ok(Self {
+            mode: settings.tls_verify,
+            cert,
+        })
+    }
+}
+
+/// Opens tls session verifying server certificate according to selected mode
+pub async fn open(
+    tls: &mut TlsConnection<'_, TcpSocket<'_>, Aes128GcmSha256>,
+    host: &str,
+    settings: &TlsSettings,
+) -> Result<(), TlsFailure> {
+    let config: TlsConfig<'_, Aes128GcmSha256> = TlsConfig::new().with_server_name(host);
+    let res = match settings.mode {
+        TlsVerifyMode::None => {
+            tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+                .await
+        }
+        TlsVerifyMode::Ca => {
+            let config = config.with_ca(Certificate::X509(&settings.cert));
+            tls.open::<OsRng, CertVerifier<Aes128GcmSha256, EpochClock, MAX_CERT_SIZE>>(
+                TlsContext::new(&config, &mut OsRng),
+            )
+            .await
+        }
+        TlsVerifyMode::Pin => {
+            let config = config.with_ca(Certificate::RawPublicKey(&settings.cert));
+            tls.open::<OsRng, PinVerifier<Aes128GcmSha256>>(TlsContext::new(&config, &mut OsRng))
+                .await
+        }
+    };
+
+    res.map_err(|e| {
+        let kind = match e {
+            TlsError::InvalidCertificate if settings.mode == TlsVerifyMode::Pin => {
+                TlsFailureKind::PinMismatch
+            }
+            TlsError::InvalidCertificate | TlsError::InvalidCertificateEntry => {
+                TlsFailureKind::CertificateInvalid
+            }
+            TlsError::InvalidSignature | TlsError::InvalidSignatureScheme => {
+                TlsFailureKind::SignatureInvalid
+            }
+            _ => TlsFailureKind::HandshakeFailed,
+        };
+
+        TlsFailure::new(kind, &alloc::format!("{e:?}"))
+    })
+}
+
+/// Before first time sync use build time as lower bound (cert validity check)
+pub struct EpochClock;
+
+impl TlsClock for EpochClock {
+    fn now() -> Option<u64> {
+        Some(crate::state::current_epoch().max(crate::version::BUILD_TIME))
+    }
+}
+
+/// Accepts server only if SHA-256 of its certificate public key (SPKI)
+/// matches pinned one. Chain and validity dates are not checked.
+pub struct PinVerifier<CipherSuite: TlsCipherSuite> {
+    transcript: Option<CipherSuite::Hash>,
+    certificate: Option<Vec<u8>>,
+}
+
+impl<'a, CipherSuite: TlsCipherSuite> TlsVerifier<'a, CipherSuite> for PinVerifier<CipherSuite> {
+    fn new(_host: Option<&'a str>) -> Self {
+        Self {
+            transcript: None,
+            certificate: None,
+        }
+    }
+
+    fn verify_certificate(
+        &mut self,
+        transcript: &CipherSuite::Hash,
+        ca: &Option<Certificate>,
+        cert: CertificateRef,
+    ) -> Result<(), TlsError> {
+        let Some(Certificate::RawPublicKey(pin)) = ca else {
+            return Err(TlsError::InvalidCertificate);
+        };
+
+        let Some(CertificateEntryRef::X509(leaf)) = cert.entries.first() else {
+            return Err(TlsError::InvalidCertificate);
+        };
+
+        let spki = subject_public_key_info(leaf).ok_or(TlsError::InvalidCertificate)?;
+        if Sha256::digest(spki).as_slice() != *pin {
+            log::error!("[TLS] Server public key doesn't match pinned one!");
+            return Err(TlsError::InvalidCertificate);
+        }
+
+        self.certificate = Some(leaf.to_vec());
+        self.transcript = Some(transcript.clone());
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, verify: HandshakeVerifyRef) -> Result<(), TlsError> {
+        let (Some(transcript), Some(certificate)) = (self.transcript.take(), &self.certificate)
+        else {
+            return Err(TlsError::InvalidSignature);
+        };
+
+        let mut msg = alloc::vec![0x20; 64];
+        msg.extend_from_slice(b"TLS 1.3, server CertificateVerify\x00");
+        msg.extend_from_slice(&transcript.finalize());
+
+        let alg = match verify.signature_scheme {
+            SignatureScheme::EcdsaSecp256r1Sha256 => &webpki::ECDSA_P256_SHA256,
+            SignatureScheme::EcdsaSecp384r1Sha384 => &webpki::ECDSA_P384_SHA384,
+            SignatureScheme::Ed25519 => &webpki::ED25519,
+            SignatureScheme::RsaPssRsaeSha256 => &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+            _ => return Err(TlsError::InvalidSignatureScheme),
+        };
+
+        let certificate = webpki::EndEntityCert::try_from(certificate.as_slice())
+            .map_err(|_| TlsError::InvalidCertificate)?;
+
+        certificate
+            .verify_signature(alg, &msg, verify.signature)
+            .map_err(|_| TlsError::InvalidSignature)
+    }
+}
+
+/// Returns (tag, content, rest) of DER element
+fn der_next(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first_len = *data.get(1)? as usize;
+
+    let (len, header) = if first_len & 0x80 == 0 {
+        (first_len, 2)
+    } else {
+        let len_bytes = first_len & 0x7f;
+        if len_bytes == 0 || len_bytes > 4 {
+            return None;
+        }
+
+        let mut len = 0;
+        for b in data.get(2..2 + len_bytes)? {
+            len = (len << 8) | *b as usize;
+        }
+
+        (len, 2 + len_bytes)
+    };
+
+    // len is up to 2^32 - 1 (overflows on 32 bit target)
+    let end = header.checked_add(len)?;
+    let content = data.get(header..end)?;
+    Some((tag, content, &data[end..]))
+}
+
+/// Extracts raw SubjectPublicKeyInfo (with its header) from DER X509 certificate
+fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = der_next(cert)?;
+    let (_, tbs, _) = der_next(certificate)?;
+
+    let mut rest = tbs;
+    // optional [0] version tag
+    if rest.first() == Some(&0xa0) {
+        rest = der_next(rest)?.2;
+    }
+
+    // serial, signature, issuer, validity, subject
+    for _ in 0..5 {
+        rest = der_next(rest)?.2;
+    }
+
+    let (_, _, after) = der_next(rest)?;
+    Some(&rest[..rest.len() - after.len()])
+}
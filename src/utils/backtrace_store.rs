@@ -147,5 +147,5 @@ pub extern "Rust" fn custom_pre_backtrace() {
 
 #[no_mangle]
 pub extern "Rust" fn custom_halt() {
-    esp_hal::system::software_reset();
+    super::reset_journal::restart(crate::structs::ResetReason::Panic, "");
 }
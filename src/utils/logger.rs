@@ -1,9 +1,4 @@
 use crate::state::{ota_state, sleep_state};
-use alloc::string::String;
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
-
-const MAX_LOGS_SIZE: usize = 100;
-pub static LOGS_CHANNEL: Channel<CriticalSectionRawMutex, String, MAX_LOGS_SIZE> = Channel::new();
 
 #[cfg(feature = "release_build")]
 pub const FILTER_MAX: log::LevelFilter = log::LevelFilter::Info;
@@ -57,12 +52,8 @@ impl log::Log for FkmLogger {
         esp_println::println!("{}{} - {}{}", color, record.level(), record.args(), reset);
 
         if !ota_state() && !sleep_state() {
-            if LOGS_CHANNEL.is_full() {
-                _ = LOGS_CHANNEL.try_receive();
-            }
-
             let msg = alloc::format!("{}{} - {}{}", color, record.level(), record.args(), reset);
-            _ = LOGS_CHANNEL.try_send(msg);
+            staff_at_device::logs::push(msg);
         }
     }
 
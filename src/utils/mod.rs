@@ -1,8 +1,13 @@
 pub mod backtrace_store;
 pub mod logger;
+pub mod nvs_json;
+pub mod reset_journal;
 pub mod rolling_average;
 pub mod signaled_mutex;
 
+use crate::structs::ResetCause;
+use esp_hal::rtc_cntl::SocResetReason;
+
 pub fn set_brownout_detection(state: bool) {
     unsafe {
         let rtc_cntl = &*esp32c3::RTC_CNTL::ptr();
@@ -10,6 +15,35 @@ pub fn set_brownout_detection(state: bool) {
     }
 }
 
+/// Hardware cause of last reset (panic restarts are software resets)
+pub fn reset_cause() -> ResetCause {
+    match esp_hal::system::reset_reason() {
+        Some(SocResetReason::ChipPowerOn) => ResetCause::PowerOn,
+        Some(SocResetReason::CoreSw | SocResetReason::Cpu0Sw) => ResetCause::Software,
+        Some(SocResetReason::CoreDeepSleep) => ResetCause::DeepSleep,
+        Some(
+            SocResetReason::CoreMwdt0
+            | SocResetReason::CoreMwdt1
+            | SocResetReason::CoreRtcWdt
+            | SocResetReason::Cpu0Mwdt0
+            | SocResetReason::Cpu0Mwdt1
+            | SocResetReason::Cpu0RtcWdt
+            | SocResetReason::SysRtcWdt
+            | SocResetReason::SysSuperWdt,
+        ) => ResetCause::Watchdog,
+        Some(SocResetReason::SysBrownOut) => ResetCause::Brownout,
+        _ => ResetCause::Other,
+    }
+}
+
+/// Rssi of connected access point (None if not connected or radio is off)
+pub fn wifi_rssi() -> Option<i8> {
+    let mut ap_info: esp_wifi_sys::include::wifi_ap_record_t = unsafe { core::mem::zeroed() };
+    let res = unsafe { esp_wifi_sys::include::esp_wifi_sta_get_ap_info(&mut ap_info) };
+
+    (res == 0).then_some(ap_info.rssi)
+}
+
 /// This function returns value with maximum of signed integer
 /// (2147483647) to easily store it in postgres db as integer
 pub fn get_efuse_u32() -> u32 {
@@ -0,0 +1,58 @@
+use esp_hal_wifimanager::Nvs;
+use serde::{de::DeserializeOwned, Serialize};
+use staff_at_device::KvStore;
+
+const MAX_VALUE_SIZE: usize = 1024;
+
+/// Reads json value (prefixed with u16 len) saved using [`store`]
+pub async fn load<T: DeserializeOwned>(nvs: &Nvs, key: &[u8]) -> Option<T> {
+    let mut buf = alloc::vec![0; MAX_VALUE_SIZE + 2];
+    nvs.get_key(key, &mut buf).await.ok()?;
+
+    let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    if len == 0 || len > MAX_VALUE_SIZE {
+        return None;
+    }
+
+    serde_json::from_slice(&buf[2..2 + len]).ok()
+}
+
+/// Overwrites value under given key (returns false if write failed)
+pub async fn store<T: Serialize>(nvs: &Nvs, key: &[u8], value: &T) -> bool {
+    let Ok(json) = serde_json::to_vec(value) else {
+        return false;
+    };
+
+    if json.len() > MAX_VALUE_SIZE {
+        log::error!("nvs_json value too big ({}b)", json.len());
+        return false;
+    }
+
+    let mut buf = alloc::vec::Vec::with_capacity(json.len() + 2);
+    buf.extend_from_slice(&(json.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&json);
+
+    _ = nvs.invalidate_key(key).await;
+    nvs.append_key(key, &buf).await.is_ok()
+}
+
+pub async fn remove(nvs: &Nvs, key: &[u8]) {
+    _ = nvs.invalidate_key(key).await;
+}
+
+/// [`KvStore`] over device nvs (used by shared device logic)
+pub struct JsonNvs<'a>(pub &'a Nvs);
+
+impl KvStore for JsonNvs<'_> {
+    async fn load<T: DeserializeOwned>(&self, key: &[u8]) -> Option<T> {
+        load(self.0, key).await
+    }
+
+    async fn store<T: Serialize>(&self, key: &[u8], value: &T) -> bool {
+        store(self.0, key, value).await
+    }
+
+    async fn remove(&self, key: &[u8]) {
+        remove(self.0, key).await
+    }
+}
@@ -0,0 +1,172 @@
+use crate::structs::{ResetReason, ResetRecord};
+use alloc::string::String;
+use alloc::vec::Vec;
+use embassy_time::Instant;
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::FlashStorage;
+use serde::{Deserialize, Serialize};
+
+/// End of nvs partition is used by backtrace store (message + u16 len)
+const BACKTRACE_STORE_LEN: usize = 1024 + 2;
+
+/// Reason recorded before reset: magic, reason code, uptime (u64), context len, context
+const PENDING_LEN: usize = 64;
+const PENDING_HEADER_LEN: usize = 11;
+const PENDING_MAGIC: u8 = 0xA5;
+
+/// Json list of entries (prefixed with u16 len), newest first
+const JOURNAL_LEN: usize = 512;
+const MAX_JOURNAL_ENTRIES: usize = 4;
+
+#[derive(Serialize, Deserialize)]
+struct JournalEntry {
+    record: ResetRecord,
+    reported: bool,
+}
+
+/// Records reason and resets device. Safe to call from panic handler
+/// (nothing is allocated)
+pub fn restart(reason: ResetReason, context: &str) -> ! {
+    record(reason, context);
+    esp_hal::system::software_reset();
+}
+
+fn record(reason: ResetReason, context: &str) {
+    let Some(offset) = pending_offset() else {
+        return;
+    };
+
+    // context is cut to fit (on char boundary)
+    let mut len = context.len().min(PENDING_LEN - PENDING_HEADER_LEN);
+    while !context.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    let mut buf = [0; PENDING_LEN];
+    buf[0] = PENDING_MAGIC;
+    buf[1] = reason as u8;
+    buf[2..10].copy_from_slice(&Instant::now().as_millis().to_be_bytes());
+    buf[10] = len as u8;
+    buf[PENDING_HEADER_LEN..PENDING_HEADER_LEN + len].copy_from_slice(&context.as_bytes()[..len]);
+
+    _ = FlashStorage::new().write(offset, &buf);
+}
+
+/// Adds current boot to journal (hardware cause with reason recorded
+/// before reset) and clears recorded reason
+pub fn load() {
+    let Some(offset) = pending_offset() else {
+        return;
+    };
+
+    let mut flash = FlashStorage::new();
+    let mut buf = [0; PENDING_LEN];
+    if let Err(e) = flash.read(offset, &mut buf) {
+        log::error!("[RESET] Read failed: {e:?}");
+        return;
+    }
+
+    let mut record = ResetRecord {
+        cause: super::reset_cause(),
+        reason: None,
+        context: None,
+        uptime_ms: None,
+    };
+
+    if buf[0] == PENDING_MAGIC {
+        let len = (buf[10] as usize).min(PENDING_LEN - PENDING_HEADER_LEN);
+        let context = &buf[PENDING_HEADER_LEN..PENDING_HEADER_LEN + len];
+
+        record.reason = ResetReason::from_code(buf[1]);
+        record.context = core::str::from_utf8(context)
+            .ok()
+            .filter(|context| !context.is_empty())
+            .map(String::from);
+        record.uptime_ms = buf[2..10].try_into().ok().map(u64::from_be_bytes);
+
+        _ = flash.write(offset, &[0x00]);
+    }
+
+    log::warn!("[RESET] Last reset: {record:?}");
+
+    let mut entries = read_entries();
+    entries.insert(
+        0,
+        JournalEntry {
+            record,
+            reported: false,
+        },
+    );
+    entries.truncate(MAX_JOURNAL_ENTRIES);
+    write_entries(&mut entries);
+}
+
+/// Resets not sent to server yet (newest first)
+pub fn unreported() -> Vec<ResetRecord> {
+    read_entries()
+        .into_iter()
+        .filter(|entry| !entry.reported)
+        .map(|entry| entry.record)
+        .collect()
+}
+
+pub fn mark_reported() {
+    let mut entries = read_entries();
+    if entries.iter().all(|entry| entry.reported) {
+        return;
+    }
+
+    entries.iter_mut().for_each(|entry| entry.reported = true);
+    write_entries(&mut entries);
+}
+
+fn read_entries() -> Vec<JournalEntry> {
+    let Some(offset) = journal_offset() else {
+        return Vec::new();
+    };
+
+    let mut buf = alloc::vec![0; JOURNAL_LEN];
+    if FlashStorage::new().read(offset, &mut buf).is_err() {
+        return Vec::new();
+    }
+
+    // erased flash reads as 0xffff
+    let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    if len == 0 || len > JOURNAL_LEN - 2 {
+        return Vec::new();
+    }
+
+    serde_json::from_slice(&buf[2..2 + len]).unwrap_or_default()
+}
+
+/// Oldest entries are dropped if journal doesn't fit
+fn write_entries(entries: &mut Vec<JournalEntry>) {
+    let Some(offset) = journal_offset() else {
+        return;
+    };
+
+    let json = loop {
+        match serde_json::to_vec(entries) {
+            Ok(json) if json.len() <= JOURNAL_LEN - 2 => break json,
+            Ok(_) if !entries.is_empty() => _ = entries.pop(),
+            _ => return,
+        }
+    };
+
+    let mut buf = Vec::with_capacity(json.len() + 2);
+    buf.extend_from_slice(&(json.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&json);
+
+    if let Err(e) = FlashStorage::new().write(offset, &buf) {
+        log::error!("[RESET] Journal write failed: {e:?}");
+    }
+}
+
+fn pending_offset() -> Option<u32> {
+    let (nvs_offset, nvs_size) = esp_hal_wifimanager::Nvs::read_nvs_partition_offset()?;
+    Some((nvs_offset + nvs_size - BACKTRACE_STORE_LEN - PENDING_LEN) as u32)
+}
+
+fn journal_offset() -> Option<u32> {
+    pending_offset().map(|offset| offset - JOURNAL_LEN as u32)
+}
@@ -1,427 +1,653 @@
 use crate::{
-    consts::WS_RETRY_MS,
-    state::{ota_state, GlobalState},
-    structs::{ApiError, FromPacket, TimerPacket, TimerPacketInner},
+    auth::DeviceAuth,
+    consts::{
+        OTA_RESUME_TIMEOUT_MS, REQUEST_LATENCY_SAMPLES, WS_MAX_REDIRECTS,
+        WS_SERVER_ERROR_BACKOFF_MULTIPLIER,
+    },
+    endpoints::Endpoints,
+    ota::OtaUpdater,
+    state::GlobalState,
+    structs::{
+        ApiError, FromPacket, HealthCheck, ResetReason, ResetReportAckPacket, TimerPacket,
+        TimerPacketInner, TlsFailure, UpdateFailure, UpdateFailureKind, WsCloseKind,
+    },
+    tls::TlsSettings,
+    utils::{reset_journal, rolling_average::RollingAverage},
 };
-use alloc::{rc::Rc, string::ToString};
+use alloc::{rc::Rc, string::ToString, vec::Vec};
 use core::str::FromStr;
 use embassy_net::{tcp::TcpSocket, IpAddress, Stack};
-use embassy_sync::{
-    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, pubsub::PubSubChannel,
-    signal::Signal,
-};
-use embassy_time::{Duration, Instant, Timer, WithTimeout};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex, signal::Signal};
+use embassy_time::{Duration, Instant};
 use embedded_io_async::Write;
-use embedded_tls::{Aes128GcmSha256, NoVerify, TlsConfig, TlsConnection, TlsContext};
-use esp_hal_ota::Ota;
-use esp_storage::FlashStorage;
-use rand_core::OsRng;
+use embedded_tls::{Aes128GcmSha256, TlsConnection};
+use staff_at_device::{
+    deliver_with_retries, http,
+    session::{
+        connection_loop, ConnectError, Connector, Frame, Mailbox, SessionHandler, WsConnection,
+        REQUEST_TIMEOUT_MS,
+    },
+    Link,
+};
 use ws_framer::{WsFrame, WsFrameOwned, WsRxFramer, WsTxFramer, WsUrl, WsUrlOwned};
 
-static FRAME_CHANNEL: Channel<CriticalSectionRawMutex, WsFrameOwned, 10> = Channel::new();
-static TAGGED_RETURN: PubSubChannel<CriticalSectionRawMutex, (u64, TimerPacket), 20, 20, 4> =
-    PubSubChannel::new();
+static MAILBOX: Mailbox = Mailbox::new();
+static REQUEST_STATS: Mutex<CriticalSectionRawMutex, RequestStats> = Mutex::new(RequestStats {
+    timeouts: 0,
+    latency_ms: RollingAverage::new(),
+});
+
+/// Tagged requests since boot (every try is counted separately)
+struct RequestStats {
+    timeouts: u32,
+    latency_ms: RollingAverage<REQUEST_LATENCY_SAMPLES>,
+}
+
+/// Raw upgrade response kept for headers (only start is needed)
+const MAX_HTTP_RESPONSE_LEN: usize = 1024;
 
 #[embassy_executor::task]
 pub async fn ws_task(
     stack: Stack<'static>,
-    ws_url: WsUrlOwned,
+    endpoints: Endpoints,
+    tls_settings: Result<TlsSettings, TlsFailure>,
+    device_auth: Option<DeviceAuth>,
     global_state: GlobalState,
     ws_sleep_sig: Rc<Signal<CriticalSectionRawMutex, bool>>,
     ws_connect_signal: Rc<Signal<CriticalSectionRawMutex, ()>>,
 ) {
-    log::debug!("ws_url: {ws_url:?}");
-
     let mut rx_buf = [0; 8192];
     let mut tx_buf = [0; 8192];
-    let mut ws_rx_buf = alloc::vec![0; 8192];
-    let mut ws_tx_buf = alloc::vec![0; 8192];
 
-    // tls buffers
-    let mut ssl_rx_buf = alloc::vec::Vec::new();
-    let mut ssl_tx_buf = alloc::vec::Vec::new();
+    let mut ota = OtaUpdater::new().expect("Ota init failed");
+    ota.restore(&global_state.nvs).await;
+
+    let mut connector = ServerConnector {
+        stack,
+        endpoints,
+        tls_settings,
+        device_auth,
+        global_state: global_state.clone(),
+        redirect: None,
+        redirects: 0,
+        auth_retried: false,
+        rx_buf: &mut rx_buf,
+        tx_buf: &mut tx_buf,
+        ws_rx_buf: alloc::vec![0; 8192],
+        ws_tx_buf: alloc::vec![0; 8192],
+
+        // tls buffers (allocated on first wss endpoint)
+        ssl_rx_buf: Vec::new(),
+        ssl_tx_buf: Vec::new(),
+    };
 
-    if ws_url.secure {
-        ssl_rx_buf.resize(16640, 0);
-        ssl_tx_buf.resize(16640, 0);
-    }
+    let mut handler = UpdateHandler {
+        global_state: global_state.clone(),
+        ota,
+        resume_deadline: None,
+        last_update_percentage: 101,
+    };
 
-    loop {
-        let ws_fut = ws_loop(
-            &global_state,
-            ws_url.as_ref(),
-            stack,
-            &mut rx_buf,
-            &mut tx_buf,
-            &mut ws_rx_buf,
-            &mut ws_tx_buf,
-            &mut ssl_rx_buf,
-            &mut ssl_tx_buf,
-            &ws_connect_signal,
-        );
+    connection_loop(
+        &*global_state,
+        &MAILBOX,
+        &mut connector,
+        &mut handler,
+        &*ws_sleep_sig,
+        &*ws_connect_signal,
+    )
+    .await
+}
 
-        let res = embassy_futures::select::select(ws_fut, ws_sleep_sig.wait()).await;
+/// Connects to configured endpoints (following redirects), tls and upgrade
+struct ServerConnector<'b> {
+    stack: Stack<'static>,
+    endpoints: Endpoints,
+    tls_settings: Result<TlsSettings, TlsFailure>,
+    device_auth: Option<DeviceAuth>,
+    global_state: GlobalState,
 
-        match res {
-            embassy_futures::select::Either::First(res) => {
-                if let Err(e) = res {
-                    log::error!("Ws_loop errored! {e:?}");
-                }
-            }
-            embassy_futures::select::Either::Second(sleep) => {
-                if sleep {
-                    loop {
-                        let sleep = ws_sleep_sig.wait().await;
-                        if !sleep {
-                            break;
-                        }
-                    }
-                }
+    /// Target of last redirect (tried instead of next endpoint)
+    redirect: Option<WsUrlOwned>,
+    redirects: u8,
+    auth_retried: bool,
+
+    rx_buf: &'b mut [u8],
+    tx_buf: &'b mut [u8],
+    ws_rx_buf: Vec<u8>,
+    ws_tx_buf: Vec<u8>,
+    ssl_rx_buf: Vec<u8>,
+    ssl_tx_buf: Vec<u8>,
+}
+
+impl Connector for ServerConnector<'_> {
+    type Connection<'a>
+        = ServerConnection<'a>
+    where
+        Self: 'a;
+
+    async fn connect(&mut self) -> Result<ServerConnection<'_>, ConnectError> {
+        let ws_url = match self.redirect.take() {
+            Some(ws_url) => ws_url,
+            None => {
+                let Some((ws_url, endpoint)) = self.endpoints.resolve(self.stack).await else {
+                    return Err(ConnectError::Failed);
+                };
+
+                log::info!("[WS] Connecting to {} ({})", endpoint.url, endpoint.index);
+                self.global_state.device_status.lock().await.ws_endpoint = Some(endpoint);
+                self.redirects = 0;
+                ws_url
             }
-        }
+        };
 
-        Timer::after_millis(500).await;
-    }
-}
+        log::debug!("ws_url: {ws_url:?}");
+        let ws_url = ws_url.as_ref();
 
-// TODO: maybe make less args?
-#[allow(clippy::too_many_arguments)]
-async fn ws_loop(
-    global_state: &GlobalState,
-    ws_url: WsUrl<'_>,
-    stack: Stack<'static>,
-    rx_buf: &mut [u8],
-    tx_buf: &mut [u8],
-    ws_rx_buf: &mut [u8],
-    ws_tx_buf: &mut [u8],
-    ssl_rx_buf: &mut [u8],
-    ssl_tx_buf: &mut [u8],
-    ws_connect_signal: &Rc<Signal<CriticalSectionRawMutex, ()>>,
-) -> Result<(), ()> {
-    loop {
-        {
-            global_state.led(false).await;
-            global_state.state.lock().await.server_connected = Some(false);
-            log::info!("Server disconnected!");
+        if ws_url.secure && self.ssl_rx_buf.is_empty() {
+            self.ssl_rx_buf.resize(16640, 0);
+            self.ssl_tx_buf.resize(16640, 0);
         }
 
         let ip = if let Ok(addr) = embassy_net::Ipv4Address::from_str(ws_url.ip) {
             addr
         } else {
-            let dns_resolver = embassy_net::dns::DnsSocket::new(stack);
+            let dns_resolver = embassy_net::dns::DnsSocket::new(self.stack);
             let res = dns_resolver
                 .query(ws_url.ip, embassy_net::dns::DnsQueryType::A)
                 .await;
 
             let Ok(res) = res else {
                 log::error!("[WS]Dns resolver error: {:?}", res.expect_err(""));
-                Timer::after_millis(1000).await;
-                continue;
+                return Err(ConnectError::Failed);
             };
 
             let Some(IpAddress::Ipv4(addr)) = res.first() else {
                 log::error!("[WS]Dns resolver empty vec");
-                Timer::after_millis(1000).await;
-                continue;
+                return Err(ConnectError::Failed);
             };
             *addr
         };
 
-        let mut socket = TcpSocket::new(stack, rx_buf, tx_buf);
+        let mut socket = TcpSocket::new(self.stack, self.rx_buf, self.tx_buf);
         socket.set_timeout(Some(embassy_time::Duration::from_secs(15)));
 
         let remote_endpoint = (ip, ws_url.port);
         let r = socket.connect(remote_endpoint).await;
         if let Err(e) = r {
             log::error!("connect error: {:?}", e);
-            Timer::after_millis(WS_RETRY_MS).await;
-            continue;
+            return Err(ConnectError::Failed);
         }
 
         let mut socket = if ws_url.secure {
-            let mut tls = TlsConnection::new(socket, ssl_rx_buf, ssl_tx_buf);
+            let mut tls = TlsConnection::new(socket, &mut self.ssl_rx_buf, &mut self.ssl_tx_buf);
 
-            let config: TlsConfig<'_, Aes128GcmSha256> =
-                TlsConfig::new().with_server_name(ws_url.host);
-            tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
-                .await
-                .map_err(|_| ())?;
+            let res = match &self.tls_settings {
+                Ok(tls_settings) => crate::tls::open(&mut tls, ws_url.host, tls_settings).await,
+                Err(e) => Err(e.clone()),
+            };
+
+            if let Err(e) = res {
+                log::error!("[WS] Tls failed ({:?}): {}", e.kind, e.detail);
+                self.global_state.device_status.lock().await.last_tls_error = Some(e);
+                return Err(ConnectError::Failed);
+            }
 
             WsSocket::Tls(tls)
         } else {
             WsSocket::Raw(socket)
         };
 
-        {
-            global_state.led(true).await;
-            global_state.state.lock().await.server_connected = Some(true);
-            ws_connect_signal.signal(());
-            log::info!("Server connected!");
-        }
-
         log::info!("connected!");
-        let mut tx_framer = WsTxFramer::new(true, ws_tx_buf);
-        let mut rx_framer = WsRxFramer::new(ws_rx_buf);
+        let mut tx_framer = WsTxFramer::new(true, &mut self.ws_tx_buf);
+        let mut rx_framer = WsRxFramer::new(&mut self.ws_rx_buf);
 
+        let device_id = crate::utils::get_efuse_u32();
+        // redirect target can have its own query
+        let separator = if ws_url.path.contains('?') { '&' } else { '?' };
         let path = alloc::format!(
-            "{}?id={}&ver={}&hw={}&firmware={}",
+            "{}{}id={}&ver={}&hw={}&firmware={}{}",
             ws_url.path,
-            crate::utils::get_efuse_u32(),
+            separator,
+            device_id,
             crate::version::VERSION,
             crate::version::HW_VER,
             crate::version::FIRMWARE,
+            self.device_auth
+                .as_ref()
+                .map(|auth| auth.query_params(
+                    device_id,
+                    crate::state::time_valid().then(crate::state::current_epoch)
+                ))
+                .unwrap_or_default(),
         );
 
         socket
             .write_all(tx_framer.generate_http_upgrade(ws_url.host, &path, None))
-            .await
-            .map_err(|_| ())?;
+            .await?;
 
-        loop {
-            let n = socket.read(rx_framer.mut_buf()).await.map_err(|_| ())?;
+        // framer returns only status code, location header is read from raw response
+        let mut response = Vec::new();
+        let code = loop {
+            let n = socket.read(rx_framer.mut_buf()).await?;
             if n == 0 {
                 log::error!("error while reading http response");
-                return Err(());
+                return Err(ConnectError::Failed);
+            }
+
+            if response.len() < MAX_HTTP_RESPONSE_LEN {
+                response.extend_from_slice(&rx_framer.mut_buf()[..n]);
+            }
+
+            if let Some(code) = rx_framer.process_http_response(n) {
+                break code;
             }
+        };
 
-            let res = rx_framer.process_http_response(n);
-            if let Some(code) = res {
-                log::info!("http_resp_code: {code}");
-                break;
+        log::info!("http_resp_code: {code}");
+        match code {
+            101 => {}
+            code if http::is_redirect(code) => {
+                let Some(location) = http::header(&response, "location") else {
+                    log::error!("[WS] Redirect ({code}) without location!");
+                    return Err(ConnectError::Failed);
+                };
+
+                let Some(url) = http::redirect_url(
+                    location,
+                    ws_url.secure,
+                    ws_url.ip,
+                    ws_url.port,
+                    ws_url.path,
+                ) else {
+                    log::error!("[WS] Redirect ({code}) to insecure url refused: {location}");
+                    return Err(ConnectError::Failed);
+                };
+
+                log::warn!("[WS] Redirected ({code}) to {url}");
+                let Some(redirect_url) = WsUrl::from_str(&url) else {
+                    log::error!("[WS] Cannot parse redirect url: {url}");
+                    return Err(ConnectError::Failed);
+                };
+
+                self.redirect = Some(WsUrlOwned::new(&redirect_url));
+                return Err(ConnectError::Redirect);
+            }
+            code if http::is_unauthorized(code) => {
+                log::error!("[WS] Device unauthorized by server ({code})!");
+                unsafe { crate::state::UNAUTHORIZED_STATE = true };
+                return Err(ConnectError::Unauthorized);
+            }
+            500..=599 => {
+                log::error!("[WS] Server error ({code})!");
+                return Err(ConnectError::ServerError);
+            }
+            _ => {
+                log::error!("[WS] Upgrade failed ({code})!");
+                return Err(ConnectError::Failed);
             }
         }
 
-        FRAME_CHANNEL
-            .send(WsFrameOwned::Ping(alloc::vec::Vec::new()))
-            .await;
+        crate::health::check_passed(HealthCheck::WsUpgrade);
+        unsafe { crate::state::UNAUTHORIZED_STATE = false };
+        self.endpoints.mark_working(&self.global_state.nvs).await;
 
-        loop {
-            let res = ws_rw(
-                &mut rx_framer,
-                &mut tx_framer,
-                global_state.clone(),
-                &mut socket,
-            )
-            .await;
+        Ok(ServerConnection {
+            socket,
+            rx_framer,
+            tx_framer,
+        })
+    }
 
-            if let Err(e) = res {
-                if ota_state() {
-                    Timer::after_millis(5000).await;
-                    esp_hal::system::software_reset();
+    fn failed(&mut self, error: ConnectError) -> Option<u64> {
+        match error {
+            ConnectError::Redirect if self.redirects < WS_MAX_REDIRECTS => {
+                self.redirects += 1;
+                return None;
+            }
+            ConnectError::Redirect => {
+                log::error!("[WS] Too many redirects!");
+                self.redirect = None;
+                self.endpoints.next();
+            }
+            ConnectError::Unauthorized | ConnectError::ServerError => {
+                self.endpoints.next();
+                return Some(WS_SERVER_ERROR_BACKOFF_MULTIPLIER);
+            }
+            ConnectError::AuthRequired => {
+                // challenge is answered right away (only once, server keeps
+                // rejecting it if device secret or clock is wrong)
+                if !self.auth_retried && crate::auth::has_server_nonce() {
+                    self.auth_retried = true;
+                    return None;
                 }
-
-                log::error!("ws_rw_error: {e:?}");
-                Timer::after_millis(WS_RETRY_MS).await;
-                break;
             }
+            ConnectError::Failed => self.endpoints.next(),
         }
+
+        Some(1)
+    }
+
+    fn session_ended(&mut self) {
+        self.auth_retried = false;
     }
 }
 
-async fn ws_rw(
-    framer_rx: &mut WsRxFramer<'_>,
-    framer_tx: &mut WsTxFramer<'_>,
+/// Updates (and resume of interrupted ones) and auth challenges
+struct UpdateHandler {
     global_state: GlobalState,
-    tls: &mut WsSocket<'_, '_>,
-) -> Result<(), ()> {
-    let mut ota = Ota::new(FlashStorage::new()).map_err(|_| ())?;
-    let tagged_publisher = TAGGED_RETURN.publisher().map_err(|_| ())?;
-    let recv = FRAME_CHANNEL.receiver();
-
-    let mut last_update_percentage = 101;
-    loop {
-        let read_fut = tls.read(framer_rx.mut_buf());
-        let write_fut = recv.receive();
-
-        let n = match embassy_futures::select::select(read_fut, write_fut).await {
-            embassy_futures::select::Either::First(read_res) => read_res,
-            embassy_futures::select::Either::Second(write_frame) => {
-                let data = framer_tx.frame(write_frame.into_ref());
-                tls.write_all(data).await.map_err(|_| ())?;
-
-                continue;
-            }
-        }?;
+    ota: OtaUpdater,
+    resume_deadline: Option<Instant>,
+    last_update_percentage: u8,
+}
 
-        if n == 0 {
-            log::warn!("read_n: 0");
-            return Err(());
+impl SessionHandler for UpdateHandler {
+    async fn started(&mut self, conn: &mut impl WsConnection) -> Result<(), ()> {
+        self.resume_deadline = None;
+        self.last_update_percentage = 101;
+
+        // update interrupted by disconnect (or restart) - ask server to continue
+        if let Some(session) = self.ota.session() {
+            log::info!("[OTA] Requesting resume from {}b", session.written);
+            let resume = TimerPacket {
+                tag: None,
+                data: TimerPacketInner::UpdateResume {
+                    version: session.version.clone(),
+                    size: session.size,
+                    crc: session.crc,
+                    offset: session.written,
+                },
+            };
+            conn.write_packet(&resume).await?;
+
+            self.resume_deadline =
+                Some(Instant::now() + Duration::from_millis(OTA_RESUME_TIMEOUT_MS));
         }
 
-        framer_rx.revolve_write_offset(n);
-        while let Some(frame) = framer_rx.process_data() {
-            match frame {
-                WsFrame::Text(text) => match serde_json::from_str::<TimerPacket>(text) {
-                    Ok(timer_packet) => {
-                        if let Some(tag) = timer_packet.tag {
-                            tagged_publisher.publish((tag, timer_packet.clone())).await;
-                        }
-
-                        match timer_packet.data {
-                            TimerPacketInner::DeviceSettings { added } => {
-                                let mut state = global_state.state.lock().await;
-                                state.device_added = Some(added);
-
-                                if !added {
-                                    crate::ws::send_packet(crate::structs::TimerPacket {
-                                        tag: None,
-                                        data: crate::structs::TimerPacketInner::Add {
-                                            firmware: alloc::string::ToString::to_string(
-                                                crate::version::FIRMWARE,
-                                            ),
-                                        },
-                                    })
-                                    .await;
-                                }
-                            }
-                            TimerPacketInner::ApiError(e) => {
-                                log::error!("Api Error: {e:?}");
-                            }
-                            TimerPacketInner::EpochTime { current_epoch } => unsafe {
-                                crate::state::EPOCH_BASE = current_epoch - Instant::now().as_secs();
-                            },
-                            TimerPacketInner::StartUpdate {
-                                version,
-                                build_time: _,
-                                size,
-                                crc,
-                                firmware,
-                            } => {
-                                if firmware != crate::version::FIRMWARE {
-                                    continue;
-                                }
-
-                                log::info!("Start update: {firmware}/{version}");
-                                log::info!("Begin update size: {size} crc: {crc}");
-                                ota.ota_begin(size, crc).map_err(|_| ())?;
-                                unsafe {
-                                    crate::state::OTA_STATE = true;
-                                }
-
-                                global_state.led_blink(5, 25).await;
-
-                                FRAME_CHANNEL
-                                    .send(WsFrameOwned::Binary(alloc::vec::Vec::new()))
-                                    .await;
-                            }
-                            _ => {}
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("timer_packet_fail: {e:?}\nTried to parse:\n{text}\n\n");
-                    }
-                },
-                WsFrame::Binary(data) => {
-                    if !crate::state::ota_state() {
-                        continue;
-                    }
-
-                    let res = ota.ota_write_chunk(data);
-                    if res == Ok(true) {
-                        log::info!("OTA complete! Veryfying..");
-                        if ota.ota_flush(true, true).is_ok() {
-                            log::info!("OTA restart!");
-                            esp_hal::system::software_reset();
-                        } else {
-                            log::error!("OTA flash verify failed!");
-                        }
-                    }
-
-                    let progress = (ota.get_ota_progress() * 100.0) as u8;
-                    log::info!("Update progress: {progress}%");
-
-                    if progress != last_update_percentage && progress % 10 == 0 {
-                        global_state.led_blink(1, 25).await;
-                        last_update_percentage = progress;
-                    }
-
-                    FRAME_CHANNEL
-                        .send(WsFrameOwned::Binary(alloc::vec::Vec::new()))
-                        .await;
+        Ok(())
+    }
+
+    async fn packet(
+        &mut self,
+        conn: &mut impl WsConnection,
+        packet: TimerPacket,
+    ) -> Result<(), ()> {
+        match packet.data {
+            TimerPacketInner::AuthChallenge { nonce } => {
+                crate::auth::set_server_nonce(nonce);
+            }
+            TimerPacketInner::StartUpdate {
+                version,
+                build_time: _,
+                size,
+                crc,
+                firmware,
+                force,
+            } => {
+                if firmware != crate::version::FIRMWARE {
+                    let failure = update_failure(UpdateFailure::new(
+                        UpdateFailureKind::FirmwareMismatch,
+                        &firmware,
+                    ));
+                    return conn.write_packet(&failure).await;
                 }
-                WsFrame::Close(_, _) => todo!(),
-                WsFrame::Ping(_) => {
-                    FRAME_CHANNEL
-                        .send(WsFrameOwned::Pong(alloc::vec::Vec::new()))
-                        .await;
+
+                log::info!("Start update: {firmware}/{version}");
+                log::info!("Begin update size: {size} crc: {crc}");
+                self.resume_deadline = None;
+                self.ota
+                    .begin(&self.global_state.nvs, version, size, crc, force)
+                    .await?;
+
+                self.global_state.led_blink(5, 25).await;
+                conn.write_frame(Frame::Binary(Vec::new())).await?;
+            }
+            TimerPacketInner::UpdateResumeAck { offset } => {
+                self.resume_deadline = None;
+
+                let Some(session) = self.ota.session() else {
+                    return Ok(());
+                };
+
+                if offset != session.written {
+                    log::error!(
+                        "[OTA] Resume offset mismatch ({offset} != {})",
+                        session.written
+                    );
+                    self.ota.abort(&self.global_state.nvs).await;
+                    return Ok(());
                 }
-                _ => {}
+
+                log::info!("[OTA] Resuming update from {offset}b");
+                conn.write_frame(Frame::Binary(Vec::new())).await?;
             }
+            _ => {}
         }
+
+        Ok(())
     }
-}
 
-pub async fn send_packet(packet: TimerPacket) {
-    match serde_json::to_string(&packet) {
-        Ok(string) => {
-            FRAME_CHANNEL.send(WsFrameOwned::Text(string)).await;
+    async fn binary(&mut self, conn: &mut impl WsConnection, data: Vec<u8>) -> Result<(), ()> {
+        if !crate::state::ota_state() || self.ota.session().is_none() {
+            return Ok(());
+        }
+
+        let nvs = &self.global_state.nvs;
+        let res = self.ota.write_chunk(nvs, &data).await;
+        if res.is_err() {
+            // chunk isn't acked, server would continue from wrong offset
+            log::error!("[OTA] Flash write failed!");
+            self.ota.abort(nvs).await;
+            let failure = UpdateFailure::new(UpdateFailureKind::VerifyFailed, "flash write failed");
+            return conn.write_packet(&update_failure(failure)).await;
         }
-        Err(e) => {
-            log::error!("send_packet json to_string failed: {e:?}");
+
+        if res == Ok(true) {
+            log::info!("OTA complete! Veryfying..");
+            let version = self.ota.session().map(|s| s.version.clone());
+            match self.ota.finish(nvs).await {
+                Ok(_) => {
+                    log::info!("OTA restart!");
+                    reset_journal::restart(
+                        ResetReason::OtaComplete,
+                        version.as_deref().unwrap_or_default(),
+                    );
+                }
+                Err(e) => {
+                    log::error!("OTA rejected ({:?}): {}", e.kind, e.detail);
+                    return conn.write_packet(&update_failure(e)).await;
+                }
+            }
         }
+
+        let progress = self.ota.progress();
+        log::info!("Update progress: {progress}%");
+
+        if progress != self.last_update_percentage && progress % 10 == 0 {
+            self.global_state.led_blink(1, 25).await;
+            self.last_update_percentage = progress;
+        }
+
+        conn.write_frame(Frame::Binary(Vec::new())).await
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.resume_deadline
+    }
+
+    async fn timeout(&mut self) {
+        log::error!("[OTA] Server didn't ack update resume!");
+        self.ota.abort(&self.global_state.nvs).await;
+        self.resume_deadline = None;
+    }
+
+    async fn closed(&mut self, kind: WsCloseKind) {
+        if kind == WsCloseKind::AuthFailed {
+            unsafe { crate::state::UNAUTHORIZED_STATE = true };
+        }
+    }
+}
+
+/// Journal entries are marked as reported only after server acks them
+/// (otherwise they are sent again after next connect)
+pub async fn send_reset_report() {
+    let resets = reset_journal::unreported();
+    if resets.is_empty() {
+        return;
+    }
+
+    match send_request::<ResetReportAckPacket>(TimerPacketInner::ResetReport { resets }).await {
+        Ok(_) => reset_journal::mark_reported(),
+        Err(e) => log::error!("[WS] Reset report not acked: {:?}", e.error),
     }
 }
 
-#[allow(dead_code)]
-pub fn clear_frame_channel() {
-    FRAME_CHANNEL.clear();
+pub async fn request_epoch_time() {
+    send_packet(TimerPacket {
+        tag: None,
+        data: TimerPacketInner::EpochTimeRequest,
+    })
+    .await;
+}
+
+/// Server says device time is wrong, it isn't trusted until next sync
+pub async fn reset_time() {
+    log::warn!("[TIME] Time rejected by server, requesting sync");
+    unsafe { crate::state::TIME_VALID = false };
+    request_epoch_time().await;
+}
+
+fn update_failure(failure: UpdateFailure) -> TimerPacket {
+    TimerPacket {
+        tag: None,
+        data: TimerPacketInner::UpdateFailed(failure),
+    }
+}
+
+pub async fn send_packet(packet: TimerPacket) {
+    MAILBOX.send_packet(packet).await;
+}
+
+/// [`Link`] over ws connection (used by shared device logic)
+pub struct WsLink;
+
+impl Link for WsLink {
+    async fn send_packet(&self, packet: TimerPacket) {
+        send_packet(packet).await;
+    }
+
+    async fn send_request<T: FromPacket>(
+        &self,
+        packet: TimerPacketInner,
+        tries: usize,
+    ) -> Result<T, ApiError> {
+        send_request_with_retries(packet, tries).await
+    }
+
+    async fn reset_time(&self) {
+        reset_time().await;
+    }
 }
 
 pub async fn send_request<T>(packet: TimerPacketInner) -> Result<T, ApiError>
 where
     T: FromPacket,
 {
-    let mut tag_bytes = [0; 8];
-    _ = getrandom::getrandom(&mut tag_bytes);
-    let tag = u64::from_be_bytes(tag_bytes);
-
-    send_tagged_request(tag, packet, true).await
+    send_tagged_request(random_tag(), packet).await
 }
 
-pub async fn send_tagged_request<T>(
-    tag: u64,
+/// Sends request up to `tries` times (only timeouts are retried). Every try
+/// uses the same tag, so late response to earlier try is accepted too
+pub async fn send_request_with_retries<T>(
     packet: TimerPacketInner,
-    timeout: bool,
+    tries: usize,
 ) -> Result<T, ApiError>
 where
     T: FromPacket,
 {
-    let packet = TimerPacket {
-        tag: Some(tag),
-        data: packet,
-    };
-    send_packet(packet).await;
-
-    let packet = if timeout {
-        wait_for_tagged_response(tag)
-            .with_timeout(Duration::from_millis(5000))
-            .await
-            .map_err(|_| ApiError {
-                should_reset_time: false,
-                error: "Communication timeout!".to_string(),
-            })?
-    } else {
-        wait_for_tagged_response(tag).await
-    };
+    let tag = random_tag();
+    deliver_with_retries(tries, || send_tagged_request(tag, packet.clone())).await
+}
 
-    FromPacket::from_packet(packet)
+fn random_tag() -> u64 {
+    let mut tag_bytes = [0; 8];
+    _ = getrandom::getrandom(&mut tag_bytes);
+    u64::from_be_bytes(tag_bytes)
 }
 
-async fn wait_for_tagged_response(tag: u64) -> TimerPacket {
-    loop {
-        match TAGGED_RETURN.subscriber() {
-            Ok(mut subscriber) => loop {
-                let (packet_tag, packet) = subscriber.next_message_pure().await;
-                if packet_tag == tag {
-                    return packet;
-                }
-            },
-            Err(_) => {
-                log::error!("failed to get TAGGED_RETURN subscriber! Retry!");
-                Timer::after_millis(500).await;
+async fn send_tagged_request<T>(tag: u64, packet: TimerPacketInner) -> Result<T, ApiError>
+where
+    T: FromPacket,
+{
+    let sent = Instant::now();
+    let res = MAILBOX.request(tag, packet, Some(REQUEST_TIMEOUT_MS)).await;
+
+    let mut stats = REQUEST_STATS.lock().await;
+    match &res {
+        Err(e) if e.is_timeout() => stats.timeouts += 1,
+        _ => {
+            let latency_ms = (Instant::now() - sent).as_millis();
+            stats.latency_ms.push(latency_ms as f32);
+        }
+    }
+
+    res
+}
+
+/// Timed out requests and average latency of recent requests
+pub async fn request_stats() -> (u32, Option<u32>) {
+    let stats = REQUEST_STATS.lock().await;
+    (
+        stats.timeouts,
+        stats.latency_ms.average().map(|avg| avg as u32),
+    )
+}
+
+/// Ws connection to server, frames are converted to owned [`Frame`]
+struct ServerConnection<'a> {
+    socket: WsSocket<'a, 'a>,
+    rx_framer: WsRxFramer<'a>,
+    tx_framer: WsTxFramer<'a>,
+}
+
+impl WsConnection for ServerConnection<'_> {
+    async fn read_frame(&mut self) -> Result<Frame, ()> {
+        loop {
+            if let Some(frame) = self.rx_framer.process_data() {
+                let frame = match frame {
+                    WsFrame::Text(text) => Frame::Text(text.to_string()),
+                    WsFrame::Binary(data) => Frame::Binary(data.to_vec()),
+                    WsFrame::Close(code, reason) => Frame::Close(code, reason.to_string()),
+                    WsFrame::Ping(payload) => Frame::Ping(payload.to_vec()),
+                    WsFrame::Pong(payload) => Frame::Pong(payload.to_vec()),
+                    _ => continue,
+                };
+
+                return Ok(frame);
             }
+
+            let n = self.socket.read(self.rx_framer.mut_buf()).await?;
+            if n == 0 {
+                log::warn!("read_n: 0");
+                return Err(());
+            }
+
+            self.rx_framer.revolve_write_offset(n);
         }
     }
+
+    async fn write_frame(&mut self, frame: Frame) -> Result<(), ()> {
+        let frame = match frame {
+            Frame::Text(text) => WsFrameOwned::Text(text),
+            Frame::Binary(data) => WsFrameOwned::Binary(data),
+            Frame::Close(code, reason) => WsFrameOwned::Close(code, reason),
+            Frame::Ping(payload) => WsFrameOwned::Ping(payload),
+            Frame::Pong(payload) => WsFrameOwned::Pong(payload),
+        };
+
+        let data = self.tx_framer.frame(frame.into_ref());
+        self.socket.write_all(data).await
+    }
 }
 
 enum WsSocket<'a, 'b> {